@@ -0,0 +1,206 @@
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+
+use crate::tryout::{self, LindenmayerPayload};
+
+// Actions registered under a name, so letters can be bound to "forward"
+// instead of a function pointer. A binding by name can be shown, saved and
+// picked from a list, and follows the registry: registering another action
+// under the same name changes it for every letter of every system bound to
+// that name. The same registry holds the turtle actions of a
+// LindenmayerSystem, the semantics of a Fractal and the ones of a Dictionary,
+// which also get the word they stand for.
+
+pub type Action<P = LindenmayerPayload> = fn(payload: &mut P);
+
+// A registry shared by several systems, see LindenmayerSystem::with_action_registry.
+pub type SharedActionRegistry<A = Action> = Arc<RwLock<ActionRegistry<A>>>;
+
+//***************************************************************************
+//
+// ActionError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionError {
+    UnknownAction(String),
+}
+
+impl Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionError::UnknownAction(name) => write!(f, "no action registered as '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+//***************************************************************************
+//
+// ActionRegistry
+//
+//***************************************************************************
+
+// Names are kept in the order they were first registered, to list them.
+#[derive(Clone)]
+pub struct ActionRegistry<A = Action> {
+    actions: Vec<(String, A)>,
+    // Counts the changes, so systems can tell that their bindings are outdated.
+    generation: u64,
+}
+
+impl<A: Copy> ActionRegistry<A> {
+    pub fn new() -> Self {
+        ActionRegistry {
+            actions: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    pub fn with_action(mut self, name: &str, action: A) -> Self {
+        self.register(name, action);
+        self
+    }
+
+    // Registering a name again replaces the action it stood for.
+    pub fn register(&mut self, name: &str, action: A) {
+        match self.actions.iter_mut().find(|(known, _)| known == name) {
+            Some((_, known)) => *known = action,
+            None => self.actions.push((name.to_owned(), action)),
+        }
+        self.generation += 1;
+    }
+
+    pub fn action(&self, name: &str) -> Result<A, ActionError> {
+        self.actions.iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, action)| *action)
+            .ok_or_else(|| ActionError::UnknownAction(name.to_owned()))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.actions.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn into_shared(self) -> SharedActionRegistry<A> {
+        Arc::new(RwLock::new(self))
+    }
+}
+
+impl ActionRegistry<Action> {
+    // The actions of the turtle, under the names grammar files use.
+    pub fn turtle() -> Self {
+        Self::new()
+            .with_action("forward", tryout::forward)
+            .with_action("move", tryout::move_forward)
+            .with_action("turn_left", tryout::turn_left)
+            .with_action("turn_right", tryout::turn_right)
+            .with_action("turn_around", tryout::turn_around)
+            .with_action("reverse_turning_direction", tryout::reverse_turning_direction)
+            .with_action("push", tryout::push)
+            .with_action("pop", tryout::pop)
+    }
+}
+
+impl<A: Copy> Default for ActionRegistry<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod action_registry {
+        use crate::actions::{Action, ActionError, ActionRegistry};
+
+        #[test]
+        fn turtle() {
+            let registry = ActionRegistry::turtle();
+            assert_eq!(
+                registry.names().collect::<Vec<_>>(),
+                vec!["forward", "move", "turn_left", "turn_right", "turn_around", "reverse_turning_direction", "push", "pop"],
+            );
+            assert_eq!(registry.action("jump").unwrap_err(), ActionError::UnknownAction("jump".to_owned()));
+        }
+
+        #[test]
+        fn register_again() {
+            let mut registry = ActionRegistry::<Action<String>>::new()
+                .with_action("shout", |payload| payload.push('!'))
+                .with_action("ask", |payload| payload.push('?'));
+            let generation = registry.generation();
+            registry.register("shout", |payload| payload.push_str("!!"));
+            assert_eq!(registry.len(), 2);
+            assert!(registry.generation() > generation);
+
+            let mut payload = String::new();
+            registry.action("shout").unwrap()(&mut payload);
+            registry.action("ask").unwrap()(&mut payload);
+            assert_eq!(payload, "!!?");
+        }
+    }
+
+    mod lindenmayer_system {
+        use crate::actions::{ActionError, ActionRegistry};
+        use crate::tryout::{self, LindenmayerSystem};
+
+        fn line() -> LindenmayerSystem<char> {
+            LindenmayerSystem::new(&['a'], 90.0f32.to_radians(), &[('a', Some(vec!['a', 'b', 'a']))], &[])
+        }
+
+        #[test]
+        fn bind_by_name() {
+            // Every system starts out with the turtle actions.
+            let mut system = line();
+            system.bind_action('a', "forward").unwrap();
+            system.bind_action('b', "turn_left").unwrap();
+            assert_eq!(system.bind_action('c', "jump"), Err(ActionError::UnknownAction("jump".to_owned())));
+            assert_eq!(system.action_name('a'), Some("forward"));
+
+            system.update_vertex_stack(1);
+            assert_eq!(system.get_vertex_stack_at(0).unwrap().len(), 2);
+            assert_eq!(system.get_vertex_stack_at(1).unwrap().len(), 3);
+
+            system.change_action('a', Some(tryout::forward));
+            assert_eq!(system.action_name('a'), None);
+        }
+
+        #[test]
+        fn follow_the_shared_registry() {
+            let registry = ActionRegistry::turtle().into_shared();
+            let mut first = line();
+            let mut second = line();
+            for system in [&mut first, &mut second] {
+                system.with_action_registry(registry.clone());
+                system.bind_action('a', "forward").unwrap();
+                system.update_vertex_stack(2);
+                assert_eq!(system.get_vertex_stack_at(2).unwrap().len(), 5);
+            }
+
+            // Turning instead of drawing leaves only the starting point.
+            registry.write().unwrap().register("forward", tryout::turn_left);
+            for system in [&mut first, &mut second] {
+                system.update_vertex_stack(2);
+                assert_eq!(system.get_vertex_stack_at(2).unwrap().len(), 1);
+                assert!(!system.refresh_actions());
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+// Interned letters. Every letter of a word is replaced by a dense id, so a
+// word of chars takes one byte per letter instead of four, and the
+// successor of a letter is found by indexing a table with its id instead of
+// hashing it.
+
+//***************************************************************************
+//
+// SymbolId
+//
+//***************************************************************************
+
+// Integer types usable as ids, the alphabet can hold CAPACITY letters.
+pub trait SymbolId: Copy + PartialEq + Eq + Hash + Debug {
+    const CAPACITY: usize;
+    fn from_index(index: usize) -> Self;
+    fn index(self) -> usize;
+}
+
+impl SymbolId for u8 {
+    const CAPACITY: usize = u8::MAX as usize + 1;
+    fn from_index(index: usize) -> Self {
+        index as u8
+    }
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl SymbolId for u16 {
+    const CAPACITY: usize = u16::MAX as usize + 1;
+    fn from_index(index: usize) -> Self {
+        index as u16
+    }
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+//***************************************************************************
+//
+// AlphabetError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlphabetError {
+    // More distinct letters than the id type can count.
+    Full { capacity: usize },
+    UnknownId(usize),
+    // Only predecessors of a single letter can be looked up in a table.
+    UnsupportedPredecessor { length: usize },
+}
+
+impl Display for AlphabetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlphabetError::Full { capacity } => write!(f, "alphabet is full, it can hold {} letters", capacity),
+            AlphabetError::UnknownId(id) => write!(f, "no letter with id {}", id),
+            AlphabetError::UnsupportedPredecessor { length } => {
+                write!(f, "predecessor of {} letters can not be interned, only single letters can", length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AlphabetError {}
+
+//***************************************************************************
+//
+// Alphabet
+//
+//***************************************************************************
+
+// Ids are handed out in the order the letters are first seen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alphabet<T, I = u8>
+where
+    T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
+{
+    letters: Vec<T>,
+    ids: HashMap<T, I>,
+}
+
+impl<T, I> Alphabet<T, I>
+where
+    T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
+{
+    pub fn new() -> Self {
+        Alphabet {
+            letters: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.letters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.letters.is_empty()
+    }
+
+    pub fn intern(&mut self, letter: &T) -> Result<I, AlphabetError> {
+        if let Some(&id) = self.ids.get(letter) {
+            return Ok(id);
+        }
+        if self.letters.len() == I::CAPACITY {
+            return Err(AlphabetError::Full { capacity: I::CAPACITY });
+        }
+        let id = I::from_index(self.letters.len());
+        self.letters.push(letter.clone());
+        self.ids.insert(letter.clone(), id);
+        Ok(id)
+    }
+
+    pub fn id(&self, letter: &T) -> Option<I> {
+        self.ids.get(letter).copied()
+    }
+
+    pub fn letter(&self, id: I) -> Option<&T> {
+        self.letters.get(id.index())
+    }
+
+    pub fn letters(&self) -> &[T] {
+        &self.letters
+    }
+
+    // Interns every letter of the word that is not known yet.
+    pub fn encode(&mut self, word: &[T]) -> Result<Vec<I>, AlphabetError> {
+        word.iter().map(|letter| self.intern(letter)).collect()
+    }
+
+    pub fn decode(&self, ids: &[I]) -> Result<Vec<T>, AlphabetError> {
+        ids.iter()
+            .map(|&id| self.letter(id).cloned().ok_or(AlphabetError::UnknownId(id.index())))
+            .collect()
+    }
+}
+
+impl<T, I> Default for Alphabet<T, I>
+where
+    T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//***************************************************************************
+//
+// RuleTable
+//
+//***************************************************************************
+
+// The successor of every id, None for letters that stay the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleTable<I: SymbolId> {
+    successors: Vec<Option<Vec<I>>>,
+}
+
+impl<I: SymbolId> RuleTable<I> {
+    pub fn new() -> Self {
+        RuleTable {
+            successors: Vec::new(),
+        }
+    }
+
+    // Interns the letters of the rules into the alphabet.
+    pub fn from_replacements<T>(alphabet: &mut Alphabet<T, I>, replacements: &HashMap<Vec<T>, Vec<T>>) -> Result<Self, AlphabetError>
+    where
+        T: Clone + PartialEq + Eq + Hash,
+    {
+        let mut table = RuleTable::new();
+        for (predecessor, successor) in replacements {
+            let letter = match predecessor.as_slice() {
+                [letter] => letter,
+                _ => return Err(AlphabetError::UnsupportedPredecessor { length: predecessor.len() }),
+            };
+            let id = alphabet.intern(letter)?;
+            let successor = alphabet.encode(successor)?;
+            table.set(id, Some(successor));
+        }
+        Ok(table)
+    }
+
+    pub fn set(&mut self, id: I, successor: Option<Vec<I>>) {
+        if self.successors.len() <= id.index() {
+            self.successors.resize(id.index() + 1, None);
+        }
+        self.successors[id.index()] = successor;
+    }
+
+    pub fn successor(&self, id: I) -> Option<&[I]> {
+        self.successors.get(id.index())?.as_deref()
+    }
+
+    pub fn rewrite(&self, word: &[I]) -> Vec<I> {
+        let mut result = Vec::with_capacity(word.len());
+        for &id in word {
+            match self.successor(id) {
+                Some(successor) => result.extend_from_slice(successor),
+                None => result.push(id),
+            }
+        }
+        result
+    }
+}
+
+impl<I: SymbolId> Default for RuleTable<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//***************************************************************************
+//
+// CompactWord
+//
+//***************************************************************************
+
+// A word stored as ids together with the alphabet needed to read it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactWord<T, I = u8>
+where
+    T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
+{
+    alphabet: Alphabet<T, I>,
+    ids: Vec<I>,
+}
+
+impl<T, I> CompactWord<T, I>
+where
+    T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
+{
+    pub fn new(alphabet: Alphabet<T, I>, ids: Vec<I>) -> Self {
+        CompactWord { alphabet, ids }
+    }
+
+    pub fn alphabet(&self) -> &Alphabet<T, I> {
+        &self.alphabet
+    }
+
+    pub fn ids(&self) -> &[I] {
+        &self.ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // Ids are only ever handed out by the alphabet, so every one of them has a letter.
+    pub fn letters(&self) -> impl Iterator<Item = &T> + '_ {
+        self.ids.iter().filter_map(|&id| self.alphabet.letter(id))
+    }
+
+    pub fn decode(&self) -> Vec<T> {
+        self.letters().cloned().collect()
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod alphabet {
+        use crate::alphabet::{Alphabet, AlphabetError};
+
+        #[test]
+        fn encode_and_decode() {
+            let mut alphabet: Alphabet<char> = Alphabet::new();
+            let word: Vec<char> = "F+F--F+F".chars().collect();
+            let ids = alphabet.encode(&word).unwrap();
+            assert_eq!(ids, vec![0, 1, 0, 2, 2, 0, 1, 0]);
+            assert_eq!(alphabet.len(), 3);
+            assert_eq!(alphabet.decode(&ids).unwrap(), word);
+            assert_eq!(alphabet.decode(&[3]), Err(AlphabetError::UnknownId(3)));
+        }
+
+        #[test]
+        fn full() {
+            let mut alphabet: Alphabet<u32> = Alphabet::new();
+            for letter in 0..256 {
+                alphabet.intern(&letter).unwrap();
+            }
+            assert_eq!(alphabet.intern(&7), Ok(7));
+            assert_eq!(alphabet.intern(&256), Err(AlphabetError::Full { capacity: 256 }));
+
+            let mut alphabet: Alphabet<u32, u16> = Alphabet::new();
+            for letter in 0..=256 {
+                alphabet.intern(&letter).unwrap();
+            }
+            assert_eq!(alphabet.id(&256), Some(256));
+        }
+    }
+
+    mod rule_table {
+        use std::collections::HashMap;
+
+        use crate::alphabet::{Alphabet, AlphabetError, RuleTable};
+        use crate::word_slice::Word;
+
+        #[test]
+        fn same_as_replacements() {
+            let mut replacements = HashMap::new();
+            replacements.insert(vec!['X'], "X+YF+".chars().collect::<Vec<char>>());
+            replacements.insert(vec!['Y'], "-FX-Y".chars().collect::<Vec<char>>());
+
+            let mut alphabet: Alphabet<char> = Alphabet::new();
+            let table = RuleTable::from_replacements(&mut alphabet, &replacements).unwrap();
+            let mut word: Vec<char> = vec!['F', 'X'];
+            let mut ids = alphabet.encode(&word).unwrap();
+            for _ in 0..6 {
+                word = word.apply_relacements(&replacements);
+                ids = table.rewrite(&ids);
+                assert_eq!(alphabet.decode(&ids).unwrap(), word);
+            }
+        }
+
+        #[test]
+        fn reject_longer_predecessors() {
+            let mut replacements = HashMap::new();
+            replacements.insert(vec!['a', 'b'], vec!['c']);
+            let result = RuleTable::<u8>::from_replacements(&mut Alphabet::new(), &replacements);
+            assert_eq!(result, Err(AlphabetError::UnsupportedPredecessor { length: 2 }));
+        }
+    }
+}
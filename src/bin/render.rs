@@ -0,0 +1,8 @@
+use fractal_generator_rust_allegro::cli;
+
+// Renders a single fractal into a file, see cli::USAGE. Only the library
+// is linked, so this works on machines without allegro.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(cli::run(&args));
+}
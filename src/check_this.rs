@@ -1,6 +1,8 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy, PartialEq)]
+use crate::tryout::LindenmayerPayload;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MyFirstLetter {
     Forward {length: f32},
     TurnLeft {angle: f32},
@@ -38,3 +40,49 @@ pub trait Letter: Copy + PartialEq {}
 
 impl Letter for MyFirstLetter {}
 impl Letter for MySecondLetter {}
+
+// A letter that carries numeric parameters, e.g. F(1.5).
+// The symbol identifies the kind of letter, the parameters its values,
+// which allows parametric production rules to bind and rebuild letters.
+pub trait Parametric: Sized {
+    fn symbol(&self) -> char;
+    fn parameters(&self) -> Vec<f32>;
+    fn from_parameters(symbol: char, parameters: &[f32]) -> Option<Self>;
+    fn interpret(&self, payload: &mut LindenmayerPayload);
+}
+
+// Turns are given in degrees.
+impl Parametric for MyFirstLetter {
+    fn symbol(&self) -> char {
+        match self {
+            Self::Forward {..} => 'F',
+            Self::TurnLeft {..} => '+',
+            Self::TurnRight {..} => '-',
+        }
+    }
+    fn parameters(&self) -> Vec<f32> {
+        match self {
+            Self::Forward {length} => vec![*length],
+            Self::TurnLeft {angle} => vec![*angle],
+            Self::TurnRight {angle} => vec![*angle],
+        }
+    }
+    fn from_parameters(symbol: char, parameters: &[f32]) -> Option<Self> {
+        match (symbol, parameters) {
+            ('F', &[length]) => Some(Self::Forward {length}),
+            ('+' | 'L', &[angle]) => Some(Self::TurnLeft {angle}),
+            ('-' | 'R', &[angle]) => Some(Self::TurnRight {angle}),
+            _ => None,
+        }
+    }
+    fn interpret(&self, payload: &mut LindenmayerPayload) {
+        match self {
+            Self::Forward {length} => {
+                payload.update_current_position_by(*length);
+                payload.push_current_position();
+            },
+            Self::TurnLeft {angle} => payload.increase_current_angle(angle.to_radians()),
+            Self::TurnRight {angle} => payload.decrease_current_angle(angle.to_radians()),
+        }
+    }
+}
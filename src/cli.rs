@@ -0,0 +1,400 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::coordinates::MathPosition;
+use crate::fractint::FractintLibrary;
+use crate::grammar_file::GrammarFile;
+use crate::raster::RasterRenderer;
+use crate::svg::SvgExporter;
+use crate::tryout::{Letter, LindenmayerSystem};
+
+// Batch rendering without opening a window. The render binary renders a
+// single fractal into a file and exits:
+//
+//     render koch --depth 5 --format png --size 800x600 --output koch.png
+//     render plants.l --name Bush --output bush.svg
+
+pub const USAGE: &str = "\
+usage: render <GRAMMAR> [OPTIONS]
+
+GRAMMAR is one of the built-in fractals koch, levy, dragon_curve, first_plant,
+a grammar file or a Fractint .l library.
+
+options:
+    -d, --depth <N>          iteration depth (default 4)
+    -f, --format <svg|png>   output format (default: from the output extension, else svg)
+    -s, --size <WxH>         image size in pixels (default 1900x1080)
+    -o, --output <PATH>      output file (default <GRAMMAR>.<FORMAT>)
+    -n, --name <NAME>        fractal to pick from a Fractint library (default: the first)
+    -h, --help               print this message
+";
+
+const DEFAULT_DEPTH: usize = 4;
+
+//***************************************************************************
+//
+// CliError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    MissingGrammar,
+    MissingValue(String),
+    UnknownOption(String),
+    InvalidValue { option: String, value: String },
+    UnexpectedArgument(String),
+    UnknownFractal(String),
+    Load { path: PathBuf, message: String },
+    Write { path: PathBuf, message: String },
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingGrammar => write!(f, "no grammar given"),
+            Self::MissingValue(option) => write!(f, "option '{}' needs a value", option),
+            Self::UnknownOption(option) => write!(f, "unknown option '{}'", option),
+            Self::InvalidValue { option, value } => write!(f, "'{}' is not a valid value for '{}'", value, option),
+            Self::UnexpectedArgument(argument) => write!(f, "unexpected argument '{}'", argument),
+            Self::UnknownFractal(name) => write!(f, "'{}' is neither a built-in fractal nor a file", name),
+            Self::Load { path, message } => write!(f, "could not load {}: {}", path.display(), message),
+            Self::Write { path, message } => write!(f, "could not write {}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl CliError {
+    // Usage errors are reported together with the usage.
+    pub fn is_usage_error(&self) -> bool {
+        !matches!(self, Self::UnknownFractal(_) | Self::Load { .. } | Self::Write { .. })
+    }
+}
+
+//***************************************************************************
+//
+// RenderOptions
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Svg,
+    Png,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "svg" => Some(Self::Svg),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Svg => "svg",
+            Self::Png => "png",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    grammar: String,
+    name: Option<String>,
+    depth: usize,
+    format: Format,
+    size: (u32, u32),
+    output: PathBuf,
+}
+
+impl RenderOptions {
+    // Returns None if only the usage was asked for.
+    pub fn parse(args: &[String]) -> Result<Option<Self>, CliError> {
+        let mut grammar = None;
+        let mut name = None;
+        let mut depth = DEFAULT_DEPTH;
+        let mut format = None;
+        let mut size = (crate::DISPLAY_WIDTH as u32, crate::DISPLAY_HEIGHT as u32);
+        let mut output: Option<PathBuf> = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |option: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| CliError::MissingValue(option.to_owned()))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-d" | "--depth" => {
+                    let depth_value = value(arg)?;
+                    depth = depth_value.parse()
+                        .map_err(|_| CliError::InvalidValue { option: arg.clone(), value: depth_value })?;
+                }
+                "-f" | "--format" => {
+                    let format_value = value(arg)?;
+                    format = Some(Format::from_name(&format_value)
+                        .ok_or(CliError::InvalidValue { option: arg.clone(), value: format_value })?);
+                }
+                "-s" | "--size" => {
+                    let size_value = value(arg)?;
+                    size = parse_size(&size_value)
+                        .ok_or(CliError::InvalidValue { option: arg.clone(), value: size_value })?;
+                }
+                "-o" | "--output" => output = Some(PathBuf::from(value(arg)?)),
+                "-n" | "--name" => name = Some(value(arg)?),
+                option if option.starts_with('-') && option.len() > 1 => {
+                    return Err(CliError::UnknownOption(option.to_owned()));
+                }
+                positional => {
+                    if grammar.is_some() {
+                        return Err(CliError::UnexpectedArgument(positional.to_owned()));
+                    }
+                    grammar = Some(positional.to_owned());
+                }
+            }
+        }
+
+        let grammar = grammar.ok_or(CliError::MissingGrammar)?;
+        let format = format
+            .or_else(|| {
+                output.as_ref()
+                    .and_then(|output| output.extension())
+                    .and_then(|extension| Format::from_name(&extension.to_string_lossy()))
+            })
+            .unwrap_or(Format::Svg);
+        let output = output.unwrap_or_else(|| {
+            let stem = Path::new(&grammar).file_stem().map(|stem| stem.to_os_string()).unwrap_or_default();
+            PathBuf::from(stem).with_extension(format.extension())
+        });
+
+        Ok(Some(RenderOptions { grammar, name, depth, format, size, output }))
+    }
+
+    pub fn grammar(&self) -> &str {
+        &self.grammar
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    // Computes the vertices of the requested fractal at the requested depth.
+    pub fn vertices(&self) -> Result<Vec<Option<MathPosition>>, CliError> {
+        Ok(self.load()?.stream(self.depth).collect())
+    }
+
+    pub fn render(&self) -> Result<(), CliError> {
+        let fractal = self.load()?;
+        let (width, height) = self.size;
+        let result = match self.format {
+            Format::Svg => SvgExporter::new()
+                .with_size(width, height)
+                .with_background(0.1, 0.1, 0.1)
+                .write_streamed(&self.output, || fractal.stream(self.depth))
+                .map_err(|error| error.to_string()),
+            Format::Png => RasterRenderer::new(width, height)
+                .render_streamed(|| fractal.stream(self.depth))
+                .write_png(&self.output)
+                .map_err(|error| error.to_string()),
+        };
+        result.map_err(|message| CliError::Write { path: self.output.clone(), message })
+    }
+
+    // The letter types of the built-in fractals, grammar files and Fractint
+    // libraries differ, so the fractal is handed out as a trait object.
+    fn load(&self) -> Result<Box<dyn Streamable>, CliError> {
+        match self.grammar.as_str() {
+            "koch" => return Ok(Box::new(LindenmayerSystem::koch())),
+            "levy" => return Ok(Box::new(LindenmayerSystem::levy())),
+            "dragon_curve" => return Ok(Box::new(LindenmayerSystem::dragon_curve())),
+            "first_plant" => return Ok(Box::new(LindenmayerSystem::first_plant())),
+            _ => (),
+        }
+
+        let path = PathBuf::from(&self.grammar);
+        if !path.is_file() {
+            return Err(CliError::UnknownFractal(self.grammar.clone()));
+        }
+        let load_error = |message: String| CliError::Load { path: path.clone(), message };
+
+        let is_fractint = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("l"));
+        if is_fractint {
+            let library = FractintLibrary::read(&path).map_err(|error| load_error(error.to_string()))?;
+            let system = match &self.name {
+                Some(name) => library.get(name)
+                    .ok_or_else(|| load_error(format!("no fractal named '{}', found {}", name, library.names().join(", "))))?,
+                None => library.systems().first()
+                    .ok_or_else(|| load_error(String::from("the library is empty")))?,
+            };
+            Ok(Box::new(system.to_lindenmayer_system()))
+        } else {
+            let grammar_file = GrammarFile::read(&path).map_err(|error| load_error(error.to_string()))?;
+            Ok(Box::new(grammar_file.to_lindenmayer_system()))
+        }
+    }
+}
+
+// The vertices are streamed straight from the derivation, so deep
+// iterations never have to fit into memory as a whole word.
+trait Streamable {
+    fn stream(&self, depth: usize) -> Box<dyn Iterator<Item = Option<MathPosition>> + '_>;
+}
+
+impl<L: Letter> Streamable for LindenmayerSystem<L> {
+    fn stream(&self, depth: usize) -> Box<dyn Iterator<Item = Option<MathPosition>> + '_> {
+        Box::new(self.derive_vertices(depth))
+    }
+}
+
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once(['x', 'X'])?;
+    let width = width.parse().ok().filter(|width| *width > 0)?;
+    let height = height.parse().ok().filter(|height| *height > 0)?;
+    Some((width, height))
+}
+
+// Entry point of the command line mode, returns the exit code of the process.
+pub fn run(args: &[String]) -> i32 {
+    let options = match RenderOptions::parse(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return 0;
+        }
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            return 2;
+        }
+    };
+    match options.render() {
+        Ok(()) => {
+            println!("wrote {}", options.output().display());
+            0
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            if error.is_usage_error() { 2 } else { 1 }
+        }
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    mod parse {
+        use std::path::Path;
+        use crate::cli::{CliError, Format, RenderOptions};
+        use super::args;
+
+        #[test]
+        fn defaults() {
+            let options = RenderOptions::parse(&args(&["koch"])).unwrap().unwrap();
+            assert_eq!(options.grammar(), "koch");
+            assert_eq!(options.depth(), 4);
+            assert_eq!(options.format(), Format::Svg);
+            assert_eq!(options.size(), (1900, 1080));
+            assert_eq!(options.output(), Path::new("koch.svg"));
+        }
+
+        #[test]
+        fn every_option() {
+            let options = RenderOptions::parse(&args(&["-d", "6", "dragon_curve", "--size", "800x600", "-o", "out/dragon.png"])).unwrap().unwrap();
+            assert_eq!(options.depth(), 6);
+            assert_eq!(options.size(), (800, 600));
+            // The format follows the extension of the output.
+            assert_eq!(options.format(), Format::Png);
+
+            let options = RenderOptions::parse(&args(&["plants.l", "--format", "PNG", "--name", "Bush"])).unwrap().unwrap();
+            assert_eq!(options.output(), Path::new("plants.png"));
+        }
+
+        #[test]
+        fn help() {
+            assert_eq!(RenderOptions::parse(&args(&["koch", "--help"])), Ok(None));
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(RenderOptions::parse(&args(&[])), Err(CliError::MissingGrammar));
+            assert_eq!(RenderOptions::parse(&args(&["koch", "--depth"])), Err(CliError::MissingValue(String::from("--depth"))));
+            assert_eq!(RenderOptions::parse(&args(&["koch", "--color", "red"])), Err(CliError::UnknownOption(String::from("--color"))));
+            assert_eq!(RenderOptions::parse(&args(&["koch", "levy"])), Err(CliError::UnexpectedArgument(String::from("levy"))));
+            assert_eq!(
+                RenderOptions::parse(&args(&["koch", "-s", "800"])),
+                Err(CliError::InvalidValue { option: String::from("-s"), value: String::from("800") })
+            );
+            assert_eq!(
+                RenderOptions::parse(&args(&["koch", "-f", "gif"])),
+                Err(CliError::InvalidValue { option: String::from("-f"), value: String::from("gif") })
+            );
+        }
+    }
+
+    mod render {
+        use crate::cli::{CliError, RenderOptions};
+        use super::args;
+
+        #[test]
+        fn built_in_to_every_format() {
+            let directory = std::env::temp_dir().join(format!("fractal_cli_{}", std::process::id()));
+            std::fs::create_dir_all(&directory).unwrap();
+
+            let svg = directory.join("levy.svg");
+            let options = RenderOptions::parse(&args(&["levy", "-d", "3", "-o", svg.to_str().unwrap()])).unwrap().unwrap();
+            options.render().unwrap();
+            assert!(std::fs::read_to_string(&svg).unwrap().contains("<path"));
+
+            let png = directory.join("levy.png");
+            let options = RenderOptions::parse(&args(&["levy", "-d", "3", "-s", "64x32", "-o", png.to_str().unwrap()])).unwrap().unwrap();
+            options.render().unwrap();
+            assert!(std::fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+
+            std::fs::remove_dir_all(&directory).unwrap();
+        }
+
+        #[test]
+        fn grammar_from_files() {
+            let directory = std::env::temp_dir().join(format!("fractal_cli_files_{}", std::process::id()));
+            std::fs::create_dir_all(&directory).unwrap();
+
+            let library = directory.join("classics.l");
+            std::fs::write(&library, "Koch1 {\n Angle 6\n Axiom F--F--F\n F=F+F--F+F\n}\n").unwrap();
+            let options = RenderOptions::parse(&args(&[library.to_str().unwrap(), "-d", "1", "-n", "koch1"])).unwrap().unwrap();
+            assert_eq!(options.vertices().unwrap().len(), 3 * 4 + 1);
+
+            let grammar = directory.join("line.txt");
+            std::fs::write(&grammar, "axiom: F\nangle: 90\nrules:\n  F -> FF\nsemantics:\n  F = forward\n").unwrap();
+            let options = RenderOptions::parse(&args(&[grammar.to_str().unwrap(), "-d", "2"])).unwrap().unwrap();
+            assert_eq!(options.vertices().unwrap().len(), 4 + 1);
+
+            std::fs::remove_dir_all(&directory).unwrap();
+
+            let options = RenderOptions::parse(&args(&["no_such_fractal"])).unwrap().unwrap();
+            assert_eq!(options.vertices(), Err(CliError::UnknownFractal(String::from("no_such_fractal"))));
+        }
+    }
+}
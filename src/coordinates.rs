@@ -1,6 +1,4 @@
 use std::ops::{Add, Sub, AddAssign, SubAssign};
-use crate::DISPLAY_HEIGHT;
-use crate::DISPLAY_WIDTH;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct MathPosition {
@@ -84,24 +82,6 @@ pub struct ScreenPosition {
     y: f32,
 }
 
-impl From<MathPosition> for ScreenPosition {
-    fn from(math_pos: MathPosition) -> Self {
-        ScreenPosition {
-            x: math_pos.x + (DISPLAY_WIDTH as f32) / 2.0,
-            y: -math_pos.y + (DISPLAY_HEIGHT as f32) / 2.0,
-        }
-    }
-}
-
-impl From<&MathPosition> for ScreenPosition {
-    fn from(math_pos: &MathPosition) -> Self {
-        ScreenPosition {
-            x: math_pos.x + (DISPLAY_WIDTH as f32) / 2.0,
-            y: -math_pos.y + (DISPLAY_HEIGHT as f32) / 2.0,
-        }
-    }
-}
-
 impl From<(f32, f32)> for ScreenPosition {
     fn from(tuple: (f32, f32)) -> Self {
         Self {
@@ -117,26 +97,209 @@ impl Into<(f32, f32)> for ScreenPosition {
     }
 }
 
-impl From<ScreenPosition> for MathPosition {
-    fn from(screen_pos: ScreenPosition) -> Self {
-        MathPosition {
-            x: screen_pos.x - (DISPLAY_WIDTH as f32) / 2.0,
-            y: -screen_pos.y + (DISPLAY_HEIGHT as f32) / 2.0,
-        }
+impl Into<(f32, f32)> for MathPosition {
+    fn into(self) -> (f32, f32) {
+        (self.x, self.y)
     }
 }
 
-impl From<&ScreenPosition> for MathPosition {
-    fn from(screen_pos: &ScreenPosition) -> Self {
+//***************************************************************************
+//
+// Viewport
+//
+//***************************************************************************
+
+const MIN_ZOOM: f32 = 1e-4;
+const MAX_ZOOM: f32 = 1e6;
+
+// The camera looking at the math plane. The offset is the math position
+// shown in the center of the screen, the zoom is the number of pixels per
+// unit and the rotation, in radians, turns the plane counterclockwise.
+// The y axis points up in math space and down in screen space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    width: f32,
+    height: f32,
+    offset: MathPosition,
+    zoom: f32,
+    rotation: f32,
+}
+
+impl Viewport {
+    pub fn new(width: i32, height: i32) -> Self {
+        Viewport {
+            width: width as f32,
+            height: height as f32,
+            offset: MathPosition::new(0.0, 0.0),
+            zoom: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: MathPosition) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn resolution(&self) -> (i32, i32) {
+        (self.width as i32, self.height as i32)
+    }
+
+    pub fn offset(&self) -> MathPosition {
+        self.offset
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    pub fn set_resolution(&mut self, width: i32, height: i32) {
+        self.width = width as f32;
+        self.height = height as f32;
+    }
+
+    pub fn set_offset(&mut self, offset: MathPosition) {
+        self.offset = offset;
+    }
+
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.rotation = rotation;
+    }
+
+    // Moves the plane along with the mouse, dx and dy are given in pixels.
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let center = ScreenPosition {
+            x: self.width / 2.0 - dx,
+            y: self.height / 2.0 - dy,
+        };
+        self.offset = self.screen_to_math(&center);
+    }
+
+    // Zooms by the given factor while the math position under the
+    // screen position, usually the mouse cursor, stays where it is.
+    pub fn zoom_at(&mut self, screen_pos: &ScreenPosition, factor: f32) {
+        let anchor = self.screen_to_math(screen_pos);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let moved_anchor = self.screen_to_math(screen_pos);
+        self.offset += anchor - moved_anchor;
+    }
+
+    // Goes back to the initial camera, keeping the resolution.
+    pub fn reset(&mut self) {
+        self.offset = MathPosition::new(0.0, 0.0);
+        self.zoom = 1.0;
+        self.rotation = 0.0;
+    }
+
+    pub fn math_to_screen(&self, math_pos: &MathPosition) -> ScreenPosition {
+        let (sin, cos) = self.rotation.sin_cos();
+        let relative = *math_pos - self.offset;
+        let x = (relative.x * cos - relative.y * sin) * self.zoom;
+        let y = (relative.x * sin + relative.y * cos) * self.zoom;
+        ScreenPosition {
+            x: x + self.width / 2.0,
+            y: -y + self.height / 2.0,
+        }
+    }
+
+    pub fn screen_to_math(&self, screen_pos: &ScreenPosition) -> MathPosition {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = (screen_pos.x - self.width / 2.0) / self.zoom;
+        let y = (-screen_pos.y + self.height / 2.0) / self.zoom;
         MathPosition {
-            x: screen_pos.x - (DISPLAY_WIDTH as f32) / 2.0,
-            y: -screen_pos.y + (DISPLAY_HEIGHT as f32) / 2.0,
+            x: x * cos + y * sin + self.offset.x,
+            y: -x * sin + y * cos + self.offset.y,
         }
     }
 }
 
-impl Into<(f32, f32)> for MathPosition {
-    fn into(self) -> (f32, f32) {
-        (self.x, self.y)
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod viewport {
+        use crate::coordinates::{MathPosition, ScreenPosition, Viewport};
+
+        fn assert_close(left: (f32, f32), right: (f32, f32)) {
+            assert!((left.0 - right.0).abs() < 1e-3 && (left.1 - right.1).abs() < 1e-3, "{:?} != {:?}", left, right);
+        }
+
+        #[test]
+        fn origin_is_the_center() {
+            let viewport = Viewport::new(1900, 1080);
+            assert_close(viewport.math_to_screen(&MathPosition::new(0.0, 0.0)).into(), (950.0, 540.0));
+            assert_close(viewport.math_to_screen(&MathPosition::new(10.0, 10.0)).into(), (960.0, 530.0));
+        }
+
+        #[test]
+        fn offset_zoom_and_rotation() {
+            let viewport = Viewport::new(200, 100)
+                .with_offset(MathPosition::new(1.0, 1.0))
+                .with_zoom(10.0)
+                .with_rotation(90.0f32.to_radians());
+            assert_close(viewport.math_to_screen(&MathPosition::new(1.0, 1.0)).into(), (100.0, 50.0));
+            // One unit to the right ends up ten pixels above the center.
+            assert_close(viewport.math_to_screen(&MathPosition::new(2.0, 1.0)).into(), (100.0, 40.0));
+        }
+
+        #[test]
+        fn round_trip() {
+            let viewport = Viewport::new(640, 480)
+                .with_offset(MathPosition::new(-3.0, 7.5))
+                .with_zoom(2.5)
+                .with_rotation(0.3);
+            let screen_pos = ScreenPosition::from((12.0, 400.0));
+            let math_pos = viewport.screen_to_math(&screen_pos);
+            assert_close(viewport.math_to_screen(&math_pos).into(), (12.0, 400.0));
+        }
+
+        #[test]
+        fn pan() {
+            let mut viewport = Viewport::new(200, 100).with_zoom(2.0);
+            viewport.pan(20.0, -10.0);
+            // Dragging moves the plane along with the mouse.
+            assert_close(viewport.math_to_screen(&MathPosition::new(0.0, 0.0)).into(), (120.0, 40.0));
+        }
+
+        #[test]
+        fn zoom_at_keeps_the_cursor_fixed() {
+            let mut viewport = Viewport::new(200, 100).with_rotation(0.5);
+            let cursor = ScreenPosition::from((30.0, 70.0));
+            let under_cursor = viewport.screen_to_math(&cursor);
+            viewport.zoom_at(&cursor, 4.0);
+            assert_eq!(viewport.zoom(), 4.0);
+            assert_close(viewport.math_to_screen(&under_cursor).into(), (30.0, 70.0));
+
+            viewport.reset();
+            assert_eq!(viewport, Viewport::new(200, 100));
+        }
+
+        #[test]
+        fn zoom_is_clamped() {
+            // A zoom of zero would collapse the plane into a single point.
+            let mut viewport = Viewport::new(200, 100).with_zoom(0.0);
+            assert!(viewport.zoom() > 0.0);
+            viewport.set_zoom(f32::INFINITY);
+            assert!(viewport.zoom().is_finite());
+        }
     }
 }
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::coordinates::MathPosition;
+use crate::tryout::{Letter, LindenmayerPayload};
+
+// Streaming derivation of an L-system. Instead of rewriting the whole word
+// once per iteration, the letters of depth n are produced depth first:
+// every letter that still has iterations left is replaced by its successor,
+// which is walked before the rest of its parent. Only one position per
+// level is kept, so the memory used grows with the depth, not with the
+// length of the word.
+
+//***************************************************************************
+//
+// Derivation
+//
+//***************************************************************************
+
+pub struct Derivation<'a, L: Letter> {
+    rules: &'a HashMap<L, Option<Vec<L>>>,
+    // The word walked on every level, the position in it and the number of
+    // iterations its letters still have to go through.
+    stack: Vec<(&'a [L], usize, usize)>,
+}
+
+impl<'a, L: Letter> Derivation<'a, L> {
+    pub fn new(axiom: &'a [L], rules: &'a HashMap<L, Option<Vec<L>>>, depth: usize) -> Self {
+        Derivation {
+            rules,
+            stack: vec![(axiom, 0, depth)],
+        }
+    }
+}
+
+impl<'a, L: Letter> Iterator for Derivation<'a, L> {
+    type Item = L;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (word, position, remaining) = self.stack.last_mut()?;
+            if *position >= word.len() {
+                self.stack.pop();
+                continue;
+            }
+            let letter = word[*position];
+            *position += 1;
+
+            // Letters without a successor stay the same in every iteration.
+            if *remaining > 0 {
+                if let Some(Some(successor)) = self.rules.get(&letter) {
+                    let remaining = *remaining - 1;
+                    self.stack.push((successor, 0, remaining));
+                    continue;
+                }
+            }
+            return Some(letter);
+        }
+    }
+}
+
+//***************************************************************************
+//
+// TurtleVertices
+//
+//***************************************************************************
+
+// Runs the actions of the letters as they come in and hands out the
+// vertices right away, including the starting point and the None entries
+// that lift the pen.
+pub struct TurtleVertices<'a, L: Letter, I: Iterator<Item = L>> {
+    letters: I,
+    actions: &'a HashMap<L, Option<fn(payload: &mut LindenmayerPayload)>>,
+    payload: LindenmayerPayload,
+    pending: VecDeque<Option<MathPosition>>,
+    finished: bool,
+}
+
+impl<'a, L: Letter, I: Iterator<Item = L>> TurtleVertices<'a, L, I> {
+    pub fn new(letters: I, actions: &'a HashMap<L, Option<fn(payload: &mut LindenmayerPayload)>>, angle: f32) -> Self {
+        let mut payload = LindenmayerPayload::new();
+        payload.set_turning_angle(angle);
+        payload.begin_vertices();
+        TurtleVertices {
+            letters,
+            actions,
+            payload,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+impl<'a, L: Letter, I: Iterator<Item = L>> Iterator for TurtleVertices<'a, L, I> {
+    type Item = Option<MathPosition>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.finished {
+            match self.letters.next() {
+                Some(letter) => {
+                    letter.interpret(&mut self.payload);
+                    if let Some(Some(action)) = self.actions.get(&letter) {
+                        action(&mut self.payload);
+                    }
+                    self.pending.extend(self.payload.drain_vertex_buffer(1));
+                }
+                None => {
+                    self.pending.extend(self.payload.drain_vertex_buffer(0));
+                    self.finished = true;
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod derivation {
+        use crate::tryout::LindenmayerSystem;
+
+        #[test]
+        fn same_letters_as_the_word_stack() {
+            for mut system in [
+                LindenmayerSystem::koch(),
+                LindenmayerSystem::dragon_curve(),
+                LindenmayerSystem::first_plant(),
+            ] {
+                system.update_vertex_stack(4);
+                for depth in 0..=4 {
+                    let streamed: Vec<_> = system.derive_letters(depth).collect();
+                    assert_eq!(&streamed, system.get_word_stack_at(depth).unwrap());
+                }
+            }
+        }
+
+        #[test]
+        fn deep_dragon_curve() {
+            // F and G double every iteration, the turns in between add up to 4 per letter.
+            let dragon = LindenmayerSystem::dragon_curve();
+            let length = dragon.derive_letters(16).count();
+            assert_eq!(length, (1 << 16) + 4 * ((1 << 16) - 1));
+        }
+    }
+
+    mod turtle_vertices {
+        use std::collections::HashMap;
+        use crate::derivation::TurtleVertices;
+        use crate::tryout::{self, LindenmayerPayload, LindenmayerSystem};
+
+        #[test]
+        fn same_vertices_as_the_payload() {
+            let mut plant = LindenmayerSystem::first_plant();
+            plant.update_vertex_stack(3);
+            let word = plant.get_word_stack_at(3).unwrap();
+            let streamed: Vec<_> = plant.derive_vertices(3).collect();
+
+            let mut payload = LindenmayerPayload::new();
+            let actions: HashMap<_, _> = [
+                (tryout::LindenmayerLetter::F, Some(tryout::forward as fn(&mut LindenmayerPayload))),
+                (tryout::LindenmayerLetter::L, Some(tryout::turn_left)),
+                (tryout::LindenmayerLetter::R, Some(tryout::turn_right)),
+                (tryout::LindenmayerLetter::PUSH, Some(tryout::push)),
+                (tryout::LindenmayerLetter::POP, Some(tryout::pop)),
+            ].into_iter().collect();
+            payload.set_turning_angle(25.0f32.to_radians());
+            payload.compute_base_vertices(word, &actions);
+            assert_eq!(streamed.len(), payload.vertex_buffer().len());
+            for (streamed, computed) in streamed.iter().zip(payload.vertex_buffer()) {
+                match (streamed, computed) {
+                    (Some(streamed), Some(computed)) => assert!((*streamed - *computed).norm() < 1e-3),
+                    (None, None) => (),
+                    _ => panic!("pen lifted at different positions"),
+                }
+            }
+        }
+
+        #[test]
+        fn actions_see_the_last_vertex() {
+            // move_forward only lifts the pen after a vertex, so the
+            // streamed vertices must not be drained too early.
+            let actions: HashMap<char, Option<fn(&mut LindenmayerPayload)>> = [
+                ('F', Some(tryout::forward as fn(&mut LindenmayerPayload))),
+                ('G', Some(tryout::move_forward)),
+            ].into_iter().collect();
+            let word: Vec<char> = "FGGF".chars().collect();
+            let streamed: Vec<_> = TurtleVertices::new(word.iter().copied(), &actions, 0.0).collect();
+
+            let mut payload = LindenmayerPayload::new();
+            payload.set_turning_angle(0.0);
+            payload.compute_base_vertices(&word, &actions);
+            assert_eq!(&streamed[..], payload.vertex_buffer());
+        }
+    }
+}
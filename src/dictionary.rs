@@ -1,9 +1,14 @@
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::{Deref, DerefMut};
+use crate::actions::{ActionError, ActionRegistry};
 use crate::semantics::Payload;
 use crate::word::Word;
 
+// Semantics also get the word they stand for. They are registered by name
+// in an ActionRegistry, like the turtle actions.
+pub type Semantics<T, P> = fn(word: &[T], payload: &mut P);
+
 #[derive(Clone)]
 pub struct Dictionary<T, P: Payload>(HashMap<Word<T>, DictionaryEntry<T, P>>);
 
@@ -13,7 +18,9 @@ where
     P: Payload,
 {
     replacement: Option<Word<T>>,
-    semantics: Option<fn(word: &[T], payload: &mut P)>,
+    semantics: Option<Semantics<T, P>>,
+    // The name the semantics are bound by, if they were.
+    semantics_name: Option<String>,
 }
 
 impl<T, P> DictionaryEntry<T, P>
@@ -21,7 +28,7 @@ where
     P: Payload,
 {
     pub fn new() -> Self {
-        DictionaryEntry { replacement: None, semantics: None }
+        DictionaryEntry { replacement: None, semantics: None, semantics_name: None }
     }
 
     pub fn with_replacement(mut self, replacement: Word<T>) -> Self {
@@ -29,8 +36,8 @@ where
         self
     }
 
-    pub fn with_semantics(mut self, semantics: fn(word: &[T], payload: &mut P)) -> Self {
-        self.semantics = Some(semantics);
+    pub fn with_semantics(mut self, semantics: Semantics<T, P>) -> Self {
+        self.add_semantics(semantics);
         self
     }
 
@@ -40,20 +47,33 @@ where
 
     pub fn clear_semantics(&mut self) {
         self.semantics = None;
+        self.semantics_name = None;
     }
 
     pub fn add_replacement(&mut self, replacement: Word<T>) {
         self.replacement = Some(replacement);
     }
 
-    pub fn add_semantics(&mut self, semantics: fn(word: &[T], payload: &mut P)) {
+    pub fn add_semantics(&mut self, semantics: Semantics<T, P>) {
         self.semantics = Some(semantics);
+        self.semantics_name = None;
     }
 
-    pub fn semantics(&self) -> Option<fn(word: &[T], payload: &mut P)> {
+    // Like add_semantics, with the semantics looked up in the registry.
+    pub fn bind_semantics(&mut self, name: &str, registry: &ActionRegistry<Semantics<T, P>>) -> Result<(), ActionError> {
+        self.semantics = Some(registry.action(name)?);
+        self.semantics_name = Some(name.to_owned());
+        Ok(())
+    }
+
+    pub fn semantics(&self) -> Option<Semantics<T, P>> {
         self.semantics
     }
 
+    pub fn semantics_name(&self) -> Option<&str> {
+        self.semantics_name.as_deref()
+    }
+
     pub fn replacement(&self) -> Option<&Word<T>> {
         self.replacement.as_ref()
     }
@@ -67,12 +87,13 @@ where
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let display_replacement = match self.replacement {
             Some(ref word) => format!("{}", word),
-            None => format!("None"),
+            None => "None".to_string(),
         };
 
-        let display_semantics = match self.semantics {
-            Some(_) => format!("Some"),
-            None => format!("None"),
+        let display_semantics = match (&self.semantics, &self.semantics_name) {
+            (Some(_), Some(name)) => name.clone(),
+            (Some(_), None) => "Some".to_string(),
+            (None, _) => "None".to_string(),
         };
 
         write!(f, "DictionaryEntry {{ replacement: {}, semantics: {} }}", display_replacement, display_semantics)?;
@@ -86,13 +107,13 @@ where
     P: Payload,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Dictionary {{\n")?;
+        writeln!(f, "Dictionary {{")?;
         for word in self.keys() {
             let display_entry = match self.get(word) {
                 Some(entry) => format!("{}", entry),
-                None => format!("None"),
+                None => "None".to_string(),
             };
-            write!(f, "\t{} -> {}\n", word, display_entry)?;
+            writeln!(f, "\t{} -> {}", word, display_entry)?;
         }
         write!(f, "}}")?;
         Ok(())
@@ -135,6 +156,146 @@ where
     }
 }
 
+// DEFINITION
+
+// Semantics are function pointers, which can not be saved. A dictionary is
+// saved as its definition instead, where every semantics is replaced by the
+// name it is bound by.
+
+impl<T, P> Dictionary<T, P>
+where
+    T: Clone + PartialEq + Eq + std::hash::Hash,
+    P: Payload,
+{
+    pub fn to_definition(&self) -> Result<DictionaryDefinition<T>, DictionaryError<T>> {
+        let mut entries = Vec::new();
+        for (word, entry) in self.iter() {
+            // A name the registry lost is still saved.
+            let semantics = match (entry.semantics_name(), entry.semantics()) {
+                (Some(name), _) => Some(name.to_owned()),
+                (None, Some(_)) => return Err(DictionaryError::UnboundSemantics { word: word.clone() }),
+                (None, None) => None,
+            };
+            entries.push(EntryDefinition {
+                word: word.clone(),
+                replacement: entry.replacement().cloned(),
+                semantics,
+            });
+        }
+        Ok(DictionaryDefinition { entries })
+    }
+
+    pub fn from_definition(definition: DictionaryDefinition<T>, registry: &ActionRegistry<Semantics<T, P>>) -> Result<Self, DictionaryError<T>> {
+        let mut dictionary = Dictionary::new();
+        for entry in definition.entries {
+            let mut dictionary_entry = DictionaryEntry::new();
+            if let Some(replacement) = entry.replacement {
+                dictionary_entry.add_replacement(replacement);
+            }
+            if let Some(name) = entry.semantics {
+                if dictionary_entry.bind_semantics(&name, registry).is_err() {
+                    return Err(DictionaryError::UnknownSemantics { word: entry.word, name });
+                }
+            }
+            dictionary.insert(entry.word, dictionary_entry);
+        }
+        Ok(dictionary)
+    }
+
+    // Resolves the words bound by name again, after the registry changed.
+    // A name the registry does not know (anymore) leaves its word without
+    // semantics.
+    pub fn refresh_semantics(&mut self, registry: &ActionRegistry<Semantics<T, P>>) {
+        for entry in self.values_mut() {
+            if let Some(name) = &entry.semantics_name {
+                entry.semantics = registry.action(name).ok();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DictionaryDefinition<T> {
+    entries: Vec<EntryDefinition<T>>,
+}
+
+impl<T> DictionaryDefinition<T> {
+    pub fn entries(&self) -> &[EntryDefinition<T>] {
+        &self.entries
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryDefinition<T> {
+    word: Word<T>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    replacement: Option<Word<T>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    semantics: Option<String>,
+}
+
+impl<T> EntryDefinition<T> {
+    pub fn word(&self) -> &Word<T> {
+        &self.word
+    }
+
+    pub fn replacement(&self) -> Option<&Word<T>> {
+        self.replacement.as_ref()
+    }
+
+    pub fn semantics(&self) -> Option<&str> {
+        self.semantics.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DictionaryError<T> {
+    // The definition names semantics the registry does not know.
+    UnknownSemantics { word: Word<T>, name: String },
+    // The semantics of the word were not bound by name, so they have none.
+    UnboundSemantics { word: Word<T> },
+}
+
+impl<T> Display for DictionaryError<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DictionaryError::UnknownSemantics { word, name } => {
+                write!(f, "{}: no semantics registered as '{}'", word, name)
+            }
+            DictionaryError::UnboundSemantics { word } => {
+                write!(f, "{}: semantics are not bound by name", word)
+            }
+        }
+    }
+}
+
+impl<T> std::error::Error for DictionaryError<T> where T: std::fmt::Debug + Display {}
+
+// DEFAULT
+
+impl<T, P> Default for Dictionary<T, P>
+where
+    P: Payload,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P> Default for DictionaryEntry<T, P>
+where
+    P: Payload,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // DEREF
 
 impl<T, P> Deref for Dictionary<T, P>
@@ -197,3 +358,123 @@ where
         )
     }
 }
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod fixtures {
+        use crate::actions::ActionRegistry;
+        use crate::dictionary::{Dictionary, DictionaryEntry, Semantics};
+        use crate::word::Word;
+
+        pub fn append(word: &[char], payload: &mut String) {
+            payload.extend(word);
+        }
+
+        pub fn clear(_: &[char], payload: &mut String) {
+            payload.clear();
+        }
+
+        pub fn registry() -> ActionRegistry<Semantics<char, String>> {
+            ActionRegistry::<Semantics<char, String>>::new().with_action("append", append).with_action("clear", clear)
+        }
+
+        fn bound(mut entry: DictionaryEntry<char, String>, name: &str) -> DictionaryEntry<char, String> {
+            entry.bind_semantics(name, &registry()).unwrap();
+            entry
+        }
+
+        pub fn dictionary() -> Dictionary<char, String> {
+            Dictionary::with_words_and_entries([
+                (Word::from("F"), bound(DictionaryEntry::new().with_replacement(Word::from("F+F")), "append")),
+                (Word::from("+"), bound(DictionaryEntry::new(), "append")),
+                (Word::from("X"), DictionaryEntry::new().with_replacement(Word::from("XF"))),
+                (Word::from("C"), bound(DictionaryEntry::new(), "clear")),
+            ])
+        }
+    }
+
+    mod refresh_semantics {
+        use crate::actions::ActionRegistry;
+        use crate::dictionary::Semantics;
+        use crate::dictionary::tests::fixtures::{append, dictionary, registry};
+        use crate::word::Word;
+
+        #[test]
+        fn follow_the_registry() {
+            let mut dictionary = dictionary();
+            let mut registry = registry();
+            registry.register("append", |word, payload| payload.extend(word.iter().rev()));
+            dictionary.refresh_semantics(&registry);
+
+            let mut payload = String::new();
+            dictionary[&Word::from("F")].semantics().unwrap()(&['F', 'G'], &mut payload);
+            assert_eq!(payload, "GF");
+            assert_eq!(dictionary[&Word::from("F")].semantics_name(), Some("append"));
+        }
+
+        #[test]
+        fn forget_unknown_names() {
+            let mut dictionary = dictionary();
+            dictionary.refresh_semantics(&ActionRegistry::<Semantics<char, String>>::new().with_action("append", append));
+            assert!(dictionary[&Word::from("C")].semantics().is_none());
+            // The name is kept, to be resolved again or saved.
+            assert_eq!(dictionary[&Word::from("C")].semantics_name(), Some("clear"));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod definition {
+        use crate::dictionary::{Dictionary, DictionaryDefinition, DictionaryEntry, DictionaryError, Semantics};
+        use crate::dictionary::tests::fixtures::{append, clear, dictionary, registry};
+        use crate::actions::ActionRegistry;
+        use crate::word::Word;
+
+        fn assert_same(first: &Dictionary<char, String>, second: &Dictionary<char, String>) {
+            assert_eq!(first.len(), second.len());
+            for (word, entry) in first.iter() {
+                let other = &second[word];
+                assert_eq!(entry.replacement(), other.replacement());
+                assert_eq!(entry.semantics_name(), other.semantics_name());
+            }
+        }
+
+        #[test]
+        fn json_round_trip() {
+            let dictionary = dictionary();
+            let json = serde_json::to_string(&dictionary.to_definition().unwrap()).unwrap();
+            let definition: DictionaryDefinition<char> = serde_json::from_str(&json).unwrap();
+            assert_same(&Dictionary::from_definition(definition, &registry()).unwrap(), &dictionary);
+        }
+
+        #[test]
+        fn toml_round_trip() {
+            let dictionary = dictionary();
+            let toml = toml::to_string(&dictionary.to_definition().unwrap()).unwrap();
+            let definition: DictionaryDefinition<char> = toml::from_str(&toml).unwrap();
+            let loaded = Dictionary::from_definition(definition, &registry()).unwrap();
+            assert_same(&loaded, &dictionary);
+
+            let mut payload = String::from("G");
+            loaded[&Word::from("F")].semantics().unwrap()(&['F'], &mut payload);
+            assert_eq!(payload, "GF");
+        }
+
+        #[test]
+        fn semantics_need_a_name() {
+            let registry = ActionRegistry::<Semantics<char, String>>::new().with_action("append", append);
+            let dictionary = Dictionary::with_words_and_entries([(Word::from("C"), DictionaryEntry::new().with_semantics(clear))]);
+            assert_eq!(
+                dictionary.to_definition().unwrap_err(),
+                DictionaryError::UnboundSemantics { word: Word::from("C") },
+            );
+
+            let json = r#"{"entries":[{"word":["F"],"semantics":"draw"}]}"#;
+            let definition: DictionaryDefinition<char> = serde_json::from_str(json).unwrap();
+            let error = Dictionary::from_definition(definition, &registry).err().unwrap();
+            assert_eq!(error.to_string(), "F: no semantics registered as 'draw'");
+        }
+    }
+}
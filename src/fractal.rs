@@ -1,19 +1,86 @@
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::sync::PoisonError;
 
-use crate::{semantics::Payload, word_slice::Word};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
-pub struct Fractal<T, P>
+use crate::grammar::{Grammar, GrammarErrors, ProductionRule};
+use crate::actions::{Action, ActionError, ActionRegistry, SharedActionRegistry};
+use crate::growth::{GrowthError, GrowthMatrix};
+use crate::limits::{saturating_u128, DerivationLimits, LimitError};
+use crate::alphabet::{Alphabet, AlphabetError, CompactWord, RuleTable, SymbolId};
+use crate::{matcher::SubwordMatcher, semantics::Payload, word::Word, word_slice::Word as _};
+
+// The words are stored as ids of the letters of the grammar, a single byte
+// per letter with the default u8 ids.
+pub struct Fractal<T, P, I = u8>
 where
     P: Payload,
     T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
 {
-        word_stack: Vec<Vec<T>>,
-        replacements: HashMap<Vec<T>, Vec<T>>,
+        // Every letter of the grammar, interned as soon as it is added.
+        alphabet: Alphabet<T, I>,
+        word_stack: Vec<Vec<I>>,
+        // The starting word and the rules, the derived words follow from it.
+        grammar: Grammar<T>,
         semantics: HashMap<Vec<T>, fn(&mut P)>,
+        // Words bound to their semantics by name, resolved in the registry.
+        semantics_bindings: HashMap<Vec<T>, String>,
+        semantics_registry: SharedActionRegistry<Action<P>>,
+        // Generation of the registry the bindings were last resolved in.
+        semantics_generation: Option<u64>,
         payload: P,
+        seed: u64,
+        rng: ChaCha8Rng,
+        // Built from the rules on first use, dropped whenever a rule is added.
+        rewriter: Option<Rewriter<I>>,
+        // Predicted when the limits are first checked, dropped along with the rewriter.
+        growth: Option<Result<GrowthMatrix<T>, GrowthError>>,
+        limits: DerivationLimits,
+}
+
+// Deterministic rules of single letters are looked up in a table of ids,
+// all other rules are matched by a copy of the grammar written in ids.
+enum Rewriter<I: SymbolId> {
+    Table(RuleTable<I>),
+    Matcher(Grammar<I>, SubwordMatcher<I>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FractalError<T> {
+    Grammar(GrammarErrors<T>),
+    // The grammar has more letters than the ids can tell apart.
+    Alphabet(AlphabetError),
+}
+
+impl<T> From<GrammarErrors<T>> for FractalError<T> {
+    fn from(errors: GrammarErrors<T>) -> Self {
+        FractalError::Grammar(errors)
+    }
+}
+
+impl<T> From<AlphabetError> for FractalError<T> {
+    fn from(error: AlphabetError) -> Self {
+        FractalError::Alphabet(error)
+    }
+}
+
+impl<T> std::fmt::Display for FractalError<T>
+where
+    T: std::fmt::Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FractalError::Grammar(errors) => write!(f, "{}", errors),
+            FractalError::Alphabet(error) => write!(f, "{}", error),
+        }
+    }
 }
 
+impl<T> std::error::Error for FractalError<T> where T: std::fmt::Debug + std::fmt::Display {}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Koch {
     Forward,
@@ -32,91 +99,358 @@ impl std::fmt::Display for Koch {
     }
 }
 
-impl<T, P> Fractal<T, P>
+impl<T, P, I> Fractal<T, P, I>
 where
     P: Payload,
     T: Clone + PartialEq + Eq + Hash,
+    I: SymbolId,
 {
     pub fn new(payload: P) -> Self
     {
         Fractal {
-            word_stack: vec![],
-            replacements: HashMap::new(),
+            alphabet: Alphabet::new(),
+            word_stack: vec![vec![]],
+            grammar: Grammar::new(vec![], vec![]),
             semantics: HashMap::new(),
+            semantics_bindings: HashMap::new(),
+            semantics_registry: ActionRegistry::new().into_shared(),
+            semantics_generation: None,
             payload,
+            seed: 0,
+            rng: ChaCha8Rng::seed_from_u64(0),
+            rewriter: None,
+            growth: None,
+            limits: DerivationLimits::new(),
         }
     }
 
-    pub fn with_starting_word(&mut self, starting_word: Vec<T>)
+    // The axiom becomes the starting word, the rules are applied the way
+    // Grammar::step applies them.
+    pub fn from_grammar(grammar: Grammar<T>, payload: P) -> Result<Self, AlphabetError>
+    {
+        let mut fractal = Self::new(payload);
+        fractal.alphabet.encode(grammar.terminals())?;
+        fractal.alphabet.encode(grammar.non_terminals())?;
+        fractal.word_stack = vec![fractal.alphabet.encode(&grammar.axiom()[..])?];
+        fractal.grammar = grammar;
+        Ok(fractal)
+    }
+
+    pub fn grammar(&self) -> &Grammar<T>
     {
-        self.word_stack[0] = starting_word;
+        &self.grammar
     }
 
+    // Letters that are new to the grammar become terminals.
+    pub fn with_starting_word(&mut self, starting_word: Vec<T>) -> Result<(), FractalError<T>>
+    {
+        self.change_grammar(&[&starting_word], |grammar| {
+            grammar.add_terminals(&starting_word);
+            grammar.change_axiom(Word::new(starting_word.clone()))
+        })
+    }
+
+    // Reseeding throws away every derived word, since those were picked with
+    // the old seed. The same seed always leads to the same derivations.
+    pub fn with_seed(&mut self, seed: u64)
+    {
+        self.seed = seed;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.word_stack.truncate(1);
+    }
+
+    pub fn seed(&self) -> u64
+    {
+        self.seed
+    }
+
+    // Replaces every rule for the predecessor, weighted ones included. Letters
+    // that are new to the grammar are added, the ones of the predecessor as
+    // non terminals. Like any change of the rules, this throws away every
+    // derived word.
+    pub fn add_replacement(&mut self, predecessor: Vec<T>, successor: Vec<T>) -> Result<(), FractalError<T>>
+    {
+        self.change_grammar(&[&predecessor, &successor], |grammar| {
+            grammar.add_non_terminals(&predecessor);
+            grammar.add_terminals(&successor);
+            let rule = ProductionRule::new(Word::new(predecessor.clone()), Word::new(successor.clone()));
+            grammar.replace_rules(&predecessor, vec![rule])
+        })
+    }
+
+    // Adds one more weighted successor for the predecessor. A deterministic
+    // rule for it is dropped, it would shadow all weighted ones.
+    pub fn add_stochastic_replacement(&mut self, predecessor: Vec<T>, successor: Vec<T>, weight: f32) -> Result<(), FractalError<T>>
+    {
+        self.change_grammar(&[&predecessor, &successor], |grammar| {
+            grammar.add_non_terminals(&predecessor);
+            grammar.add_terminals(&successor);
+            let lhs = Word::new(predecessor.clone());
+            let mut rules: Vec<ProductionRule<T>> = grammar.rules_for(&lhs).into_iter()
+                .filter(|rule| rule.is_stochastic())
+                .cloned()
+                .collect();
+            rules.push(ProductionRule::new(lhs.clone(), Word::new(successor.clone())).with_weight(weight));
+            grammar.replace_rules(&lhs[..], rules)
+        })
+    }
+
+    // Interns the letters and applies the change to copies of the alphabet
+    // and the grammar, the fractal stays as it is if either fails. Otherwise
+    // the derivation restarts from the axiom with the current seed.
+    fn change_grammar<F>(&mut self, letters: &[&[T]], change: F) -> Result<(), FractalError<T>>
+    where
+        F: FnOnce(&mut Grammar<T>) -> Result<(), GrammarErrors<T>>,
+    {
+        let mut alphabet = self.alphabet.clone();
+        for letters in letters {
+            alphabet.encode(letters)?;
+        }
+        let mut grammar = self.grammar.clone();
+        change(&mut grammar)?;
+
+        self.word_stack = vec![alphabet.encode(&grammar.axiom()[..])?];
+        self.alphabet = alphabet;
+        self.grammar = grammar;
+        self.rewriter = None;
+        self.growth = None;
+        self.with_seed(self.seed);
+        Ok(())
+    }
+
+    // Every letter of the grammar is interned when it is added, so the
+    // letters of the derived words always have an id.
     pub fn apply_replacements(&mut self)
     {
-        self.word_stack.push(
-            self.word_stack
-                .last()
-                .unwrap()
-                .apply_relacements(&self.replacements)
-        );
+        if self.rewriter.is_none() {
+            self.rewriter = Some(self.build_rewriter());
+        }
+        let word = self.word_stack.last().unwrap();
+        let next_word = match self.rewriter.as_ref().unwrap() {
+            Rewriter::Table(table) => table.rewrite(word),
+            Rewriter::Matcher(grammar, matcher) => grammar.step_matched(matcher, word, &mut self.rng),
+        };
+        self.word_stack.push(next_word);
     }
 
-    pub fn iteration(&mut self, depth: usize) -> &[T]
+    fn build_rewriter(&mut self) -> Rewriter<I>
     {
+        let rules = match self.grammar.single_letter_rules() {
+            Ok(rules) => rules,
+            Err(_) => {
+                let grammar = self.encode_grammar();
+                let matcher = grammar.matcher();
+                return Rewriter::Matcher(grammar, matcher);
+            }
+        };
+        let replacements: HashMap<Vec<T>, Vec<T>> = rules.into_iter()
+            .map(|(letter, successor)| (vec![letter.clone()], successor[..].to_vec()))
+            .collect();
+        Rewriter::Table(RuleTable::from_replacements(&mut self.alphabet, &replacements).unwrap())
+    }
+
+    // The rules keep their order and weights, so the rng picks the same
+    // successors as it would for the letters.
+    fn encode_grammar(&self) -> Grammar<I>
+    {
+        let encode = |letters: &[T]| -> Vec<I> {
+            letters.iter().map(|letter| self.alphabet.id(letter).unwrap()).collect()
+        };
+        let rules = self.grammar.production_rules().iter()
+            .map(|rule| {
+                let encoded = ProductionRule::new(Word::new(encode(&rule.lhs()[..])), Word::new(encode(&rule.rhs()[..])));
+                if rule.is_stochastic() { encoded.with_weight(rule.weight()) } else { encoded }
+            })
+            .collect();
+        Grammar::new(encode(self.grammar.terminals()), encode(self.grammar.non_terminals()))
+            .with_production_rules(rules)
+            .unwrap()
+    }
+
+    pub fn with_limits(&mut self, limits: DerivationLimits)
+    {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &DerivationLimits
+    {
+        &self.limits
+    }
+
+    // Borrows the word of the depth, the letters are looked up as they are read.
+    pub fn iteration(&mut self, depth: usize) -> Result<impl ExactSizeIterator<Item = &T> + '_, LimitError>
+    {
+        self.derive(depth)?;
+        let letters = self.alphabet.letters();
+        Ok(self.word_stack[depth].iter().map(move |&id| &letters[id.index()]))
+    }
+
+    // Same as iteration, as ids together with a copy of the alphabet, for a
+    // word that has to outlive the borrow of the fractal.
+    pub fn compact_iteration(&mut self, depth: usize) -> Result<CompactWord<T, I>, LimitError>
+    {
+        self.derive(depth)?;
+        Ok(CompactWord::new(self.alphabet.clone(), self.word_stack[depth].clone()))
+    }
+
+    fn derive(&mut self, depth: usize) -> Result<(), LimitError>
+    {
+        self.check_limits(depth)?;
         while self.word_stack.len() <= depth {
             self.apply_replacements();
         }
-        return self.word_stack[depth].as_slice();
+        Ok(())
+    }
+
+    // Checks the words still missing up to the given depth against the
+    // limits, before any of them is derived. Deterministic rules of single
+    // letters have an exact length, for all other rules every letter is
+    // assumed to grow into the longest successor.
+    pub fn check_limits(&mut self, depth: usize) -> Result<(), LimitError>
+    {
+        if depth < self.word_stack.len() {
+            return Ok(());
+        }
+        if self.growth.is_none() {
+            self.growth = Some(self.growth());
+        }
+
+        let letter_size = std::mem::size_of::<I>() as u128;
+        let mut bytes = self.word_stack.iter()
+            .fold(0u128, |bytes, word| bytes.saturating_add(word.len() as u128 * letter_size));
+        let growth = self.growth.as_ref().unwrap().as_ref().ok();
+        let growth_factor = self.grammar.production_rules().iter()
+            .map(|rule| rule.rhs().len() as u128)
+            .fold(1, u128::max);
+        let mut length = self.word_stack.last().map_or(0, |word| word.len() as u128);
+
+        for depth in self.word_stack.len()..=depth {
+            length = match growth {
+                Some(growth) => saturating_u128(&growth.length(depth as u64)),
+                None => length.saturating_mul(growth_factor),
+            };
+            bytes = bytes.saturating_add(length.saturating_mul(letter_size));
+            self.limits.check_word_length(depth, length)?;
+            self.limits.check_memory(depth, bytes)?;
+        }
+        Ok(())
+    }
+
+    // Words bound by name follow the changes to the registry, which can be
+    // shared with other fractals. A new fractal starts out with an empty one.
+    pub fn with_semantics_registry(&mut self, registry: SharedActionRegistry<Action<P>>)
+    {
+        self.semantics_registry = registry;
+        self.semantics_generation = None;
+    }
+
+    pub fn semantics_registry(&self) -> &SharedActionRegistry<Action<P>>
+    {
+        &self.semantics_registry
+    }
+
+    // The semantics of the word are looked up in the registry by name.
+    pub fn bind_semantics(&mut self, word: Vec<T>, name: &str) -> Result<(), ActionError>
+    {
+        let semantics = self.semantics_registry.read().unwrap_or_else(PoisonError::into_inner).action(name)?;
+        self.semantics.insert(word.clone(), semantics);
+        self.semantics_bindings.insert(word, name.to_owned());
+        Ok(())
+    }
+
+    pub fn semantics_name(&self, word: &[T]) -> Option<&str>
+    {
+        self.semantics_bindings.get(word).map(String::as_str)
+    }
+
+    // Resolves the words bound by name again if the registry changed since.
+    // A name the registry does not know (anymore) leaves its word without
+    // semantics. Returns whether anything was resolved.
+    pub fn refresh_semantics(&mut self) -> bool
+    {
+        let registry = self.semantics_registry.read().unwrap_or_else(PoisonError::into_inner);
+        if self.semantics_generation == Some(registry.generation()) {
+            return false;
+        }
+        self.semantics_generation = Some(registry.generation());
+        for (word, name) in &self.semantics_bindings {
+            match registry.action(name) {
+                Ok(semantics) => self.semantics.insert(word.clone(), semantics),
+                Err(_) => self.semantics.remove(word),
+            };
+        }
+        true
     }
 
     pub fn apply_semantics(&mut self, depth: usize)
     {
-        self.word_stack[depth].apply_semantics(&self.semantics, &mut self.payload);
+        self.refresh_semantics();
+        // Words with letters the alphabet does not know can never match.
+        let semantics: HashMap<Vec<I>, fn(&mut P)> = self.semantics.iter()
+            .filter_map(|(word, &semantics)| {
+                let ids = word.iter().map(|letter| self.alphabet.id(letter)).collect::<Option<Vec<I>>>()?;
+                Some((ids, semantics))
+            })
+            .collect();
+        self.word_stack[depth].apply_semantics(&semantics, &mut self.payload);
+    }
+
+    // Predicts the growth of deterministic rules from the starting word.
+    pub fn growth(&self) -> Result<GrowthMatrix<T>, GrowthError>
+    {
+        if self.is_stochastic() {
+            return Err(GrowthError::NotDeterministic);
+        }
+        GrowthMatrix::from_grammar(&self.grammar.axiom()[..], &self.grammar)
+    }
+
+    fn is_stochastic(&self) -> bool
+    {
+        self.grammar.production_rules().iter().any(ProductionRule::is_stochastic)
     }
 }
 
-pub fn Koch(payload: String) -> Fractal<Koch, String>
+pub fn koch(payload: String) -> Fractal<Koch, String>
 {
-    let word_stack = vec![
-        vec![Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward],
-    ];
-
-    let mut replacements = HashMap::new();
-    replacements.insert(
-        vec![Koch::Forward],
-        vec![Koch::Forward, Koch::TurnLeft, Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward, Koch::TurnLeft, Koch::Forward]
-    );
-
-    let mut semantics: HashMap<Vec<Koch>, fn(&mut String)> = HashMap::new();
-    semantics.insert(
-        vec![Koch::Forward],
-        |s| {
-            let to_push = format!("{}", Koch::Forward);
-            s.push_str(&to_push);
-        }
-    );
-    semantics.insert(
-        vec![Koch::TurnLeft],
-        |s| {
-            let to_push = format!("{}", Koch::TurnLeft);
-            s.push_str(&to_push);
-        }
-    );
-    semantics.insert(
-        vec![Koch::TurnRight],
-        |s| {
-            let to_push = format!("{}", Koch::TurnRight);
-            s.push_str(&to_push);
-        }
-    );
-
-    Fractal { 
-        word_stack,
-        replacements,
-        semantics,
-        payload,
-    }
+    let grammar = Grammar::new(vec![Koch::TurnLeft, Koch::TurnRight], vec![Koch::Forward])
+        .with_production_rules(vec![ProductionRule::new(
+            Word::from(vec![Koch::Forward]),
+            Word::from(vec![Koch::Forward, Koch::TurnLeft, Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward, Koch::TurnLeft, Koch::Forward]),
+        )])
+        .unwrap()
+        .with_axiom(Word::from(vec![Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward]))
+        .unwrap();
+
+    // Every letter writes itself, bound by name like the turtle actions.
+    let registry = ActionRegistry::<Action<String>>::new()
+        .with_action(
+            "forward",
+            |s| {
+                let to_push = format!("{}", Koch::Forward);
+                s.push_str(&to_push);
+            }
+        )
+        .with_action(
+            "turn_left",
+            |s| {
+                let to_push = format!("{}", Koch::TurnLeft);
+                s.push_str(&to_push);
+            }
+        )
+        .with_action(
+            "turn_right",
+            |s| {
+                let to_push = format!("{}", Koch::TurnRight);
+                s.push_str(&to_push);
+            }
+        );
+
+    let mut fractal = Fractal::from_grammar(grammar, payload).unwrap();
+    fractal.with_semantics_registry(registry.into_shared());
+    fractal.bind_semantics(vec![Koch::Forward], "forward").unwrap();
+    fractal.bind_semantics(vec![Koch::TurnLeft], "turn_left").unwrap();
+    fractal.bind_semantics(vec![Koch::TurnRight], "turn_right").unwrap();
+    fractal
 }
 
 // TESTS
@@ -125,14 +459,14 @@ pub fn Koch(payload: String) -> Fractal<Koch, String>
 mod tests {
 
     mod apply_replacements {
-        use crate::fractal::Koch;
+        use crate::fractal::{self, Koch};
 
         #[test]
         fn koch() {
-            let mut koch = Koch(String::new());
+            let mut koch = fractal::koch(String::new());
             koch.apply_replacements();
             assert_eq!(
-                koch.word_stack[1],
+                koch.alphabet.decode(&koch.word_stack[1]).unwrap(),
                 vec![
                     Koch::Forward, Koch::TurnLeft, Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward, Koch::TurnLeft, Koch::Forward,
                     Koch::TurnRight,
@@ -146,14 +480,277 @@ mod tests {
         }
     }
 
+    mod apply_stochastic_replacements {
+        use crate::fractal::{self, Koch};
+
+        fn stochastic_koch(seed: u64) -> crate::fractal::Fractal<Koch, String> {
+            let mut koch = fractal::koch(String::new());
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnLeft, Koch::Forward], 1.0).unwrap();
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnRight, Koch::Forward], 1.0).unwrap();
+            koch.with_seed(seed);
+            koch
+        }
+
+        #[test]
+        fn same_seed_gives_same_word() {
+            let mut first = stochastic_koch(42);
+            let mut second = stochastic_koch(42);
+            assert!(first.iteration(4).unwrap().eq(second.iteration(4).unwrap()));
+        }
+
+        #[test]
+        fn reseeding_restarts_the_derivation() {
+            let mut koch = stochastic_koch(7);
+            let expected: Vec<Koch> = koch.iteration(3).unwrap().cloned().collect();
+            koch.with_seed(8);
+            assert!(koch.iteration(3).is_ok());
+            koch.with_seed(7);
+            assert!(koch.iteration(3).unwrap().eq(&expected));
+        }
+
+        #[test]
+        fn successors_with_zero_weight_are_never_picked() {
+            let mut koch = fractal::koch(String::new());
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnLeft, Koch::Forward], 0.0).unwrap();
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnRight, Koch::Forward], 1.0).unwrap();
+            assert!(!koch.iteration(3).unwrap().any(|&letter| letter == Koch::TurnLeft));
+        }
+
+        #[test]
+        fn stochastic_replacement_drops_the_deterministic_rule() {
+            let mut koch = fractal::koch(String::new());
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::Forward], 1.0).unwrap();
+            assert!(koch.grammar().production_rules().iter().all(|rule| rule.is_stochastic()));
+            assert_eq!(koch.iteration(1).unwrap().len(), 3 * 2 + 4);
+        }
+    }
+
+    mod add_replacement {
+        use crate::fractal::{self, Fractal, Koch};
+        use crate::grammar::{Grammar, ProductionRule};
+        use crate::word::Word;
+
+        fn stochastic_koch(seed: u64) -> Fractal<Koch, String> {
+            let mut koch = fractal::koch(String::new());
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnLeft, Koch::Forward], 1.0).unwrap();
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnRight, Koch::Forward], 1.0).unwrap();
+            koch.with_seed(seed);
+            koch
+        }
+
+        #[test]
+        fn rebuild_the_matcher() {
+            let mut koch = fractal::koch(String::new());
+            assert!(koch.iteration(1).is_ok());
+            koch.add_replacement(vec![Koch::TurnRight, Koch::TurnRight], vec![Koch::TurnLeft]).unwrap();
+            assert_eq!(koch.iteration(1).unwrap().filter(|&&letter| letter == Koch::TurnLeft).count(), 3 * 2 + 2);
+            assert_eq!(koch.iteration(1).unwrap().len(), 3 * 8 + 2);
+        }
+
+        #[test]
+        fn restart_with_the_same_seed() {
+            let mut koch = stochastic_koch(42);
+            let mut fresh = stochastic_koch(42);
+            assert!(koch.iteration(3).is_ok());
+            koch.add_replacement(vec![Koch::TurnLeft], vec![Koch::TurnRight, Koch::TurnLeft]).unwrap();
+            fresh.add_replacement(vec![Koch::TurnLeft], vec![Koch::TurnRight, Koch::TurnLeft]).unwrap();
+            assert!(koch.iteration(3).unwrap().eq(fresh.iteration(3).unwrap()));
+
+            assert!(koch.iteration(4).is_ok());
+            koch.with_starting_word(vec![Koch::Forward]).unwrap();
+            fresh.with_starting_word(vec![Koch::Forward]).unwrap();
+            assert!(koch.iteration(3).unwrap().eq(fresh.iteration(3).unwrap()));
+        }
+
+        #[test]
+        fn nothing_changes_on_error() {
+            let grammar = Grammar::new(vec!['+'], vec!['F'])
+                .with_production_rules(vec![ProductionRule::new(Word::from("F"), Word::from("F+F"))])
+                .unwrap()
+                .with_axiom(Word::from("F"))
+                .unwrap();
+            let mut fractal: Fractal<char, String> = Fractal::from_grammar(grammar.clone(), String::new()).unwrap();
+            assert!(fractal.add_replacement(vec![], vec!['G']).is_err());
+            assert!(fractal.add_stochastic_replacement(vec![], vec!['G'], 1.0).is_err());
+            assert_eq!(fractal.grammar().terminals(), grammar.terminals());
+            assert_eq!(fractal.grammar().non_terminals(), grammar.non_terminals());
+        }
+    }
+
+    mod from_grammar {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::fractal::{Fractal, Koch};
+        use crate::grammar::{Grammar, ProductionRule};
+        use crate::word::Word;
+
+        #[test]
+        fn same_words_as_the_grammar() {
+            let forward = Word::from(vec![Koch::Forward]);
+            let grammar = Grammar::new(vec![Koch::TurnLeft, Koch::TurnRight], vec![Koch::Forward])
+                .with_production_rules(vec![ProductionRule::new(forward.clone(), Word::from(vec![Koch::Forward, Koch::TurnLeft, Koch::Forward]))])
+                .unwrap()
+                .with_axiom(forward)
+                .unwrap();
+            let mut fractal: Fractal<Koch, String> = Fractal::from_grammar(grammar.clone(), String::new()).unwrap();
+            for depth in 0..5 {
+                assert!(fractal.iteration(depth).unwrap().eq(&grammar.derive(depth)[..]));
+            }
+        }
+
+        #[test]
+        fn same_stochastic_words_as_the_grammar() {
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F'])
+                .with_production_rules(vec![
+                    ProductionRule::new(Word::from("F"), Word::from("F+F")).with_weight(1.0),
+                    ProductionRule::new(Word::from("F"), Word::from("F-F")).with_weight(2.0),
+                ])
+                .unwrap()
+                .with_axiom(Word::from("F"))
+                .unwrap();
+            let mut fractal: Fractal<char, String> = Fractal::from_grammar(grammar.clone(), String::new()).unwrap();
+            fractal.with_seed(3);
+            let expected = grammar.derive_with_rng(5, &mut ChaCha8Rng::seed_from_u64(3));
+            assert!(fractal.iteration(5).unwrap().eq(&expected[..]));
+        }
+
+        #[test]
+        fn deterministic_rules_shadow_weighted_ones() {
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F'])
+                .with_production_rules(vec![
+                    ProductionRule::new(Word::from("F"), Word::from("F+F")),
+                    ProductionRule::new(Word::from("F"), Word::from("F-F")).with_weight(1.0),
+                ])
+                .unwrap()
+                .with_axiom(Word::from("F"))
+                .unwrap();
+            let mut fractal: Fractal<char, String> = Fractal::from_grammar(grammar, String::new()).unwrap();
+            assert!(fractal.iteration(2).unwrap().eq(&['F', '+', 'F', '+', 'F', '+', 'F']));
+        }
+    }
+
+    mod check_limits {
+        use crate::fractal::{self, Koch};
+        use crate::limits::{DerivationLimits, LimitError};
+
+        #[test]
+        fn refuse_before_deriving() {
+            let mut koch = fractal::koch(String::new());
+            koch.with_limits(DerivationLimits::unlimited().with_max_word_length(1000));
+            // Depth n has 3 * 4^n forward letters and 4 * 4^n turns.
+            assert_eq!(koch.iteration(3).unwrap().len(), 448);
+            assert_eq!(koch.iteration(30).err(), Some(LimitError::WordLength { depth: 4, length: 1792, limit: 1000 }));
+            assert_eq!(koch.word_stack.len(), 4);
+        }
+
+        #[test]
+        fn derived_words_pass() {
+            let mut koch = fractal::koch(String::new());
+            assert!(koch.iteration(3).is_ok());
+            koch.with_limits(DerivationLimits::unlimited().with_max_word_length(10));
+            assert!(koch.check_limits(3).is_ok());
+            assert!(koch.check_limits(4).is_err());
+        }
+
+        #[test]
+        fn predict_the_new_rules() {
+            let mut koch = fractal::koch(String::new());
+            koch.with_limits(DerivationLimits::unlimited().with_max_word_length(1000));
+            assert!(koch.check_limits(4).is_err());
+            koch.add_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnLeft]).unwrap();
+            // Depth n has 3 forward letters and 4 + 3 * n turns.
+            assert!(koch.check_limits(4).is_ok());
+        }
+
+        #[test]
+        fn memory_of_all_words() {
+            let mut koch = fractal::koch(String::new());
+            // A letter takes a single byte as a u8 id.
+            let bytes = [7, 28, 112, 448].iter().sum::<usize>();
+            koch.with_limits(DerivationLimits::unlimited().with_max_memory(bytes));
+            assert!(koch.iteration(3).is_ok());
+            assert!(matches!(koch.iteration(4), Err(LimitError::Memory { depth: 4, .. })));
+        }
+
+        #[test]
+        fn bound_stochastic_growth() {
+            let mut koch = fractal::koch(String::new());
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward], 1.0).unwrap();
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::TurnLeft, Koch::Forward, Koch::TurnRight, Koch::TurnRight, Koch::Forward, Koch::TurnLeft, Koch::Forward], 1.0).unwrap();
+            koch.with_limits(DerivationLimits::unlimited().with_max_word_length(7 * 8 * 8));
+            assert!(koch.check_limits(2).is_ok());
+            assert!(koch.check_limits(3).is_err());
+        }
+    }
+
+    mod compact_iteration {
+        use crate::alphabet::AlphabetError;
+        use crate::fractal::{self, Fractal, FractalError, Koch};
+        use crate::grammar::Grammar;
+        use crate::word::Word;
+
+        #[test]
+        fn same_word_as_iteration() {
+            let mut koch = fractal::koch(String::new());
+            let compact = koch.compact_iteration(4).unwrap();
+            assert_eq!(compact.alphabet().len(), 3);
+            assert!(koch.iteration(4).unwrap().eq(&compact.decode()));
+        }
+
+        #[test]
+        fn stochastic_replacements() {
+            let mut koch = fractal::koch(String::new());
+            koch.add_stochastic_replacement(vec![Koch::Forward], vec![Koch::Forward, Koch::Forward], 1.0).unwrap();
+            let compact = koch.compact_iteration(1).unwrap();
+            assert_eq!(compact.len(), 3 * 2 + 4);
+            assert!(koch.iteration(1).unwrap().eq(&compact.decode()));
+        }
+
+        #[test]
+        fn too_many_letters() {
+            let letters: Vec<u32> = (0..300).collect();
+            let grammar = Grammar::new(letters[..200].to_vec(), vec![]).with_axiom(Word::from(vec![0])).unwrap();
+            let mut fractal: Fractal<u32, String> = Fractal::from_grammar(grammar, String::new()).unwrap();
+            assert_eq!(
+                fractal.with_starting_word(letters),
+                Err(FractalError::Alphabet(AlphabetError::Full { capacity: 256 })),
+            );
+            assert!(fractal.iteration(0).unwrap().eq(&[0]));
+            // None of the letters was interned, so there is still room.
+            assert!(fractal.with_starting_word(vec![299]).is_ok());
+        }
+    }
+
     mod apply_semantics {
-        use crate::fractal::Koch;
+        use crate::actions::ActionError;
+        use crate::fractal::{self, Koch};
 
         #[test]
         fn koch() {
-            let mut koch = Koch(String::new());
+            let mut koch = fractal::koch(String::new());
             koch.apply_semantics(0);
             assert_eq!(koch.payload, "F--F--F");
         }
+
+        #[test]
+        fn by_name() {
+            let mut koch = fractal::koch(String::new());
+            koch.semantics_registry().write().unwrap().register("shout", |payload: &mut String| payload.push('!'));
+            koch.bind_semantics(vec![Koch::TurnRight], "shout").unwrap();
+            assert_eq!(koch.bind_semantics(vec![Koch::Forward], "whisper"), Err(ActionError::UnknownAction("whisper".to_owned())));
+            assert_eq!(koch.semantics_name(&[Koch::TurnRight]), Some("shout"));
+            koch.apply_semantics(0);
+            assert_eq!(koch.payload, "F!!F!!F");
+        }
+
+        #[test]
+        fn follow_the_registry() {
+            let mut koch = fractal::koch(String::new());
+            let registry = koch.semantics_registry().clone();
+            registry.write().unwrap().register("forward", |payload: &mut String| payload.push('G'));
+            koch.apply_semantics(0);
+            assert_eq!(koch.payload, "G--G--G");
+        }
     }
 }
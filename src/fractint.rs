@@ -0,0 +1,490 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::tryout::{self, Letter, LindenmayerPayload, LindenmayerSystem};
+
+// Importer for Fractint .l files. Such a file contains any number of named
+// blocks, everything after a ';' is a comment:
+//
+//     Koch1 {          ; Koch snowflake
+//       Angle 6        ; 360 / 6 = 60 degrees per turn
+//       Axiom F--F--F
+//       F=F+F--F+F
+//     }
+//
+// Letters are case insensitive. F and D draw a line, G and M move without
+// drawing, + and - turn, | turns around, ! swaps + and -, [ and ] save and
+// restore the turtle, @ scales the step length (@I inverts the factor and
+// @Q takes its square root, as in @IQ3), \ and / turn by the given degrees.
+// Colour commands (C, < and > followed by a number) are skipped.
+
+//***************************************************************************
+//
+// FractintLetter
+//
+//***************************************************************************
+
+// Parameters are stored as the bits of their f32 value, so the letters
+// stay hashable and can be used as keys of the production rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FractintLetter {
+    Draw(char),
+    Move(char),
+    Symbol(char),
+    TurnLeft,
+    TurnRight,
+    TurnAround,
+    Reverse,
+    Push,
+    Pop,
+    Scale(u32),
+    TurnBy(u32),
+}
+
+impl Letter for FractintLetter {
+    fn forward() -> Self {
+        Self::Draw('F')
+    }
+    fn interpret(&self, payload: &mut LindenmayerPayload) {
+        match self {
+            Self::Scale(factor) => payload.scale_step_length(f32::from_bits(*factor)),
+            Self::TurnBy(degrees) => payload.increase_current_angle(f32::from_bits(*degrees).to_radians()),
+            _ => (),
+        }
+    }
+}
+
+impl FractintLetter {
+    fn action(&self) -> Option<fn(payload: &mut LindenmayerPayload)> {
+        match self {
+            Self::Draw(_) => Some(tryout::forward),
+            Self::Move(_) => Some(tryout::move_forward),
+            Self::TurnLeft => Some(tryout::turn_left),
+            Self::TurnRight => Some(tryout::turn_right),
+            Self::TurnAround => Some(tryout::turn_around),
+            Self::Reverse => Some(tryout::reverse_turning_direction),
+            Self::Push => Some(tryout::push),
+            Self::Pop => Some(tryout::pop),
+            Self::Symbol(_) | Self::Scale(_) | Self::TurnBy(_) => None,
+        }
+    }
+}
+
+impl Display for FractintLetter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Draw(letter) | Self::Move(letter) | Self::Symbol(letter) => write!(f, "{}", letter),
+            Self::TurnLeft => write!(f, "+"),
+            Self::TurnRight => write!(f, "-"),
+            Self::TurnAround => write!(f, "|"),
+            Self::Reverse => write!(f, "!"),
+            Self::Push => write!(f, "["),
+            Self::Pop => write!(f, "]"),
+            Self::Scale(factor) => write!(f, "@{}", f32::from_bits(*factor)),
+            Self::TurnBy(degrees) => write!(f, "\\{}", f32::from_bits(*degrees)),
+        }
+    }
+}
+
+//***************************************************************************
+//
+// FractintError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FractintErrorKind {
+    ExpectedBlock,
+    UnterminatedBlock(String),
+    MissingAxiom(String),
+    InvalidAngle(String),
+    InvalidNumber(String),
+    InvalidStatement(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FractintError {
+    line: usize,
+    kind: FractintErrorKind,
+}
+
+impl FractintError {
+    fn new(line: usize, kind: FractintErrorKind) -> Self {
+        FractintError { line, kind }
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn kind(&self) -> &FractintErrorKind {
+        &self.kind
+    }
+}
+
+impl Display for FractintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            FractintErrorKind::ExpectedBlock => write!(f, "expected 'name {{'"),
+            FractintErrorKind::UnterminatedBlock(name) => write!(f, "block '{}' is missing its closing '}}'", name),
+            FractintErrorKind::MissingAxiom(name) => write!(f, "block '{}' has no axiom", name),
+            FractintErrorKind::InvalidAngle(angle) => write!(f, "'{}' is not a valid angle", angle),
+            FractintErrorKind::InvalidNumber(number) => write!(f, "'{}' is not a valid number", number),
+            FractintErrorKind::InvalidStatement(statement) => write!(f, "'{}' is neither an angle, an axiom nor a rule", statement),
+        }
+    }
+}
+
+impl std::error::Error for FractintError {}
+
+//***************************************************************************
+//
+// FractintSystem
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FractintSystem {
+    name: String,
+    angle_divisions: u32,
+    axiom: Vec<FractintLetter>,
+    rules: Vec<(FractintLetter, Vec<FractintLetter>)>,
+}
+
+impl FractintSystem {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The angle of a single turn in degrees.
+    pub fn angle(&self) -> f32 {
+        360.0 / self.angle_divisions as f32
+    }
+
+    pub fn axiom(&self) -> &[FractintLetter] {
+        &self.axiom
+    }
+
+    pub fn rules(&self) -> &[(FractintLetter, Vec<FractintLetter>)] {
+        &self.rules
+    }
+
+    pub fn to_lindenmayer_system(&self) -> LindenmayerSystem<FractintLetter> {
+        let production_rules: Vec<(FractintLetter, Option<Vec<FractintLetter>>)> = self.rules.iter()
+            .map(|(letter, successor)| (*letter, Some(successor.clone())))
+            .collect();
+
+        let mut actions = Vec::new();
+        let letters = self.axiom.iter()
+            .chain(self.rules.iter().flat_map(|(letter, successor)| std::iter::once(letter).chain(successor)));
+        for letter in letters {
+            if !actions.iter().any(|(known, _)| known == letter) {
+                actions.push((*letter, letter.action()));
+            }
+        }
+
+        let mut system = LindenmayerSystem::new(
+            &self.axiom,
+            self.angle().to_radians(),
+            &production_rules,
+            &actions,
+        );
+        system.change_name(&self.name);
+        system
+    }
+}
+
+//***************************************************************************
+//
+// FractintLibrary
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FractintLibrary {
+    systems: Vec<FractintSystem>,
+}
+
+struct Block {
+    name: String,
+    first_line: usize,
+    angle_divisions: Option<u32>,
+    axiom: Option<Vec<FractintLetter>>,
+    rules: Vec<(FractintLetter, Vec<FractintLetter>)>,
+}
+
+impl FractintLibrary {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let input = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&input)?)
+    }
+
+    pub fn parse(input: &str) -> Result<Self, FractintError> {
+        let mut systems = Vec::new();
+        let mut block: Option<Block> = None;
+
+        for (index, line) in input.lines().enumerate() {
+            let line_number = index + 1;
+            let mut line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }.trim();
+
+            if block.is_none() {
+                if line.is_empty() {
+                    continue;
+                }
+                let (name, rest) = line.split_once('{')
+                    .ok_or_else(|| FractintError::new(line_number, FractintErrorKind::ExpectedBlock))?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(FractintError::new(line_number, FractintErrorKind::ExpectedBlock));
+                }
+                block = Some(Block {
+                    name: name.to_owned(),
+                    first_line: line_number,
+                    angle_divisions: None,
+                    axiom: None,
+                    rules: Vec::new(),
+                });
+                line = rest.trim();
+            }
+
+            let (statement, closed) = match line.split_once('}') {
+                Some((statement, _)) => (statement.trim(), true),
+                None => (line, false),
+            };
+            if let Some(current) = block.as_mut() {
+                if !statement.is_empty() {
+                    parse_statement(current, statement, line_number)?;
+                }
+            }
+            if closed {
+                if let Some(finished) = block.take() {
+                    let axiom = finished.axiom
+                        .ok_or_else(|| FractintError::new(finished.first_line, FractintErrorKind::MissingAxiom(finished.name.clone())))?;
+                    systems.push(FractintSystem {
+                        name: finished.name,
+                        // Fractint falls back to quarter turns if no angle is given.
+                        angle_divisions: finished.angle_divisions.unwrap_or(4),
+                        axiom,
+                        rules: finished.rules,
+                    });
+                }
+            }
+        }
+
+        if let Some(unfinished) = block {
+            return Err(FractintError::new(unfinished.first_line, FractintErrorKind::UnterminatedBlock(unfinished.name)));
+        }
+
+        Ok(FractintLibrary { systems })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.systems.iter().map(|system| system.name()).collect()
+    }
+
+    // Names are compared case insensitively, like Fractint does.
+    pub fn get(&self, name: &str) -> Option<&FractintSystem> {
+        self.systems.iter().find(|system| system.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn systems(&self) -> &[FractintSystem] {
+        &self.systems
+    }
+}
+
+fn parse_statement(block: &mut Block, statement: &str, line_number: usize) -> Result<(), FractintError> {
+    let keyword_end = statement.find(char::is_whitespace).unwrap_or(statement.len());
+    let (keyword, argument) = statement.split_at(keyword_end);
+    let argument = argument.trim();
+
+    if keyword.eq_ignore_ascii_case("angle") {
+        let divisions = argument.parse::<u32>()
+            .ok()
+            .filter(|divisions| *divisions > 0)
+            .ok_or_else(|| FractintError::new(line_number, FractintErrorKind::InvalidAngle(argument.to_owned())))?;
+        block.angle_divisions = Some(divisions);
+    } else if keyword.eq_ignore_ascii_case("axiom") {
+        block.axiom = Some(parse_letters(argument, line_number)?);
+    } else if let Some((predecessor, successor)) = statement.split_once('=') {
+        let mut predecessor = parse_letters(predecessor.trim(), line_number)?;
+        if predecessor.len() != 1 {
+            return Err(FractintError::new(line_number, FractintErrorKind::InvalidStatement(statement.to_owned())));
+        }
+        block.rules.push((predecessor.remove(0), parse_letters(successor, line_number)?));
+    } else {
+        return Err(FractintError::new(line_number, FractintErrorKind::InvalidStatement(statement.to_owned())));
+    }
+    Ok(())
+}
+
+fn parse_letters(input: &str, line_number: usize) -> Result<Vec<FractintLetter>, FractintError> {
+    let letters: Vec<char> = input.chars()
+        .filter(|letter| !letter.is_whitespace())
+        .map(|letter| letter.to_ascii_uppercase())
+        .collect();
+    let mut word = Vec::with_capacity(letters.len());
+    let mut index = 0;
+
+    while index < letters.len() {
+        let letter = letters[index];
+        index += 1;
+        match letter {
+            'F' | 'D' => word.push(FractintLetter::Draw(letter)),
+            'G' | 'M' => word.push(FractintLetter::Move(letter)),
+            '+' => word.push(FractintLetter::TurnLeft),
+            '-' => word.push(FractintLetter::TurnRight),
+            '|' => word.push(FractintLetter::TurnAround),
+            '!' => word.push(FractintLetter::Reverse),
+            '[' => word.push(FractintLetter::Push),
+            ']' => word.push(FractintLetter::Pop),
+            '@' => {
+                let mut inverse = false;
+                let mut root = false;
+                while index < letters.len() && matches!(letters[index], 'I' | 'Q') {
+                    inverse |= letters[index] == 'I';
+                    root |= letters[index] == 'Q';
+                    index += 1;
+                }
+                let mut factor = parse_number(&letters, &mut index, line_number)?;
+                if root {
+                    factor = factor.sqrt();
+                }
+                if inverse {
+                    factor = 1.0 / factor;
+                }
+                word.push(FractintLetter::Scale(factor.to_bits()));
+            }
+            '\\' => word.push(FractintLetter::TurnBy(parse_number(&letters, &mut index, line_number)?.to_bits())),
+            '/' => word.push(FractintLetter::TurnBy((-parse_number(&letters, &mut index, line_number)?).to_bits())),
+            'C' | '<' | '>' => {
+                parse_number(&letters, &mut index, line_number)?;
+            }
+            _ => word.push(FractintLetter::Symbol(letter)),
+        }
+    }
+
+    Ok(word)
+}
+
+fn parse_number(letters: &[char], index: &mut usize, line_number: usize) -> Result<f32, FractintError> {
+    let start = *index;
+    while *index < letters.len() && (letters[*index].is_ascii_digit() || letters[*index] == '.') {
+        *index += 1;
+    }
+    let number: String = letters[start..*index].iter().collect();
+    number.parse()
+        .map_err(|_| FractintError::new(line_number, FractintErrorKind::InvalidNumber(number)))
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod parse {
+        use crate::fractint::{FractintErrorKind, FractintLetter, FractintLibrary};
+
+        const LIBRARY: &str = "\
+; A few classics
+Koch1 {          ; Koch snowflake
+  Angle 6
+  Axiom F--F--F
+  F=F+F--F+F
+  }
+
+Dragon { Angle 8
+  Axiom FX
+  x=-FX++FY-
+  y=+FX--FY+
+}
+
+Scaled {
+  angle 4
+  axiom F@IQ2|!F
+  }
+";
+
+        #[test]
+        fn list_every_fractal() {
+            let library = FractintLibrary::parse(LIBRARY).unwrap();
+            assert_eq!(library.names(), vec!["Koch1", "Dragon", "Scaled"]);
+        }
+
+        #[test]
+        fn koch() {
+            let library = FractintLibrary::parse(LIBRARY).unwrap();
+            let koch = library.get("koch1").unwrap();
+            assert_eq!(koch.angle(), 60.0);
+            assert_eq!(koch.axiom().len(), 7);
+            assert_eq!(koch.rules()[0].0, FractintLetter::Draw('F'));
+            assert_eq!(koch.rules()[0].1.len(), 8);
+        }
+
+        #[test]
+        fn letters_are_case_insensitive() {
+            let library = FractintLibrary::parse(LIBRARY).unwrap();
+            let dragon = library.get("Dragon").unwrap();
+            assert_eq!(dragon.rules()[0].0, FractintLetter::Symbol('X'));
+            assert_eq!(dragon.axiom(), &[FractintLetter::Draw('F'), FractintLetter::Symbol('X')][..]);
+        }
+
+        #[test]
+        fn scale_turn_around_and_reverse() {
+            let library = FractintLibrary::parse(LIBRARY).unwrap();
+            let scaled = library.get("Scaled").unwrap();
+            assert_eq!(
+                scaled.axiom(),
+                &[
+                    FractintLetter::Draw('F'),
+                    FractintLetter::Scale((1.0 / 2.0f32.sqrt()).to_bits()),
+                    FractintLetter::TurnAround,
+                    FractintLetter::Reverse,
+                    FractintLetter::Draw('F'),
+                ][..]
+            );
+        }
+
+        #[test]
+        fn report_errors_with_line() {
+            let error = FractintLibrary::parse("Broken {\n  Angle 6\n  F=FF\n").unwrap_err();
+            assert_eq!(error.kind(), &FractintErrorKind::UnterminatedBlock(String::from("Broken")));
+            assert_eq!(error.line(), 1);
+
+            let error = FractintLibrary::parse("NoAxiom {\n  Angle 6\n}\n").unwrap_err();
+            assert_eq!(error.kind(), &FractintErrorKind::MissingAxiom(String::from("NoAxiom")));
+
+            let error = FractintLibrary::parse("Bad {\n  Angle six\n}\n").unwrap_err();
+            assert_eq!(error.line(), 2);
+            assert_eq!(format!("{}", error), "line 2: 'six' is not a valid angle");
+        }
+    }
+
+    mod convert {
+        use crate::fractint::FractintLibrary;
+
+        #[test]
+        fn to_lindenmayer_system() {
+            let library = FractintLibrary::parse("Koch1 {\n Angle 6\n Axiom F--F--F\n F=F+F--F+F\n}\n").unwrap();
+            let mut koch = library.get("Koch1").unwrap().to_lindenmayer_system();
+            assert_eq!(koch.name(), "Koch1");
+            koch.update_vertex_stack(2);
+            // Every F draws one line, the closed curve ends at its starting point.
+            let vertices = koch.get_vertex_stack_at(2).unwrap();
+            assert_eq!(vertices.len(), 3 * 16 + 1);
+            let (first, last) = (vertices[0].unwrap(), vertices[vertices.len() - 1].unwrap());
+            assert!((first - last).norm() < 1e-2);
+        }
+
+        #[test]
+        fn scale_shortens_steps() {
+            let library = FractintLibrary::parse("Steps {\n Angle 4\n Axiom F@.5F\n}\n").unwrap();
+            let system = library.get("Steps").unwrap().to_lindenmayer_system();
+            let vertices = system.get_vertex_stack_at(0).unwrap();
+            let first = (vertices[1].unwrap() - vertices[0].unwrap()).norm();
+            let second = (vertices[2].unwrap() - vertices[1].unwrap()).norm();
+            assert!((first - 2.0 * second).abs() < 1e-3);
+        }
+    }
+}
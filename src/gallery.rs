@@ -0,0 +1,322 @@
+use crate::coordinates::MathPosition;
+use crate::limits::LimitError;
+use crate::tryout::{Letter, LindenmayerLetter, LindenmayerSystem};
+use crate::worker::{DerivationWorker, WorkerMessage};
+
+// The fractals browsed in the viewer. Every fractal keeps its own vertex
+// stack, which is only extended when a deeper iteration is actually shown,
+// so switching between fractals or going back up never recomputes anything.
+// Missing iterations are computed by a DerivationWorker; until they arrive,
+// the deepest finished iteration below the requested depth is shown.
+// Depths beyond the limits of a fractal are refused before anything is
+// computed, the reason is kept until the depth or fractal changes.
+
+pub struct Gallery<L: Letter> {
+    fractals: Vec<(LindenmayerSystem<L>, usize)>,
+    current_fractal: usize,
+    current_depth: usize,
+    // The worker and the index of the fractal it computes.
+    worker: Option<(usize, DerivationWorker<L>)>,
+    limit_error: Option<LimitError>,
+}
+
+impl<L: Letter + Send + Sync + 'static> Gallery<L> {
+    pub fn new() -> Self {
+        Gallery {
+            fractals: vec![],
+            current_fractal: 0,
+            current_depth: 0,
+            worker: None,
+            limit_error: None,
+        }
+    }
+
+    // The maximal depth keeps the viewer from asking for iterations
+    // that would not fit into memory.
+    pub fn with_fractal(mut self, fractal: LindenmayerSystem<L>, max_depth: usize) -> Self {
+        self.fractals.push((fractal, max_depth));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.fractals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fractals.is_empty()
+    }
+
+    pub fn current_fractal(&self) -> Option<&LindenmayerSystem<L>> {
+        self.fractals.get(self.current_fractal).map(|(fractal, _)| fractal)
+    }
+
+    pub fn current_depth(&self) -> usize {
+        self.current_depth
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.fractals.get(self.current_fractal).map_or(0, |(_, max_depth)| *max_depth)
+    }
+
+    pub fn next_fractal(&mut self) {
+        if !self.fractals.is_empty() {
+            self.current_fractal = (self.current_fractal + 1) % self.fractals.len();
+            self.current_depth = self.current_depth.min(self.max_depth());
+            self.limit_error = None;
+        }
+    }
+
+    pub fn previous_fractal(&mut self) {
+        if !self.fractals.is_empty() {
+            self.current_fractal = (self.current_fractal + self.fractals.len() - 1) % self.fractals.len();
+            self.current_depth = self.current_depth.min(self.max_depth());
+            self.limit_error = None;
+        }
+    }
+
+    pub fn increase_depth(&mut self) {
+        if self.current_depth >= self.max_depth() {
+            return;
+        }
+        let checked = self.current_fractal().map(|fractal| fractal.check_limits(self.current_depth + 1));
+        match checked {
+            Some(Err(error)) => self.limit_error = Some(error),
+            _ => {
+                self.current_depth += 1;
+                self.limit_error = None;
+            }
+        }
+    }
+
+    pub fn decrease_depth(&mut self) {
+        self.current_depth = self.current_depth.saturating_sub(1);
+        self.limit_error = None;
+    }
+
+    // Why the last increase of the depth was refused.
+    pub fn limit_error(&self) -> Option<&LimitError> {
+        self.limit_error.as_ref()
+    }
+
+    // The depth actually shown, which lags behind the requested depth
+    // while the worker is still busy.
+    pub fn displayed_depth(&self) -> usize {
+        self.current_fractal()
+            .map_or(0, |fractal| fractal.computed_depth().min(self.current_depth))
+    }
+
+    // The depth the worker is computing right now and the depth it works
+    // towards, if it is busy with the current fractal.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        match &self.worker {
+            Some((index, worker)) if *index == self.current_fractal => Some((worker.current_depth(), worker.target_depth())),
+            _ => None,
+        }
+    }
+
+    pub fn is_computing(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    // Collects the iterations finished by the worker and starts, extends or
+    // cancels workers to match the requested fractal and depth. Going back
+    // up lets the worker finish, so its iterations are cached. Never blocks.
+    pub fn update(&mut self) {
+        if let Some((index, worker)) = &mut self.worker {
+            let (fractal, _) = &mut self.fractals[*index];
+            for message in worker.poll() {
+                match message {
+                    WorkerMessage::Iteration { depth, word, vertices } if depth == fractal.computed_depth() + 1 => {
+                        fractal.append_iteration(word, vertices);
+                    }
+                    // Only happens if the limits changed after the depth was increased.
+                    WorkerMessage::LimitExceeded(error) if *index == self.current_fractal => {
+                        self.current_depth = self.current_depth.min(error.depth() - 1);
+                        self.limit_error = Some(error);
+                    }
+                    _ => (),
+                }
+            }
+            let outdated = *index != self.current_fractal;
+            if outdated {
+                worker.cancel();
+            } else if worker.target_depth() < self.current_depth {
+                if let Err(error) = worker.raise_target(fractal, self.current_depth) {
+                    self.current_depth = error.depth() - 1;
+                    self.limit_error = Some(error);
+                }
+            }
+            if outdated || worker.is_finished() {
+                self.worker = None;
+            }
+        }
+
+        if self.worker.is_none() {
+            if let Some((fractal, _)) = self.fractals.get_mut(self.current_fractal) {
+                if fractal.computed_depth() < self.current_depth {
+                    fractal.refresh_actions();
+                    let worker = DerivationWorker::spawn(fractal, self.current_depth);
+                    self.worker = Some((self.current_fractal, worker));
+                }
+            }
+        }
+    }
+
+    pub fn vertices(&self) -> Option<&Vec<Option<MathPosition>>> {
+        let (fractal, _) = self.fractals.get(self.current_fractal)?;
+        fractal.get_vertex_stack_at(self.displayed_depth())
+    }
+}
+
+impl<L: Letter + Send + Sync + 'static> Default for Gallery<L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gallery<LindenmayerLetter> {
+    pub fn built_in() -> Self {
+        Gallery::new()
+            .with_fractal(LindenmayerSystem::koch(), 7)
+            .with_fractal(LindenmayerSystem::levy(), 16)
+            .with_fractal(LindenmayerSystem::dragon_curve(), 16)
+            .with_fractal(LindenmayerSystem::first_plant(), 7)
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod gallery {
+        use crate::gallery::Gallery;
+        use crate::limits::{DerivationLimits, LimitError};
+        use crate::tryout::{LindenmayerLetter, LindenmayerSystem};
+
+        fn wait(gallery: &mut Gallery<LindenmayerLetter>) {
+            gallery.update();
+            while gallery.is_computing() {
+                std::thread::yield_now();
+                gallery.update();
+            }
+        }
+
+        #[test]
+        fn cycle_fractals() {
+            let mut gallery = Gallery::built_in();
+            assert_eq!(gallery.len(), 4);
+            assert_eq!(gallery.current_fractal().unwrap().name(), "koch");
+            gallery.next_fractal();
+            assert_eq!(gallery.current_fractal().unwrap().name(), "levy");
+            gallery.previous_fractal();
+            gallery.previous_fractal();
+            assert_eq!(gallery.current_fractal().unwrap().name(), "first_plant");
+        }
+
+        #[test]
+        fn compute_lazily() {
+            let mut gallery = Gallery::built_in();
+            gallery.increase_depth();
+            gallery.increase_depth();
+            assert_eq!(gallery.current_fractal().unwrap().computed_depth(), 0);
+            // Until the worker is done, the finished depth is shown.
+            assert_eq!(gallery.displayed_depth(), 0);
+            assert_eq!(gallery.vertices().unwrap().len(), 3 + 1);
+
+            wait(&mut gallery);
+            assert_eq!(gallery.displayed_depth(), 2);
+            assert_eq!(gallery.vertices().unwrap().len(), 3 * 16 + 1);
+            assert_eq!(gallery.current_fractal().unwrap().computed_depth(), 2);
+
+            // Going back up only reads from the cache.
+            gallery.decrease_depth();
+            gallery.update();
+            assert!(!gallery.is_computing());
+            assert_eq!(gallery.vertices().unwrap().len(), 3 * 4 + 1);
+            assert_eq!(gallery.current_fractal().unwrap().computed_depth(), 2);
+
+            gallery.next_fractal();
+            assert_eq!(gallery.current_fractal().unwrap().computed_depth(), 0);
+        }
+
+        #[test]
+        fn clamp_depth() {
+            let mut gallery = Gallery::built_in();
+            gallery.decrease_depth();
+            assert_eq!(gallery.current_depth(), 0);
+            for _ in 0..10 {
+                gallery.increase_depth();
+            }
+            assert_eq!(gallery.current_depth(), 7);
+
+            gallery.next_fractal();
+            for _ in 0..20 {
+                gallery.increase_depth();
+            }
+            assert_eq!(gallery.current_depth(), 16);
+            gallery.next_fractal();
+            assert_eq!(gallery.current_depth(), 16);
+            gallery.next_fractal();
+            assert_eq!(gallery.current_depth(), 7);
+        }
+
+        #[test]
+        fn refuse_depths_beyond_the_limits() {
+            let mut dragon = LindenmayerSystem::dragon_curve();
+            dragon.with_limits(DerivationLimits::unlimited().with_max_word_length(100));
+            let mut gallery = Gallery::new().with_fractal(dragon, 16);
+            for _ in 0..10 {
+                gallery.increase_depth();
+            }
+            // Depth n of the dragon curve has 5 * 2^n - 4 letters.
+            assert_eq!(gallery.current_depth(), 4);
+            assert!(matches!(gallery.limit_error(), Some(LimitError::WordLength { depth: 5, .. })));
+            wait(&mut gallery);
+            assert_eq!(gallery.displayed_depth(), 4);
+
+            gallery.decrease_depth();
+            assert_eq!(gallery.limit_error(), None);
+        }
+
+        #[test]
+        fn going_up_keeps_the_worker() {
+            let mut gallery = Gallery::new().with_fractal(LindenmayerSystem::levy(), 20);
+            for _ in 0..16 {
+                gallery.increase_depth();
+            }
+            gallery.update();
+            gallery.decrease_depth();
+            gallery.update();
+            assert_eq!(gallery.progress().map(|(_, target)| target), Some(16));
+
+            // Going deeper again extends the same worker.
+            gallery.increase_depth();
+            gallery.increase_depth();
+            gallery.update();
+            assert_eq!(gallery.progress().map(|(_, target)| target), Some(17));
+            wait(&mut gallery);
+            assert_eq!(gallery.displayed_depth(), 17);
+        }
+
+        #[test]
+        fn switching_cancels_the_worker() {
+            let mut gallery = Gallery::built_in();
+            gallery.next_fractal();
+            for _ in 0..16 {
+                gallery.increase_depth();
+            }
+            gallery.update();
+            assert_eq!(gallery.progress().map(|(_, target)| target), Some(16));
+
+            gallery.next_fractal();
+            gallery.decrease_depth();
+            gallery.update();
+            // The worker for levy is gone, a new one works on the dragon curve.
+            assert_eq!(gallery.progress().map(|(_, target)| target), Some(15));
+            wait(&mut gallery);
+            assert_eq!(gallery.displayed_depth(), 15);
+            assert_eq!(gallery.current_fractal().unwrap().name(), "dragon_curve");
+        }
+    }
+}
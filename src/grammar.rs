@@ -1,5 +1,11 @@
 use std::fmt::Display;
+use std::hash::Hash;
 
+use rand::Rng;
+use rand::rngs::ThreadRng;
+use rand::distributions::{Distribution, WeightedIndex};
+
+use crate::matcher::SubwordMatcher;
 use crate::word::Word;
 
 //***************************************************************************
@@ -8,9 +14,15 @@ use crate::word::Word;
 //
 //***************************************************************************
 
+// A rule without a weight is deterministic. Rules that share their lhs and
+// carry a weight are alternatives, one of which gets picked per derivation step.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProductionRule<T> {
     lhs: Word<T>,
     rhs: Word<T>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    weight: Option<f32>,
 }
 
 //***************************************************************************
@@ -24,6 +36,13 @@ impl<T> ProductionRule<T> {
         Self {
             lhs,
             rhs,
+            weight: None,
+        }
+    }
+    pub fn with_weight(self, weight: f32) -> Self {
+        Self {
+            weight: Some(weight),
+            ..self
         }
     }
     pub fn lhs(&self) -> &Word<T> {
@@ -32,6 +51,25 @@ impl<T> ProductionRule<T> {
     pub fn rhs(&self) -> &Word<T> {
         &self.rhs
     }
+    pub fn weight(&self) -> f32 {
+        self.weight.unwrap_or(1.0)
+    }
+    pub fn is_stochastic(&self) -> bool {
+        self.weight.is_some()
+    }
+}
+
+impl<T> Display for ProductionRule<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.lhs, self.rhs)?;
+        if let Some(weight) = self.weight {
+            write!(f, " ({})", weight)?;
+        }
+        Ok(())
+    }
 }
 
 impl<T> From<(&[T], &[T])> for ProductionRule<T>
@@ -42,6 +80,7 @@ where
         Self {
             lhs: Word::from(rule.0),
             rhs: Word::from(rule.1),
+            weight: None,
         }
     }
 }
@@ -54,33 +93,198 @@ where
         Self {
             lhs: Word::from(rule.0),
             rhs: Word::from(rule.1),
+            weight: None,
         }
     }
 }
 
-// TODO: Maybe add data to the errors later, but then i need restrictions
-// on the Data (the generic Type T) with regards to Debug and Display
-#[derive(Debug, Clone)]
-pub enum GrammarError {
-    ProductionRulesContainUnknownTerminal,
-    ProductionRulesContainUnknownNonTerminal,
+//***************************************************************************
+//
+// GrammarError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleSide {
+    Lhs,
+    Rhs,
 }
 
-impl Display for GrammarError {
+impl Display for RuleSide {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            GrammarError::ProductionRulesContainUnknownTerminal => {
-                write!(f, "production rules contain unknown terminals")
+            RuleSide::Lhs => write!(f, "lhs"),
+            RuleSide::Rhs => write!(f, "rhs"),
+        }
+    }
+}
+
+// Rules are counted from 0 like their index in the production rules,
+// the messages count from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrammarError<T> {
+    // Letters on the lhs have to be non terminals, letters on the rhs
+    // terminals or non terminals.
+    UnknownLetters { rule: usize, side: RuleSide, letters: Vec<T> },
+    EmptyPredecessor { rule: usize },
+    // The same rule once more.
+    DuplicateRule { rule: usize, previous_rule: usize },
+    // Two deterministic rules that rewrite the same lhs differently.
+    ConflictingPredecessor { rule: usize, previous_rule: usize, lhs: Word<T> },
+    MissingAxiom,
+    UnknownAxiomLetters { letters: Vec<T> },
+    // A derived word, see DerivationSnapshot.
+    UnknownWordLetters { letters: Vec<T> },
+}
+
+impl<T> GrammarError<T> {
+    // The index of the rule at fault, if it is about a rule.
+    pub fn rule(&self) -> Option<usize> {
+        match self {
+            GrammarError::UnknownLetters { rule, .. } => Some(*rule),
+            GrammarError::EmptyPredecessor { rule } => Some(*rule),
+            GrammarError::DuplicateRule { rule, .. } => Some(*rule),
+            GrammarError::ConflictingPredecessor { rule, .. } => Some(*rule),
+            GrammarError::MissingAxiom
+            | GrammarError::UnknownAxiomLetters { .. }
+            | GrammarError::UnknownWordLetters { .. } => None,
+        }
+    }
+}
+
+impl<T> Display for GrammarError<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn letters<T: Display>(f: &mut std::fmt::Formatter<'_>, letters: &[T]) -> std::fmt::Result {
+            for (index, letter) in letters.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "'{}'", letter)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            GrammarError::UnknownLetters { rule, side: RuleSide::Lhs, letters: unknown } => {
+                write!(f, "rule {}: lhs contains letters that are no non terminals: ", rule + 1)?;
+                letters(f, unknown)
+            }
+            GrammarError::UnknownLetters { rule, side: RuleSide::Rhs, letters: unknown } => {
+                write!(f, "rule {}: rhs contains unknown letters: ", rule + 1)?;
+                letters(f, unknown)
+            }
+            GrammarError::EmptyPredecessor { rule } => write!(f, "rule {}: lhs is empty", rule + 1),
+            GrammarError::DuplicateRule { rule, previous_rule } => {
+                write!(f, "rule {}: same as rule {}", rule + 1, previous_rule + 1)
             }
-            GrammarError::ProductionRulesContainUnknownNonTerminal => {
-                write!(f, "production rules contain unknown non terminals")
+            GrammarError::ConflictingPredecessor { rule, previous_rule, lhs } => {
+                write!(f, "rule {}: rule {} already rewrites '{}' deterministically", rule + 1, previous_rule + 1, lhs)
+            }
+            GrammarError::MissingAxiom => write!(f, "axiom is empty"),
+            GrammarError::UnknownAxiomLetters { letters: unknown } => {
+                write!(f, "axiom contains unknown letters: ")?;
+                letters(f, unknown)
+            }
+            GrammarError::UnknownWordLetters { letters: unknown } => {
+                write!(f, "word contains unknown letters: ")?;
+                letters(f, unknown)
             }
         }
     }
 }
 
-impl std::error::Error for GrammarError {}
+impl<T> std::error::Error for GrammarError<T> where T: std::fmt::Debug + Display {}
+
+// Validation does not stop at the first error, all of them are kept in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarErrors<T> {
+    errors: Vec<GrammarError<T>>,
+}
+
+impl<T> GrammarErrors<T> {
+    pub fn errors(&self) -> &[GrammarError<T>] {
+        &self.errors
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl<T> From<Vec<GrammarError<T>>> for GrammarErrors<T> {
+    fn from(errors: Vec<GrammarError<T>>) -> Self {
+        GrammarErrors { errors }
+    }
+}
+
+// One error per line.
+impl<T> Display for GrammarErrors<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> std::error::Error for GrammarErrors<T> where T: std::fmt::Debug + Display {}
+
+//***************************************************************************
+//
+// ConversionError
+//
+//***************************************************************************
+
+// Fractal takes every grammar, LindenmayerSystem only deterministic rules
+// of single letters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionError {
+    MultiLetterPredecessor { rule: usize, length: usize },
+    StochasticRule { rule: usize },
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::MultiLetterPredecessor { rule, length } => {
+                write!(f, "rule {}: lhs has {} letters, only single letters are supported", rule + 1, length)
+            }
+            ConversionError::StochasticRule { rule } => {
+                write!(f, "rule {}: weighted rules are not supported", rule + 1)
+            }
+        }
+    }
+}
 
+impl std::error::Error for ConversionError {}
+
+//***************************************************************************
+//
+// Grammar
+//
+//***************************************************************************
+
+// Loading a grammar validates it like with_production_rules and with_axiom
+// do, a grammar saved without an axiom is loaded without one.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(
+    try_from = "GrammarDefinition<T>",
+    bound(deserialize = "T: serde::Deserialize<'de> + Clone + Display"),
+))]
 pub struct Grammar<T>
 where
     T: PartialEq,
@@ -88,6 +292,37 @@ where
     terminals: Vec<T>,
     non_terminals: Vec<T>,
     production_rules: Vec<ProductionRule<T>>,
+    // Empty until one is set with with_axiom.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Word::is_empty"))]
+    axiom: Word<T>,
+}
+
+// The fields of a grammar as they are saved, before they are validated.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct GrammarDefinition<T> {
+    terminals: Vec<T>,
+    non_terminals: Vec<T>,
+    production_rules: Vec<ProductionRule<T>>,
+    #[serde(default = "Vec::new")]
+    axiom: Vec<T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> TryFrom<GrammarDefinition<T>> for Grammar<T>
+where
+    T: Clone + PartialEq,
+{
+    type Error = GrammarErrors<T>;
+
+    fn try_from(definition: GrammarDefinition<T>) -> Result<Self, Self::Error> {
+        let grammar = Grammar::new(definition.terminals, definition.non_terminals)
+            .with_production_rules(definition.production_rules)?;
+        if definition.axiom.is_empty() {
+            return Ok(grammar);
+        }
+        grammar.with_axiom(Word::new(definition.axiom))
+    }
 }
 
 impl<T> Grammar<T>
@@ -99,24 +334,726 @@ where
             terminals,
             non_terminals,
             production_rules: Vec::new(),
+            axiom: Word::new(Vec::new()),
         }
     }
-    pub fn validate_production_rules(&self, production_rules: &[ProductionRule<T>]) -> Result<(), GrammarError> {
+    pub fn validate_production_rules(&self, production_rules: &[ProductionRule<T>]) -> Result<(), GrammarErrors<T>>
+    where
+        T: Clone,
+    {
+        let mut errors = Vec::new();
         for (index, rule) in production_rules.iter().enumerate() {
-            // check that neither the rhs nor the lhs contain symbols that are not contained in
-            // terminals and nonterminals
-            // TODO: If i decide to add data to the errors, i need to rework this section.
-            if rule.lhs().contains_any_letter_besides(&self.terminals) || rule.rhs().contains_any_letter_besides(&self.terminals) {
-                return Err(GrammarError::ProductionRulesContainUnknownTerminal);
+            if rule.lhs().is_empty() {
+                errors.push(GrammarError::EmptyPredecessor { rule: index });
+            }
+            let unknown = unknown_letters(&rule.lhs()[..], |letter| self.non_terminals.contains(letter));
+            if !unknown.is_empty() {
+                errors.push(GrammarError::UnknownLetters { rule: index, side: RuleSide::Lhs, letters: unknown });
             }
-            if rule.lhs().contains_any_letter_besides(&self.non_terminals) || rule.rhs().contains_any_letter_besides(&self.non_terminals) {
-                return Err(GrammarError::ProductionRulesContainUnknownNonTerminal);
+            let unknown = unknown_letters(&rule.rhs()[..], |letter| self.is_known_letter(letter));
+            if !unknown.is_empty() {
+                errors.push(GrammarError::UnknownLetters { rule: index, side: RuleSide::Rhs, letters: unknown });
             }
+
+            let previous_rules = production_rules[..index].iter().enumerate()
+                .filter(|(_, previous)| previous.lhs() == rule.lhs() && !rule.lhs().is_empty());
+            for (previous_index, previous) in previous_rules {
+                if previous == rule {
+                    errors.push(GrammarError::DuplicateRule { rule: index, previous_rule: previous_index });
+                    break;
+                }
+                if !previous.is_stochastic() && !rule.is_stochastic() {
+                    errors.push(GrammarError::ConflictingPredecessor { rule: index, previous_rule: previous_index, lhs: rule.lhs().clone() });
+                    break;
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(GrammarErrors::from(errors))
+        }
+    }
+
+    pub fn validate_axiom(&self, axiom: &[T]) -> Result<(), GrammarErrors<T>>
+    where
+        T: Clone,
+    {
+        if axiom.is_empty() {
+            return Err(GrammarErrors::from(vec![GrammarError::MissingAxiom]));
+        }
+        let unknown = unknown_letters(axiom, |letter| self.is_known_letter(letter));
+        if !unknown.is_empty() {
+            return Err(GrammarErrors::from(vec![GrammarError::UnknownAxiomLetters { letters: unknown }]));
         }
         Ok(())
     }
 
-    pub fn with_production_rules(self, production_rules: Vec<ProductionRule<T>>) -> Result<Self, GrammarError> {
+    fn is_known_letter(&self, letter: &T) -> bool {
+        self.terminals.contains(letter) || self.non_terminals.contains(letter)
+    }
+
+    pub fn with_production_rules(self, production_rules: Vec<ProductionRule<T>>) -> Result<Self, GrammarErrors<T>>
+    where
+        T: Clone,
+    {
         self.validate_production_rules(&production_rules).map(|()| Self { production_rules, ..self})
     }
+
+    pub fn with_axiom(self, axiom: Word<T>) -> Result<Self, GrammarErrors<T>>
+    where
+        T: Clone,
+    {
+        self.validate_axiom(&axiom[..]).map(|()| Self { axiom, ..self })
+    }
+
+    // Letters that are not known yet become terminals.
+    pub fn add_terminals(&mut self, letters: &[T])
+    where
+        T: Clone,
+    {
+        for letter in letters {
+            if !self.is_known_letter(letter) {
+                self.terminals.push(letter.clone());
+            }
+        }
+    }
+
+    // Terminals among the letters become non terminals and letters that are
+    // not known yet are added, neither makes any rule or the axiom invalid.
+    pub fn add_non_terminals(&mut self, letters: &[T])
+    where
+        T: Clone,
+    {
+        for letter in letters {
+            self.terminals.retain(|terminal| terminal != letter);
+            if !self.non_terminals.contains(letter) {
+                self.non_terminals.push(letter.clone());
+            }
+        }
+    }
+
+    // Replaces every rule for the lhs by the given rules, which go to the end.
+    // The rules stay as they are if the result is not valid.
+    pub fn replace_rules(&mut self, lhs: &[T], rules: Vec<ProductionRule<T>>) -> Result<(), GrammarErrors<T>>
+    where
+        T: Clone,
+    {
+        let mut production_rules: Vec<ProductionRule<T>> = self.production_rules.iter()
+            .filter(|rule| &rule.lhs()[..] != lhs)
+            .cloned()
+            .collect();
+        production_rules.extend(rules);
+        self.validate_production_rules(&production_rules)?;
+        self.production_rules = production_rules;
+        Ok(())
+    }
+
+    pub fn change_axiom(&mut self, axiom: Word<T>) -> Result<(), GrammarErrors<T>>
+    where
+        T: Clone,
+    {
+        self.validate_axiom(&axiom[..])?;
+        self.axiom = axiom;
+        Ok(())
+    }
+
+    pub fn axiom(&self) -> &Word<T> {
+        &self.axiom
+    }
+
+    pub fn terminals(&self) -> &[T] {
+        &self.terminals
+    }
+
+    pub fn non_terminals(&self) -> &[T] {
+        &self.non_terminals
+    }
+
+    pub fn production_rules(&self) -> &[ProductionRule<T>] {
+        &self.production_rules
+    }
+
+    pub fn rules_for(&self, lhs: &Word<T>) -> Vec<&ProductionRule<T>> {
+        self.production_rules.iter().filter(|rule| rule.lhs() == lhs).collect()
+    }
+
+    // Validation allows a single deterministic rule per lhs, which shadows all
+    // weighted rules for it, no matter whether they come before or after it.
+    // Without one, one of the rules is picked according to their weights.
+    // Returns None if there is no such rule or all of them have weight zero.
+    pub fn choose_rule<R: Rng>(&self, lhs: &Word<T>, rng: &mut R) -> Option<&ProductionRule<T>> {
+        self.choose_rule_for(&lhs[..], rng)
+    }
+
+    fn choose_rule_for<R: Rng>(&self, lhs: &[T], rng: &mut R) -> Option<&ProductionRule<T>> {
+        let rules = || self.production_rules.iter().filter(move |rule| &rule.lhs()[..] == lhs);
+        if let Some(rule) = rules().find(|rule| !rule.is_stochastic()) {
+            return Some(rule);
+        }
+        let distribution = WeightedIndex::new(rules().map(|rule| rule.weight())).ok()?;
+        rules().nth(distribution.sample(rng))
+    }
+
+    // Every rule in order, or the first of them that is weighted or
+    // rewrites more than a single letter.
+    pub fn single_letter_rules(&self) -> Result<Vec<(&T, &Word<T>)>, ConversionError> {
+        let mut rules = Vec::new();
+        for (index, rule) in self.production_rules.iter().enumerate() {
+            if rule.is_stochastic() {
+                return Err(ConversionError::StochasticRule { rule: index });
+            }
+            if rule.lhs().len() != 1 {
+                return Err(ConversionError::MultiLetterPredecessor { rule: index, length: rule.lhs().len() });
+            }
+            rules.push((&rule.lhs()[0], rule.rhs()));
+        }
+        Ok(rules)
+    }
+}
+
+//***************************************************************************
+//
+// Grammar derivations
+//
+//***************************************************************************
+
+// A derivation step rewrites the word from left to right through a
+// SubwordMatcher over the lhs of the rules: the longest lhs at every position
+// is rewritten by the rule choose_rule picks for it and skipped. Letters where
+// no lhs starts stay the same, as does an lhs whose rules all have weight zero.
+impl<T> Grammar<T>
+where
+    T: Clone + Eq + Hash,
+{
+    pub fn matcher(&self) -> SubwordMatcher<T> {
+        SubwordMatcher::new(self.production_rules.iter().map(|rule| &rule.lhs()[..]))
+    }
+
+    pub fn step(&self, word: &Word<T>) -> Word<T> {
+        self.step_with_rng(word, &mut rand::thread_rng())
+    }
+
+    pub fn step_with_rng<R: Rng>(&self, word: &Word<T>, rng: &mut R) -> Word<T> {
+        Word::new(self.step_matched(&self.matcher(), &word[..], rng))
+    }
+
+    // Same as step_with_rng with the matcher of the rules built beforehand,
+    // so it can be reused for every step.
+    pub fn step_matched<R: Rng>(&self, matcher: &SubwordMatcher<T>, word: &[T], rng: &mut R) -> Vec<T> {
+        matcher.rewrite(word, |lhs, result| match self.choose_rule_for(lhs, rng) {
+            Some(rule) => result.extend_from_slice(&rule.rhs()[..]),
+            None => result.extend_from_slice(lhs),
+        })
+    }
+
+    // The axiom after the given number of steps.
+    pub fn derive(&self, depth: usize) -> Word<T> {
+        self.derive_with_rng(depth, &mut rand::thread_rng())
+    }
+
+    pub fn derive_with_rng<R: Rng>(&self, depth: usize, rng: &mut R) -> Word<T> {
+        let matcher = self.matcher();
+        let mut word = self.axiom.clone();
+        for _ in 0..depth {
+            word = Word::new(self.step_matched(&matcher, &word[..], rng));
+        }
+        word
+    }
+
+    pub fn derivations(&self) -> Derivations<'_, T, ThreadRng> {
+        self.derivations_with_rng(rand::thread_rng())
+    }
+
+    // The axiom, then every following step, endlessly.
+    pub fn derivations_with_rng<R: Rng>(&self, rng: R) -> Derivations<'_, T, R> {
+        Derivations {
+            grammar: self,
+            matcher: self.matcher(),
+            previous: None,
+            rng,
+        }
+    }
+
+    // The axiom after the given number of steps, kept with the grammar.
+    pub fn snapshot(&self, depth: usize) -> DerivationSnapshot<T> {
+        DerivationSnapshot {
+            grammar: self.clone(),
+            depth,
+            word: self.derive(depth),
+        }
+    }
+}
+
+//***************************************************************************
+//
+// Derivations
+//
+//***************************************************************************
+
+pub struct Derivations<'a, T, R>
+where
+    T: PartialEq,
+{
+    grammar: &'a Grammar<T>,
+    matcher: SubwordMatcher<T>,
+    previous: Option<Word<T>>,
+    rng: R,
+}
+
+impl<'a, T, R> Iterator for Derivations<'a, T, R>
+where
+    T: Clone + Eq + Hash,
+    R: Rng,
+{
+    type Item = Word<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let word = match &self.previous {
+            None => self.grammar.axiom().clone(),
+            Some(previous) => Word::new(self.grammar.step_matched(&self.matcher, &previous[..], &mut self.rng)),
+        };
+        self.previous = Some(word.clone());
+        Some(word)
+    }
+}
+
+//***************************************************************************
+//
+// DerivationSnapshot
+//
+//***************************************************************************
+
+// A derived word together with its grammar and the number of steps it took,
+// saved to continue the derivation later instead of starting over from the
+// axiom. Loading validates the grammar and the letters of the word.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(
+    try_from = "SnapshotDefinition<T>",
+    bound(deserialize = "T: serde::Deserialize<'de> + Clone + Display"),
+))]
+pub struct DerivationSnapshot<T>
+where
+    T: PartialEq,
+{
+    grammar: Grammar<T>,
+    depth: usize,
+    word: Word<T>,
+}
+
+// The fields of a snapshot as they are saved, before the word is validated.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(bound(deserialize = "T: serde::Deserialize<'de> + Clone + Display"))]
+struct SnapshotDefinition<T>
+where
+    T: PartialEq,
+{
+    grammar: Grammar<T>,
+    depth: usize,
+    word: Vec<T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> TryFrom<SnapshotDefinition<T>> for DerivationSnapshot<T>
+where
+    T: Clone + PartialEq,
+{
+    type Error = GrammarErrors<T>;
+
+    fn try_from(definition: SnapshotDefinition<T>) -> Result<Self, Self::Error> {
+        DerivationSnapshot::new(definition.grammar, definition.depth, Word::new(definition.word))
+    }
+}
+
+impl<T> DerivationSnapshot<T>
+where
+    T: Clone + PartialEq,
+{
+    // The word is not derived again, only its letters are checked.
+    pub fn new(grammar: Grammar<T>, depth: usize, word: Word<T>) -> Result<Self, GrammarErrors<T>> {
+        let unknown = unknown_letters(&word[..], |letter| grammar.is_known_letter(letter));
+        if !unknown.is_empty() {
+            return Err(GrammarErrors::from(vec![GrammarError::UnknownWordLetters { letters: unknown }]));
+        }
+        Ok(DerivationSnapshot { grammar, depth, word })
+    }
+
+    pub fn grammar(&self) -> &Grammar<T> {
+        &self.grammar
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn word(&self) -> &Word<T> {
+        &self.word
+    }
+
+    // Continues the derivation by the given number of steps.
+    pub fn advance_with_rng<R: Rng>(&mut self, steps: usize, rng: &mut R)
+    where
+        T: Eq + Hash,
+    {
+        let matcher = self.grammar.matcher();
+        for _ in 0..steps {
+            self.word = Word::new(self.grammar.step_matched(&matcher, &self.word[..], rng));
+        }
+        self.depth += steps;
+    }
+
+    pub fn advance(&mut self, steps: usize)
+    where
+        T: Eq + Hash,
+    {
+        self.advance_with_rng(steps, &mut rand::thread_rng());
+    }
+}
+
+// Every unknown letter once, in the order of the word.
+fn unknown_letters<T, F>(letters: &[T], is_known: F) -> Vec<T>
+where
+    T: Clone + PartialEq,
+    F: Fn(&T) -> bool,
+{
+    let mut unknown: Vec<T> = Vec::new();
+    for letter in letters {
+        if !is_known(letter) && !unknown.contains(letter) {
+            unknown.push(letter.clone());
+        }
+    }
+    unknown
+}
+
+#[cfg(test)]
+mod tests {
+
+    mod validate_production_rules {
+        use crate::grammar::{Grammar, GrammarError, ProductionRule, RuleSide};
+        use crate::word::Word;
+
+        #[test]
+        fn lhs_has_to_be_non_terminal() {
+            let grammar = Grammar::new(vec!['+'], vec!['F']);
+            let rules = vec![ProductionRule::new(Word::from("+"), Word::from("F"))];
+            assert_eq!(
+                grammar.validate_production_rules(&rules).unwrap_err().errors(),
+                &[GrammarError::UnknownLetters { rule: 0, side: RuleSide::Lhs, letters: vec!['+'] }],
+            );
+            let rules = vec![ProductionRule::new(Word::from("F"), Word::from("F+F"))];
+            assert!(grammar.validate_production_rules(&rules).is_ok());
+        }
+
+        #[test]
+        fn collect_every_error() {
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F', 'X']);
+            let rules = vec![
+                ProductionRule::new(Word::from("F"), Word::from("F+G+H+G")),
+                ProductionRule::new(Word::from(""), Word::from("F")),
+                ProductionRule::new(Word::from("X"), Word::from("F-X")),
+                ProductionRule::new(Word::from("X"), Word::from("F-X")),
+                ProductionRule::new(Word::from("F"), Word::from("FF")),
+                ProductionRule::new(Word::from("F"), Word::from("F-F")).with_weight(1.0),
+            ];
+            let errors = grammar.validate_production_rules(&rules).unwrap_err();
+            assert_eq!(
+                errors.errors(),
+                &[
+                    GrammarError::UnknownLetters { rule: 0, side: RuleSide::Rhs, letters: vec!['G', 'H'] },
+                    GrammarError::EmptyPredecessor { rule: 1 },
+                    GrammarError::DuplicateRule { rule: 3, previous_rule: 2 },
+                    GrammarError::ConflictingPredecessor { rule: 4, previous_rule: 0, lhs: Word::from("F") },
+                ],
+            );
+            assert_eq!(
+                errors.to_string(),
+                "rule 1: rhs contains unknown letters: 'G', 'H'\n\
+                 rule 2: lhs is empty\n\
+                 rule 4: same as rule 3\n\
+                 rule 5: rule 1 already rewrites 'F' deterministically",
+            );
+        }
+
+        #[test]
+        fn axiom() {
+            let grammar = Grammar::new(vec!['+'], vec!['F']);
+            assert_eq!(grammar.validate_axiom(&[]).unwrap_err().errors(), &[GrammarError::MissingAxiom]);
+            assert_eq!(
+                grammar.validate_axiom(&['F', 'G']).unwrap_err().to_string(),
+                "axiom contains unknown letters: 'G'",
+            );
+            assert!(grammar.validate_axiom(&['F', '+']).is_ok());
+        }
+    }
+
+    mod choose_rule {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::grammar::{Grammar, ProductionRule};
+        use crate::word::Word;
+
+        fn grammar() -> Grammar<char> {
+            Grammar {
+                terminals: vec!['+', '-'],
+                non_terminals: vec!['F'],
+                production_rules: vec![
+                    ProductionRule::new(Word::from("F"), Word::from("F+F")).with_weight(1.0),
+                    ProductionRule::new(Word::from("F"), Word::from("F-F")).with_weight(3.0),
+                    ProductionRule::new(Word::from("F"), Word::from("FF")).with_weight(0.0),
+                ],
+                axiom: Word::from("F"),
+            }
+        }
+
+        #[test]
+        fn same_seed_picks_same_rules() {
+            let grammar = grammar();
+            let mut first = ChaCha8Rng::seed_from_u64(3);
+            let mut second = ChaCha8Rng::seed_from_u64(3);
+            for _ in 0..20 {
+                let lhs = Word::from("F");
+                assert_eq!(
+                    grammar.choose_rule(&lhs, &mut first).unwrap().rhs(),
+                    grammar.choose_rule(&lhs, &mut second).unwrap().rhs(),
+                );
+            }
+        }
+
+        #[test]
+        fn never_picks_rules_with_weight_zero() {
+            let grammar = grammar();
+            let mut rng = ChaCha8Rng::seed_from_u64(0);
+            for _ in 0..100 {
+                let rule = grammar.choose_rule(&Word::from("F"), &mut rng).unwrap();
+                assert_ne!(rule.rhs(), &Word::from("FF"));
+            }
+        }
+
+        #[test]
+        fn deterministic_rules_shadow_the_others() {
+            let mut grammar = grammar();
+            grammar.production_rules.insert(1, ProductionRule::new(Word::from("F"), Word::from("F")));
+            let mut rng = ChaCha8Rng::seed_from_u64(0);
+            for _ in 0..20 {
+                assert_eq!(grammar.choose_rule(&Word::from("F"), &mut rng).unwrap().rhs(), &Word::from("F"));
+            }
+        }
+
+        #[test]
+        fn return_none_for_unknown_lhs() {
+            let grammar = grammar();
+            let mut rng = ChaCha8Rng::seed_from_u64(0);
+            assert!(grammar.choose_rule(&Word::from("X"), &mut rng).is_none());
+        }
+    }
+
+    mod derive {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::grammar::{ConversionError, Grammar, ProductionRule};
+        use crate::word::Word;
+
+        fn rule(lhs: &str, rhs: &str) -> ProductionRule<char> {
+            ProductionRule::new(Word::from(lhs), Word::from(rhs))
+        }
+
+        fn koch() -> Grammar<char> {
+            Grammar::new(vec!['+', '-'], vec!['F'])
+                .with_production_rules(vec![rule("F", "F+F--F+F")])
+                .unwrap()
+                .with_axiom(Word::from("F--F--F"))
+                .unwrap()
+        }
+
+        #[test]
+        fn koch_lengths() {
+            let grammar = koch();
+            assert_eq!(grammar.derive(0), Word::from("F--F--F"));
+            assert_eq!(grammar.derive(1), Word::from("F+F--F+F--F+F--F+F--F+F--F+F"));
+            assert_eq!(grammar.derive(4).len(), 7 * 4usize.pow(4));
+        }
+
+        #[test]
+        fn derivations_are_successive_steps() {
+            let grammar = koch();
+            let words: Vec<Word<char>> = grammar.derivations().take(4).collect();
+            for (depth, word) in words.iter().enumerate() {
+                assert_eq!(word, &grammar.derive(depth));
+            }
+            assert_eq!(grammar.step(&words[2]), grammar.derive(3));
+        }
+
+        #[test]
+        fn longest_lhs_first() {
+            let grammar = Grammar::new(vec!['x', 'y'], vec!['A', 'B'])
+                .with_production_rules(vec![rule("A", "x"), rule("AB", "y"), rule("B", "AB")])
+                .unwrap();
+            assert_eq!(grammar.step(&Word::from("ABBA")), Word::from("yABx"));
+        }
+
+        #[test]
+        fn letters_without_rules_stay() {
+            let grammar = Grammar::new(vec!['+'], vec!['F', 'G'])
+                .with_production_rules(vec![rule("F", "FF"), rule("G", "+").with_weight(0.0)])
+                .unwrap();
+            assert_eq!(grammar.step(&Word::from("F+G")), Word::from("FF+G"));
+        }
+
+        #[test]
+        fn keep_an_lhs_whose_rules_have_weight_zero() {
+            let grammar = Grammar::new(vec!['x'], vec!['A', 'B'])
+                .with_production_rules(vec![rule("AB", "x").with_weight(0.0), rule("B", "x")])
+                .unwrap();
+            assert_eq!(grammar.step(&Word::from("ABB")), Word::from("ABx"));
+        }
+
+        #[test]
+        fn same_seed_same_derivations() {
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F'])
+                .with_production_rules(vec![rule("F", "F+F").with_weight(1.0), rule("F", "F-F").with_weight(1.0)])
+                .unwrap()
+                .with_axiom(Word::from("F"))
+                .unwrap();
+            let first: Vec<Word<char>> = grammar.derivations_with_rng(ChaCha8Rng::seed_from_u64(5)).take(5).collect();
+            let second: Vec<Word<char>> = grammar.derivations_with_rng(ChaCha8Rng::seed_from_u64(5)).take(5).collect();
+            assert_eq!(first, second);
+            assert_eq!(grammar.derive_with_rng(4, &mut ChaCha8Rng::seed_from_u64(5)), first[4]);
+        }
+
+        #[test]
+        fn single_letter_rules() {
+            assert_eq!(koch().single_letter_rules().unwrap(), vec![(&'F', &Word::from("F+F--F+F"))]);
+            let grammar = Grammar::new(vec![], vec!['A', 'B'])
+                .with_production_rules(vec![rule("A", "B"), rule("AB", "A")])
+                .unwrap();
+            assert_eq!(grammar.single_letter_rules(), Err(ConversionError::MultiLetterPredecessor { rule: 1, length: 2 }));
+        }
+    }
+
+    mod edit {
+        use crate::grammar::{Grammar, GrammarError, ProductionRule};
+        use crate::word::Word;
+
+        #[test]
+        fn replace_rules() {
+            let mut grammar = Grammar::new(vec!['+'], vec!['F', 'X'])
+                .with_production_rules(vec![
+                    ProductionRule::new(Word::from("F"), Word::from("F+F")),
+                    ProductionRule::new(Word::from("X"), Word::from("F")),
+                ])
+                .unwrap();
+            grammar.add_terminals(&['-', 'F']);
+            grammar.add_non_terminals(&['+', 'Y']);
+            assert_eq!(grammar.terminals(), &['-'][..]);
+            assert_eq!(grammar.non_terminals(), &['F', 'X', '+', 'Y'][..]);
+
+            grammar.replace_rules(&['F'], vec![ProductionRule::new(Word::from("F"), Word::from("F-F")).with_weight(1.0)]).unwrap();
+            assert_eq!(grammar.production_rules()[0].lhs(), &Word::from("X"));
+            assert_eq!(grammar.production_rules()[1].rhs(), &Word::from("F-F"));
+
+            let error = grammar.replace_rules(&['X'], vec![ProductionRule::new(Word::from("X"), Word::from("G"))]).unwrap_err();
+            assert!(matches!(error.errors(), [GrammarError::UnknownLetters { rule: 1, .. }]));
+            assert_eq!(grammar.production_rules()[0].rhs(), &Word::from("F"));
+        }
+
+        #[test]
+        fn change_axiom() {
+            let mut grammar = Grammar::new(vec!['+'], vec!['F']);
+            assert_eq!(grammar.change_axiom(Word::from("")).unwrap_err().errors(), &[GrammarError::MissingAxiom]);
+            grammar.change_axiom(Word::from("F+F")).unwrap();
+            assert_eq!(grammar.axiom(), &Word::from("F+F"));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde {
+        use crate::grammar::{DerivationSnapshot, Grammar, ProductionRule};
+        use crate::word::Word;
+
+        fn plant() -> Grammar<char> {
+            Grammar::new(vec!['+', '-', '[', ']'], vec!['X', 'F'])
+                .with_production_rules(vec![
+                    ProductionRule::new(Word::from("X"), Word::from("F+[[X]-X]-F[-FX]+X")),
+                    ProductionRule::new(Word::from("F"), Word::from("FF")).with_weight(2.0),
+                    ProductionRule::new(Word::from("F"), Word::from("F")).with_weight(1.0),
+                ])
+                .unwrap()
+                .with_axiom(Word::from("X"))
+                .unwrap()
+        }
+
+        fn assert_same(first: &Grammar<char>, second: &Grammar<char>) {
+            assert_eq!(first.terminals(), second.terminals());
+            assert_eq!(first.non_terminals(), second.non_terminals());
+            assert_eq!(first.production_rules(), second.production_rules());
+            assert_eq!(first.axiom(), second.axiom());
+        }
+
+        #[test]
+        fn json_round_trip() {
+            let grammar = plant();
+            let json = serde_json::to_string(&grammar).unwrap();
+            assert_same(&serde_json::from_str(&json).unwrap(), &grammar);
+        }
+
+        #[test]
+        fn toml_round_trip() {
+            let grammar = plant();
+            let toml = toml::to_string(&grammar).unwrap();
+            assert_same(&toml::from_str(&toml).unwrap(), &grammar);
+        }
+
+        #[test]
+        fn rules_without_weight_and_axiom() {
+            let toml = r#"
+                terminals = ["+"]
+                non_terminals = ["F"]
+                production_rules = [{ lhs = ["F"], rhs = ["F", "+", "F"] }]
+            "#;
+            let grammar: Grammar<char> = toml::from_str(toml).unwrap();
+            assert!(!grammar.production_rules()[0].is_stochastic());
+            assert!(grammar.axiom().is_empty());
+        }
+
+        #[test]
+        fn snapshot_round_trip() {
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F'])
+                .with_production_rules(vec![ProductionRule::new(Word::from("F"), Word::from("F+F-F"))])
+                .unwrap()
+                .with_axiom(Word::from("F"))
+                .unwrap();
+            let snapshot = grammar.snapshot(2);
+            let toml = toml::to_string(&snapshot).unwrap();
+            let mut loaded: DerivationSnapshot<char> = toml::from_str(&toml).unwrap();
+            assert_eq!(loaded.depth(), 2);
+            assert_eq!(loaded.word(), snapshot.word());
+            assert_same(loaded.grammar(), &grammar);
+
+            loaded.advance(1);
+            assert_eq!(loaded.depth(), 3);
+            assert_eq!(loaded.word(), &grammar.derive(3));
+
+            let json = serde_json::to_string(&loaded).unwrap();
+            assert_eq!(serde_json::from_str::<DerivationSnapshot<char>>(&json).unwrap().word(), loaded.word());
+        }
+
+        #[test]
+        fn snapshot_with_unknown_letters() {
+            let json = r#"{"grammar":{"terminals":["+"],"non_terminals":["F"],"production_rules":[]},"depth":1,"word":["F","G"]}"#;
+            let error = serde_json::from_str::<DerivationSnapshot<char>>(json).err().unwrap();
+            assert!(error.to_string().contains("word contains unknown letters: 'G'"));
+        }
+
+        #[test]
+        fn validate_when_loading() {
+            let json = r#"{"terminals":["+"],"non_terminals":["F"],"production_rules":[{"lhs":["F"],"rhs":["G"]}],"axiom":["F"]}"#;
+            let error = serde_json::from_str::<Grammar<char>>(json).err().unwrap();
+            assert!(error.to_string().contains("rule 1: rhs contains unknown letters: 'G'"));
+        }
+    }
 }
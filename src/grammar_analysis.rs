@@ -0,0 +1,320 @@
+use std::fmt::Display;
+
+use crate::grammar::{Grammar, ProductionRule};
+
+// Static analysis of a grammar, starting from its own axiom.
+// Rules are looked at the way Grammar::choose_rule applies them: the one
+// deterministic rule validation allows for an lhs shadows every weighted rule
+// for the same lhs, weighted rules without such a rule are alternatives to
+// each other. Only weighted rules can be shadowed.
+//
+// Rules with an lhs of several letters only count as applicable once all
+// of their letters are reachable, whether the letters ever stand next to
+// each other is not checked.
+
+//***************************************************************************
+//
+// GrammarReport
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarReport<T> {
+    reachable: Vec<T>,
+    unreachable_non_terminals: Vec<T>,
+    never_rewritten: Vec<T>,
+    dead: Vec<T>,
+    shadowed_rules: Vec<(usize, ProductionRule<T>)>,
+    unused_rules: Vec<(usize, ProductionRule<T>)>,
+    propagating: bool,
+    deterministic: bool,
+}
+
+impl<T> GrammarReport<T> {
+    // Letters that show up in some derivation of the axiom, in the order they are found.
+    pub fn reachable(&self) -> &[T] {
+        &self.reachable
+    }
+
+    pub fn unreachable_non_terminals(&self) -> &[T] {
+        &self.unreachable_non_terminals
+    }
+
+    // Reachable letters no applicable rule rewrites, they stay as they are.
+    pub fn never_rewritten(&self) -> &[T] {
+        &self.never_rewritten
+    }
+
+    // Reachable letters that always vanish after a few derivation steps,
+    // since all of their successors end up empty.
+    pub fn dead(&self) -> &[T] {
+        &self.dead
+    }
+
+    // Weighted rules with their index, hidden by the deterministic rule for
+    // the same lhs.
+    pub fn shadowed_rules(&self) -> &[(usize, ProductionRule<T>)] {
+        &self.shadowed_rules
+    }
+
+    // Rules with their index that are never applied: their lhs is empty or
+    // not reachable, or their weight is zero.
+    pub fn unused_rules(&self) -> &[(usize, ProductionRule<T>)] {
+        &self.unused_rules
+    }
+
+    // No applicable rule has an empty successor.
+    pub fn is_propagating(&self) -> bool {
+        self.propagating
+    }
+
+    // A D0L system: every applicable rule rewrites a single letter, and
+    // no letter has more than one applicable rule.
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+}
+
+impl<T> Display for GrammarReport<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn letters<T: Display>(f: &mut std::fmt::Formatter<'_>, name: &str, letters: &[T]) -> std::fmt::Result {
+            write!(f, "{:<28}", name)?;
+            for (index, letter) in letters.iter().enumerate() {
+                if index > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", letter)?;
+            }
+            writeln!(f)
+        }
+        fn rules<T: Display>(f: &mut std::fmt::Formatter<'_>, name: &str, rules: &[(usize, ProductionRule<T>)]) -> std::fmt::Result {
+            writeln!(f, "{:<28}{}", name, rules.len())?;
+            for (index, rule) in rules {
+                writeln!(f, "    rule {}: {}", index + 1, rule)?;
+            }
+            Ok(())
+        }
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+        letters(f, "reachable:", &self.reachable)?;
+        letters(f, "unreachable non terminals:", &self.unreachable_non_terminals)?;
+        letters(f, "never rewritten:", &self.never_rewritten)?;
+        letters(f, "dead:", &self.dead)?;
+        rules(f, "shadowed rules:", &self.shadowed_rules)?;
+        rules(f, "unused rules:", &self.unused_rules)?;
+        writeln!(f, "{:<28}{}", "propagating:", yes_no(self.propagating))?;
+        write!(f, "{:<28}{}", "deterministic:", yes_no(self.deterministic))
+    }
+}
+
+//***************************************************************************
+//
+// Grammar::analyze
+//
+//***************************************************************************
+
+impl<T> Grammar<T>
+where
+    T: Clone + PartialEq,
+{
+    pub fn analyze(&self) -> GrammarReport<T> {
+        let axiom = &self.axiom()[..];
+        let rules = self.production_rules();
+        let shadowed: Vec<bool> = rules.iter()
+            .map(|rule| rule.is_stochastic() && rules.iter().any(|other| !other.is_stochastic() && other.lhs() == rule.lhs()))
+            .collect();
+        let effective = |index: usize| !shadowed[index] && !rules[index].lhs().is_empty() && rules[index].weight() > 0.0;
+
+        let mut reachable: Vec<T> = Vec::new();
+        for letter in axiom {
+            if !reachable.contains(letter) {
+                reachable.push(letter.clone());
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, rule) in rules.iter().enumerate() {
+                if !effective(index) || !rule.lhs().iter().all(|letter| reachable.contains(letter)) {
+                    continue;
+                }
+                for letter in rule.rhs() {
+                    if !reachable.contains(letter) {
+                        reachable.push(letter.clone());
+                        changed = true;
+                    }
+                }
+            }
+        }
+        let is_applicable = |index: usize| effective(index) && rules[index].lhs().iter().all(|letter| reachable.contains(letter));
+
+        let unreachable_non_terminals = self.non_terminals().iter()
+            .filter(|letter| !reachable.contains(letter))
+            .cloned()
+            .collect();
+        let never_rewritten = reachable.iter()
+            .filter(|letter| !(0..rules.len()).any(|index| is_applicable(index) && rules[index].lhs().contains_letter(letter)))
+            .cloned()
+            .collect();
+
+        // A letter stays alive if it is never rewritten, or if one of its
+        // rules has a successor with a living letter in it.
+        let rewrites = |index: usize, letter: &T| {
+            effective(index) && rules[index].lhs().len() == 1 && &rules[index].lhs()[0] == letter
+        };
+        let mut alive: Vec<T> = reachable.iter()
+            .filter(|letter| !(0..rules.len()).any(|index| rewrites(index, letter)))
+            .cloned()
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for letter in &reachable {
+                if alive.contains(letter) {
+                    continue;
+                }
+                if (0..rules.len()).any(|index| rewrites(index, letter) && rules[index].rhs().iter().any(|successor| alive.contains(successor))) {
+                    alive.push(letter.clone());
+                    changed = true;
+                }
+            }
+        }
+        let dead = reachable.iter().filter(|letter| !alive.contains(letter)).cloned().collect();
+
+        let shadowed_rules = (0..rules.len())
+            .filter(|&index| shadowed[index])
+            .map(|index| (index, rules[index].clone()))
+            .collect();
+        let unused_rules = (0..rules.len())
+            .filter(|&index| !shadowed[index] && !is_applicable(index))
+            .map(|index| (index, rules[index].clone()))
+            .collect();
+
+        let applicable: Vec<usize> = (0..rules.len()).filter(|&index| is_applicable(index)).collect();
+        let propagating = applicable.iter().all(|&index| !rules[index].rhs().is_empty());
+        let deterministic = applicable.iter().all(|&index| {
+            rules[index].lhs().len() == 1
+                && applicable.iter().filter(|&&other| rules[other].lhs() == rules[index].lhs()).count() == 1
+        });
+
+        GrammarReport {
+            reachable,
+            unreachable_non_terminals,
+            never_rewritten,
+            dead,
+            shadowed_rules,
+            unused_rules,
+            propagating,
+            deterministic,
+        }
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod analyze {
+        use crate::grammar::{Grammar, ProductionRule};
+        use crate::word::Word;
+
+        fn rule(lhs: &str, rhs: &str) -> ProductionRule<char> {
+            ProductionRule::new(Word::from(lhs), Word::from(rhs))
+        }
+
+        #[test]
+        fn koch() {
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F'])
+                .with_production_rules(vec![rule("F", "F+F--F+F")])
+                .and_then(|grammar| grammar.with_axiom(Word::from("F--F--F")))
+                .unwrap();
+            let report = grammar.analyze();
+            assert_eq!(report.reachable(), &['F', '-', '+']);
+            assert_eq!(report.never_rewritten(), &['-', '+']);
+            assert!(report.unreachable_non_terminals().is_empty());
+            assert!(report.dead().is_empty());
+            assert!(report.shadowed_rules().is_empty() && report.unused_rules().is_empty());
+            assert!(report.is_propagating());
+            assert!(report.is_deterministic());
+        }
+
+        #[test]
+        fn problems() {
+            let grammar = Grammar::new(vec!['+'], vec!['A', 'B', 'C', 'D', 'E'])
+                .with_production_rules(vec![
+                    rule("A", "AB+"),
+                    rule("A", "A").with_weight(1.0),
+                    rule("B", "C"),
+                    rule("C", ""),
+                    rule("D", "A"),
+                    rule("E", "A").with_weight(0.0),
+                ])
+                .and_then(|grammar| grammar.with_axiom(Word::from("AE")))
+                .unwrap();
+            let report = grammar.analyze();
+            assert_eq!(report.reachable(), &['A', 'E', 'B', '+', 'C']);
+            assert_eq!(report.unreachable_non_terminals(), &['D']);
+            assert_eq!(report.never_rewritten(), &['E', '+']);
+            assert_eq!(report.dead(), &['B', 'C']);
+            assert_eq!(report.shadowed_rules().iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![1]);
+            assert_eq!(report.unused_rules().iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![4, 5]);
+            assert!(!report.is_propagating());
+            assert!(report.is_deterministic());
+        }
+
+        #[test]
+        fn shadowed_before_the_deterministic_rule() {
+            let grammar = Grammar::new(vec![], vec!['A', 'B'])
+                .with_production_rules(vec![
+                    rule("A", "B").with_weight(1.0),
+                    rule("A", "AB"),
+                    rule("A", "BB").with_weight(2.0),
+                    rule("B", "A"),
+                ])
+                .and_then(|grammar| grammar.with_axiom(Word::from("A")))
+                .unwrap();
+            let report = grammar.analyze();
+            assert_eq!(report.shadowed_rules().iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![0, 2]);
+            assert!(report.is_deterministic());
+        }
+
+        #[test]
+        fn stochastic_and_context() {
+            let grammar = Grammar::new(vec![], vec!['A', 'B'])
+                .with_production_rules(vec![
+                    rule("A", "AB").with_weight(1.0),
+                    rule("A", "BA").with_weight(1.0),
+                    rule("AB", "B"),
+                ])
+                .and_then(|grammar| grammar.with_axiom(Word::from("A")))
+                .unwrap();
+            let report = grammar.analyze();
+            assert!(!report.is_deterministic());
+            assert!(report.unused_rules().is_empty());
+        }
+
+        #[test]
+        fn display() {
+            let grammar = Grammar::new(vec!['+'], vec!['A', 'B'])
+                .with_production_rules(vec![rule("A", "A+"), rule("A", "B").with_weight(1.0), rule("B", "")])
+                .and_then(|grammar| grammar.with_axiom(Word::from("A")))
+                .unwrap();
+            let report = grammar.analyze();
+            assert_eq!(
+                report.to_string(),
+                "reachable:                  A +\n\
+                 unreachable non terminals:  B\n\
+                 never rewritten:            +\n\
+                 dead:                       \n\
+                 shadowed rules:             1\n    rule 2: A -> B (1)\n\
+                 unused rules:               1\n    rule 3: B -> \n\
+                 propagating:                yes\n\
+                 deterministic:              yes",
+            );
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::actions::{Action, ActionError, ActionRegistry, SharedActionRegistry};
+use crate::grammar::{Grammar, ProductionRule};
+use crate::tryout::LindenmayerSystem;
+use crate::word::Word;
+
+// Plain text description of an L-system, e.g.
+//
+//     # Koch snowflake
+//     name: koch
+//     axiom: F--F--F
+//     angle: 60
+//     alphabet: F + -
+//     rules:
+//         F -> F+F--F+F
+//     semantics:
+//         F = forward
+//         + = turn_left
+//         - = turn_right
+//
+// Every letter is a single character, whitespace between letters is ignored.
+// The angle is given in degrees. The alphabet is optional; if it is given,
+// every letter used in the file has to be part of it. Everything after a '#'
+// is a comment.
+
+//***************************************************************************
+//
+// ParseError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnknownKey(String),
+    DuplicateKey(String),
+    MissingKey(&'static str),
+    EmptyAxiom,
+    InvalidAngle(String),
+    EntryOutsideOfSection,
+    MissingArrow,
+    MissingEquals,
+    InvalidPredecessor(String),
+    DuplicateRule(char),
+    UnknownSemantics(String),
+    LetterNotInAlphabet(char),
+}
+
+// Lines and columns start at 1, like in any text editor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    line: usize,
+    column: usize,
+    kind: ParseErrorKind,
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, kind: ParseErrorKind) -> Self {
+        ParseError {
+            line,
+            column,
+            kind,
+        }
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn column(&self) -> usize {
+        self.column
+    }
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            ParseErrorKind::UnknownKey(key) => write!(f, "unknown key '{}'", key),
+            ParseErrorKind::DuplicateKey(key) => write!(f, "key '{}' is given more than once", key),
+            ParseErrorKind::MissingKey(key) => write!(f, "missing key '{}'", key),
+            ParseErrorKind::EmptyAxiom => write!(f, "axiom is empty"),
+            ParseErrorKind::InvalidAngle(angle) => write!(f, "'{}' is not a valid angle", angle),
+            ParseErrorKind::EntryOutsideOfSection => write!(f, "expected 'key: value' or a 'rules:' / 'semantics:' section"),
+            ParseErrorKind::MissingArrow => write!(f, "rule is missing the '->'"),
+            ParseErrorKind::MissingEquals => write!(f, "semantics entry is missing the '='"),
+            ParseErrorKind::InvalidPredecessor(predecessor) => write!(f, "predecessor '{}' has to be a single letter", predecessor),
+            ParseErrorKind::DuplicateRule(letter) => write!(f, "letter '{}' already has a rule", letter),
+            ParseErrorKind::UnknownSemantics(name) => write!(f, "unknown semantics '{}'", name),
+            ParseErrorKind::LetterNotInAlphabet(letter) => write!(f, "letter '{}' is not part of the alphabet", letter),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+//***************************************************************************
+//
+// GrammarFile
+//
+//***************************************************************************
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Header,
+    Rules,
+    Semantics,
+}
+
+#[derive(Debug, Clone)]
+pub struct GrammarFile {
+    name: Option<String>,
+    axiom: Vec<char>,
+    angle: f32,
+    alphabet: Vec<char>,
+    rules: Vec<(char, Vec<char>)>,
+    semantics: Vec<(char, String, Option<Action>)>,
+}
+
+impl GrammarFile {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let input = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&input)?)
+    }
+
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let mut name = None;
+        let mut axiom = None;
+        let mut angle = None;
+        let mut alphabet: Option<Vec<char>> = None;
+        let mut rules = Vec::new();
+        let mut semantics = Vec::new();
+        let turtle = ActionRegistry::turtle();
+        // Every letter together with the position it was found at,
+        // to check them against the alphabet once it is known.
+        let mut used_letters: Vec<(char, usize, usize)> = Vec::new();
+        let mut section = Section::Header;
+
+        for (index, raw_line) in input.lines().enumerate() {
+            let line_number = index + 1;
+            let line = match raw_line.find('#') {
+                Some(comment) => &raw_line[..comment],
+                None => raw_line,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let indent = column_of(line, line.trim_start());
+
+            if let Some((key, value)) = split_key_value(line) {
+                let value_column = column_of(line, value);
+                let duplicate = || ParseError::new(line_number, indent, ParseErrorKind::DuplicateKey(key.to_owned()));
+                section = Section::Header;
+                match key {
+                    "rules" if value.is_empty() => section = Section::Rules,
+                    "semantics" if value.is_empty() => section = Section::Semantics,
+                    "name" => {
+                        if name.replace(value.to_owned()).is_some() {
+                            return Err(duplicate());
+                        }
+                    }
+                    "axiom" => {
+                        let letters = letters_with_columns(line, value, line_number);
+                        if letters.is_empty() {
+                            return Err(ParseError::new(line_number, indent, ParseErrorKind::EmptyAxiom));
+                        }
+                        used_letters.extend(&letters);
+                        let letters = letters.into_iter().map(|(letter, _, _)| letter).collect();
+                        if axiom.replace(letters).is_some() {
+                            return Err(duplicate());
+                        }
+                    }
+                    "angle" => {
+                        let degrees: f32 = value.parse()
+                            .map_err(|_| ParseError::new(line_number, value_column, ParseErrorKind::InvalidAngle(value.to_owned())))?;
+                        if angle.replace(degrees).is_some() {
+                            return Err(duplicate());
+                        }
+                    }
+                    "alphabet" => {
+                        let letters = letters_with_columns(line, value, line_number).into_iter().map(|(letter, _, _)| letter).collect();
+                        if alphabet.replace(letters).is_some() {
+                            return Err(duplicate());
+                        }
+                    }
+                    _ => return Err(ParseError::new(line_number, indent, ParseErrorKind::UnknownKey(key.to_owned()))),
+                }
+                continue;
+            }
+
+            match section {
+                Section::Header => {
+                    return Err(ParseError::new(line_number, indent, ParseErrorKind::EntryOutsideOfSection));
+                }
+                Section::Rules => {
+                    let (predecessor, successor) = line.split_once("->")
+                        .ok_or_else(|| ParseError::new(line_number, indent, ParseErrorKind::MissingArrow))?;
+                    let letter = single_letter(predecessor)
+                        .ok_or_else(|| ParseError::new(line_number, indent, ParseErrorKind::InvalidPredecessor(predecessor.trim().to_owned())))?;
+                    if rules.iter().any(|(existing, _)| *existing == letter) {
+                        return Err(ParseError::new(line_number, indent, ParseErrorKind::DuplicateRule(letter)));
+                    }
+                    let successor = letters_with_columns(line, successor, line_number);
+                    used_letters.push((letter, line_number, indent));
+                    used_letters.extend(&successor);
+                    rules.push((letter, successor.into_iter().map(|(letter, _, _)| letter).collect()));
+                }
+                Section::Semantics => {
+                    let (letter, action_name) = line.split_once('=')
+                        .ok_or_else(|| ParseError::new(line_number, indent, ParseErrorKind::MissingEquals))?;
+                    let letter = single_letter(letter)
+                        .ok_or_else(|| ParseError::new(line_number, indent, ParseErrorKind::InvalidPredecessor(letter.trim().to_owned())))?;
+                    let action_name = action_name.trim();
+                    let action = action_by_name(action_name, &turtle)
+                        .ok_or_else(|| ParseError::new(line_number, column_of(line, action_name), ParseErrorKind::UnknownSemantics(action_name.to_owned())))?;
+                    used_letters.push((letter, line_number, indent));
+                    semantics.push((letter, action_name.to_owned(), action));
+                }
+            }
+        }
+
+        let last_line = input.lines().count().max(1);
+        let axiom = axiom.ok_or_else(|| ParseError::new(last_line, 1, ParseErrorKind::MissingKey("axiom")))?;
+        let angle = angle.ok_or_else(|| ParseError::new(last_line, 1, ParseErrorKind::MissingKey("angle")))?;
+
+        let alphabet = match alphabet {
+            Some(alphabet) => {
+                if let Some((letter, line, column)) = used_letters.iter().find(|(letter, _, _)| !alphabet.contains(letter)) {
+                    return Err(ParseError::new(*line, *column, ParseErrorKind::LetterNotInAlphabet(*letter)));
+                }
+                alphabet
+            }
+            None => {
+                let mut alphabet = Vec::new();
+                for (letter, _, _) in used_letters {
+                    if !alphabet.contains(&letter) {
+                        alphabet.push(letter);
+                    }
+                }
+                alphabet
+            }
+        };
+
+        Ok(GrammarFile {
+            name,
+            axiom,
+            angle,
+            alphabet,
+            rules,
+            semantics,
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn axiom(&self) -> &[char] {
+        &self.axiom
+    }
+
+    // The angle in degrees, as written in the file.
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+
+    pub fn alphabet(&self) -> &[char] {
+        &self.alphabet
+    }
+
+    pub fn rules(&self) -> &[(char, Vec<char>)] {
+        &self.rules
+    }
+
+    // Drawn from the grammar of the file, see to_grammar.
+    pub fn to_lindenmayer_system(&self) -> LindenmayerSystem<char> {
+        let actions: Vec<(char, Option<Action>)> = self.semantics.iter()
+            .map(|(letter, _, action)| (*letter, *action))
+            .collect();
+
+        // The rules of a file rewrite single letters deterministically.
+        let mut system = LindenmayerSystem::from_grammar(self.to_grammar(), self.angle.to_radians()).unwrap();
+        system.with_actions(&actions);
+        if let Some(name) = &self.name {
+            system.change_name(name);
+        }
+        system
+    }
+
+    // Same as to_lindenmayer_system, but the letters are bound to their
+    // actions by name, so they follow the changes to the registry.
+    pub fn to_lindenmayer_system_with(&self, registry: SharedActionRegistry) -> Result<LindenmayerSystem<char>, ActionError> {
+        let mut system = self.to_lindenmayer_system();
+        system.with_action_registry(registry);
+        for (letter, name, _) in &self.semantics {
+            if name != "none" {
+                system.bind_action(*letter, name)?;
+            }
+        }
+        system.refresh_actions();
+        Ok(system)
+    }
+
+    // Letters with a rule become the non terminals, all other letters of the
+    // alphabet the terminals. The axiom of the file is the axiom of the grammar.
+    // Parsing already checks everything the grammar checks, so every file
+    // is a valid grammar.
+    pub fn to_grammar(&self) -> Grammar<char> {
+        let non_terminals: Vec<char> = self.rules.iter().map(|(letter, _)| *letter).collect();
+        let terminals: Vec<char> = self.alphabet.iter()
+            .filter(|letter| !non_terminals.contains(letter))
+            .copied()
+            .collect();
+        let production_rules = self.rules.iter()
+            .map(|(letter, successor)| ProductionRule::new(Word::from(*letter), Word::from(successor)))
+            .collect();
+
+        Grammar::new(terminals, non_terminals)
+            .with_production_rules(production_rules)
+            .and_then(|grammar| grammar.with_axiom(Word::from(&self.axiom)))
+            .unwrap()
+    }
+}
+
+// "none" explicitly binds a letter to do nothing, e.g. for the X in plants.
+// All other names are looked up in the turtle actions.
+fn action_by_name(name: &str, turtle: &ActionRegistry) -> Option<Option<Action>> {
+    if name == "none" {
+        return Some(None);
+    }
+    turtle.action(name).ok().map(Some)
+}
+
+// Splits `key: value`, where the key has to be a plain identifier.
+// Rules like `F -> F:F` do not count, as their key is not an identifier.
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+// Columns count characters, not bytes. The part has to be a subslice of line.
+fn column_of(line: &str, part: &str) -> usize {
+    let offset = part.as_ptr() as usize - line.as_ptr() as usize;
+    line[..offset].chars().count() + 1
+}
+
+fn letters_with_columns(line: &str, part: &str, line_number: usize) -> Vec<(char, usize, usize)> {
+    let start = column_of(line, part);
+    part.chars()
+        .enumerate()
+        .filter(|(_, letter)| !letter.is_whitespace())
+        .map(|(index, letter)| (letter, line_number, start + index))
+        .collect()
+}
+
+fn single_letter(part: &str) -> Option<char> {
+    let mut letters = part.trim().chars();
+    match (letters.next(), letters.next()) {
+        (Some(letter), None) => Some(letter),
+        _ => None,
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod parse {
+        use crate::grammar_file::{GrammarFile, ParseErrorKind};
+
+        const KOCH: &str = "\
+# Koch snowflake
+name: koch
+axiom: F--F--F
+angle: 60
+alphabet: F + -
+rules:
+    F -> F+F--F+F
+semantics:
+    F = forward
+    + = turn_left
+    - = turn_right
+";
+
+        #[test]
+        fn koch() {
+            let file = GrammarFile::parse(KOCH).unwrap();
+            assert_eq!(file.name(), Some("koch"));
+            assert_eq!(file.axiom(), &['F', '-', '-', 'F', '-', '-', 'F'][..]);
+            assert_eq!(file.angle(), 60.0);
+            assert_eq!(file.rules(), &[('F', "F+F--F+F".chars().collect())][..]);
+        }
+
+        #[test]
+        fn infer_alphabet() {
+            let file = GrammarFile::parse("axiom: X\nangle: 25\nrules:\n  X -> F[+X]\n").unwrap();
+            assert_eq!(file.alphabet(), &['X', 'F', '[', '+', ']'][..]);
+        }
+
+        #[test]
+        fn report_line_and_column_of_unknown_letter() {
+            let input = KOCH.replace("F -> F+F--F+F", "F -> F+G--F+F");
+            let error = GrammarFile::parse(&input).unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::LetterNotInAlphabet('G'));
+            assert_eq!((error.line(), error.column()), (7, 12));
+            assert_eq!(format!("{}", error), "7:12: letter 'G' is not part of the alphabet");
+        }
+
+        #[test]
+        fn report_unknown_semantics() {
+            let input = KOCH.replace("+ = turn_left", "+ = spin");
+            let error = GrammarFile::parse(&input).unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::UnknownSemantics(String::from("spin")));
+            assert_eq!((error.line(), error.column()), (10, 9));
+        }
+
+        #[test]
+        fn report_missing_axiom() {
+            let error = GrammarFile::parse("angle: 90\n").unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::MissingKey("axiom"));
+            let error = GrammarFile::parse("axiom:\nangle: 90\n").unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::EmptyAxiom);
+        }
+
+        #[test]
+        fn report_invalid_lines() {
+            let error = GrammarFile::parse("axiom: F\nangle: ninety\n").unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::InvalidAngle(String::from("ninety")));
+            assert_eq!((error.line(), error.column()), (2, 8));
+
+            let error = GrammarFile::parse("axiom: F\nangle: 90\nF -> FF\n").unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::EntryOutsideOfSection);
+
+            let error = GrammarFile::parse("axiom: F\nangle: 90\nrules:\n  FF -> F\n").unwrap_err();
+            assert_eq!(error.kind(), &ParseErrorKind::InvalidPredecessor(String::from("FF")));
+            assert_eq!((error.line(), error.column()), (4, 3));
+        }
+    }
+
+    mod convert {
+        use crate::grammar_file::GrammarFile;
+        use crate::actions::{ActionError, ActionRegistry};
+        use crate::tryout::LindenmayerSystem;
+        use crate::word::Word;
+
+        const PLANT: &str = "\
+axiom: X
+angle: 25
+rules:
+    X -> F+[[X]-X]-F[-FX]+X
+    F -> FF
+semantics:
+    F = forward
+    + = turn_left
+    - = turn_right
+    [ = push
+    ] = pop
+    X = none
+";
+
+        #[test]
+        fn to_lindenmayer_system() {
+            let mut system = GrammarFile::parse(PLANT).unwrap().to_lindenmayer_system();
+            system.update_vertex_stack(2);
+            assert_eq!(system.get_vertex_stack().len(), 3);
+            assert_eq!(system.get_vertex_stack_at(0).unwrap().len(), 1);
+        }
+
+        #[test]
+        fn to_grammar() {
+            let grammar = GrammarFile::parse(PLANT).unwrap().to_grammar();
+            assert_eq!(grammar.non_terminals(), &['X', 'F'][..]);
+            assert_eq!(grammar.terminals(), &['+', '[', ']', '-'][..]);
+            assert_eq!(grammar.production_rules()[1].rhs(), &Word::from("FF"));
+            assert_eq!(grammar.axiom(), &Word::from("X"));
+        }
+
+        #[test]
+        fn to_lindenmayer_system_with() {
+            let registry = ActionRegistry::turtle().into_shared();
+            let file = GrammarFile::parse(PLANT).unwrap();
+            let mut system = file.to_lindenmayer_system_with(registry.clone()).unwrap();
+            let mut expected = file.to_lindenmayer_system();
+            system.update_vertex_stack(2);
+            expected.update_vertex_stack(2);
+            assert_eq!(system.get_vertex_stack_at(2), expected.get_vertex_stack_at(2));
+            assert_eq!(system.action_name('['), Some("push"));
+            assert_eq!(system.action_name('X'), None);
+
+            let empty = ActionRegistry::new().into_shared();
+            assert_eq!(file.to_lindenmayer_system_with(empty).err(), Some(ActionError::UnknownAction("forward".to_owned())));
+        }
+
+        #[test]
+        fn grammar_to_lindenmayer_system() {
+            let file = GrammarFile::parse(PLANT).unwrap();
+            let grammar = file.to_grammar();
+            let mut from_grammar = LindenmayerSystem::from_grammar(grammar.clone(), file.angle().to_radians()).unwrap();
+            let mut from_file = file.to_lindenmayer_system();
+            from_grammar.update_vertex_stack(3);
+            from_file.update_vertex_stack(3);
+            assert_eq!(from_grammar.get_word_stack_at(3), from_file.get_word_stack_at(3));
+            assert_eq!(&from_grammar.get_word_stack_at(3).unwrap()[..], &grammar.derive(3)[..]);
+            assert_eq!(from_file.grammar().production_rules(), grammar.production_rules());
+
+            from_file.change_production_rule('F', Some(vec!['F', 'G']));
+            assert_eq!(from_file.grammar().rules_for(&Word::from("F"))[0].rhs(), &Word::from("FG"));
+            assert!(from_file.grammar().terminals().contains(&'G'));
+            from_file.change_production_rule('X', None);
+            assert!(from_file.grammar().rules_for(&Word::from("X")).is_empty());
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::hash::Hash;
+
+use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::grammar::Grammar;
+
+// Growth of a D0L system, computed from its Parikh matrix instead of the
+// words themselves. Row i of the matrix counts how often every letter
+// appears in the successor of letter i, so the letter counts of depth n + 1
+// are the counts of depth n times the matrix, and the counts of depth n are
+// the counts of the axiom times the n-th power of the matrix.
+//
+// Only letters reachable from the axiom are part of the matrix. Letters
+// without a rule stay the same, so their row only counts themselves.
+
+//***************************************************************************
+//
+// GrowthError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrowthError {
+    // The Parikh matrix needs a successor for every single letter.
+    MultiLetterPredecessor { length: usize },
+    // Several rules for the same predecessor.
+    NotDeterministic,
+}
+
+impl Display for GrowthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrowthError::MultiLetterPredecessor { length } => {
+                write!(f, "predecessor of {} letters, growth is only known for single letters", length)
+            }
+            GrowthError::NotDeterministic => write!(f, "rules are not deterministic"),
+        }
+    }
+}
+
+impl std::error::Error for GrowthError {}
+
+//***************************************************************************
+//
+// GrowthMatrix
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrowthMatrix<T> {
+    letters: Vec<T>,
+    matrix: Vec<Vec<BigUint>>,
+    axiom: Vec<BigUint>,
+}
+
+impl<T> GrowthMatrix<T>
+where
+    T: Clone + PartialEq + Eq + Hash,
+{
+    pub fn new(axiom: &[T], successors: &HashMap<T, Vec<T>>) -> Self {
+        // Letters in the order they are first reached from the axiom.
+        let mut letters: Vec<T> = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending: Vec<&T> = axiom.iter().rev().collect();
+        while let Some(letter) = pending.pop() {
+            if !seen.insert(letter) {
+                continue;
+            }
+            letters.push(letter.clone());
+            if let Some(successor) = successors.get(letter) {
+                pending.extend(successor.iter().rev());
+            }
+        }
+
+        let index: HashMap<&T, usize> = letters.iter().enumerate().map(|(index, letter)| (letter, index)).collect();
+        let counts = |word: &[T]| {
+            let mut counts = vec![BigUint::zero(); letters.len()];
+            for letter in word {
+                counts[index[letter]] += 1u32;
+            }
+            counts
+        };
+        let matrix = letters.iter()
+            .map(|letter| match successors.get(letter) {
+                Some(successor) => counts(successor),
+                None => counts(std::slice::from_ref(letter)),
+            })
+            .collect();
+        let axiom = counts(axiom);
+
+        GrowthMatrix { letters, matrix, axiom }
+    }
+
+    pub fn from_replacements(axiom: &[T], replacements: &HashMap<Vec<T>, Vec<T>>) -> Result<Self, GrowthError> {
+        let mut successors = HashMap::new();
+        for (predecessor, successor) in replacements {
+            match predecessor.as_slice() {
+                [letter] => successors.insert(letter.clone(), successor.clone()),
+                _ => return Err(GrowthError::MultiLetterPredecessor { length: predecessor.len() }),
+            };
+        }
+        Ok(Self::new(axiom, &successors))
+    }
+
+    pub fn from_grammar(axiom: &[T], grammar: &Grammar<T>) -> Result<Self, GrowthError> {
+        let mut successors = HashMap::new();
+        for rule in grammar.production_rules() {
+            if rule.lhs().len() != 1 {
+                return Err(GrowthError::MultiLetterPredecessor { length: rule.lhs().len() });
+            }
+            let successor: Vec<T> = rule.rhs().iter().cloned().collect();
+            if successors.insert(rule.lhs()[0].clone(), successor).is_some() {
+                return Err(GrowthError::NotDeterministic);
+            }
+        }
+        Ok(Self::new(axiom, &successors))
+    }
+
+    pub fn letters(&self) -> &[T] {
+        &self.letters
+    }
+
+    pub fn matrix(&self) -> &[Vec<BigUint>] {
+        &self.matrix
+    }
+
+    // How often every letter of letters() appears at the given depth.
+    pub fn letter_counts(&self, depth: u64) -> Vec<BigUint> {
+        // Square and multiply, so deep depths only take log(depth) products.
+        let mut counts = self.axiom.clone();
+        let mut power = self.matrix.clone();
+        let mut depth = depth;
+        while depth > 0 {
+            if depth & 1 == 1 {
+                counts = multiply_vector(&counts, &power);
+            }
+            depth >>= 1;
+            if depth > 0 {
+                power = multiply_matrices(&power, &power);
+            }
+        }
+        counts
+    }
+
+    pub fn letter_count(&self, letter: &T, depth: u64) -> BigUint {
+        match self.letters.iter().position(|known| known == letter) {
+            Some(index) => self.letter_counts(depth).swap_remove(index),
+            None => BigUint::zero(),
+        }
+    }
+
+    pub fn length(&self, depth: u64) -> BigUint {
+        self.letter_counts(depth).into_iter().sum()
+    }
+
+    // The dominant eigenvalue of the matrix: the length grows like
+    // growth_rate^n, up to a polynomial factor. A rate of 1 means
+    // polynomial growth, 0 means the word dies out.
+    //
+    // Power iteration on the matrix plus the identity, whose dominant
+    // eigenvalue is the one of the matrix plus one. Adding the identity
+    // makes the iteration converge for periodic matrices as well, though
+    // slowly if the growth has a polynomial factor.
+    pub fn growth_rate(&self) -> f64 {
+        let size = self.letters.len();
+        let matrix: Vec<Vec<f64>> = self.matrix.iter()
+            .map(|row| row.iter().map(|entry| entry.to_f64().unwrap_or(f64::MAX)).collect())
+            .collect();
+
+        let mut vector = vec![1.0; size];
+        let mut rate = 0.0;
+        for _ in 0..10_000 {
+            let mut next = vector.clone();
+            for (row, value) in matrix.iter().zip(&vector) {
+                for (entry, next) in row.iter().zip(&mut next) {
+                    *next += value * entry;
+                }
+            }
+            let norm = next.iter().cloned().fold(0.0, f64::max);
+            if norm == 0.0 {
+                return 0.0;
+            }
+            next.iter_mut().for_each(|value| *value /= norm);
+            vector = next;
+            let converged = (norm - rate).abs() <= 1e-12 * norm;
+            rate = norm;
+            if converged {
+                break;
+            }
+        }
+        (rate - 1.0).max(0.0)
+    }
+}
+
+fn multiply_vector(vector: &[BigUint], matrix: &[Vec<BigUint>]) -> Vec<BigUint> {
+    let mut result = vec![BigUint::zero(); vector.len()];
+    for (row, value) in matrix.iter().zip(vector) {
+        if value.is_zero() {
+            continue;
+        }
+        for (entry, result) in row.iter().zip(&mut result) {
+            *result += value * entry;
+        }
+    }
+    result
+}
+
+fn multiply_matrices(left: &[Vec<BigUint>], right: &[Vec<BigUint>]) -> Vec<Vec<BigUint>> {
+    left.iter().map(|row| multiply_vector(row, right)).collect()
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod growth_matrix {
+        use std::collections::HashMap;
+
+        use num_bigint::BigUint;
+
+        use crate::grammar::{Grammar, ProductionRule};
+        use crate::growth::{GrowthError, GrowthMatrix};
+        use crate::word::Word;
+
+        fn fibonacci() -> GrowthMatrix<char> {
+            let successors: HashMap<char, Vec<char>> = [('a', vec!['a', 'b']), ('b', vec!['a'])].into_iter().collect();
+            GrowthMatrix::new(&['a'], &successors)
+        }
+
+        #[test]
+        fn fibonacci_lengths() {
+            let growth = fibonacci();
+            let (mut previous, mut current) = (BigUint::from(1u32), BigUint::from(1u32));
+            for depth in 0..200 {
+                assert_eq!(growth.length(depth), current);
+                let next = &previous + &current;
+                previous = current;
+                current = next;
+            }
+            assert!((growth.growth_rate() - (1.0 + 5f64.sqrt()) / 2.0).abs() < 1e-9);
+        }
+
+        #[test]
+        fn only_reachable_letters() {
+            let successors: HashMap<char, Vec<char>> = [('a', vec!['a', 'b']), ('c', vec!['c', 'c', 'c'])].into_iter().collect();
+            let growth = GrowthMatrix::new(&['a'], &successors);
+            assert_eq!(growth.letters(), &['a', 'b']);
+            assert_eq!(growth.letter_count(&'b', 10), BigUint::from(10u32));
+            assert_eq!(growth.letter_count(&'c', 10), BigUint::from(0u32));
+            assert!((growth.growth_rate() - 1.0).abs() < 1e-3);
+        }
+
+        #[test]
+        fn dying_word() {
+            let successors: HashMap<char, Vec<char>> = [('a', vec![])].into_iter().collect();
+            let growth = GrowthMatrix::new(&['a', 'a'], &successors);
+            assert_eq!(growth.length(0), BigUint::from(2u32));
+            assert_eq!(growth.length(1), BigUint::from(0u32));
+            assert_eq!(growth.growth_rate(), 0.0);
+        }
+
+        #[test]
+        fn reject_multi_letter_predecessors() {
+            let replacements: HashMap<Vec<char>, Vec<char>> = [(vec!['a', 'b'], vec!['a'])].into_iter().collect();
+            assert_eq!(
+                GrowthMatrix::from_replacements(&['a'], &replacements),
+                Err(GrowthError::MultiLetterPredecessor { length: 2 }),
+            );
+        }
+
+        #[test]
+        fn from_grammar() {
+            let rules = vec![
+                ProductionRule::new(Word::from("F"), Word::from("F+F")),
+                ProductionRule::new(Word::from("F"), Word::from("F-F")).with_weight(1.0),
+            ];
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F']).with_production_rules(rules).unwrap();
+            assert_eq!(GrowthMatrix::from_grammar(&['F'], &grammar), Err(GrowthError::NotDeterministic));
+
+            let rules = vec![ProductionRule::new(Word::from("F"), Word::from("F+F"))];
+            let grammar = Grammar::new(vec!['+', '-'], vec!['F']).with_production_rules(rules).unwrap();
+            let growth = GrowthMatrix::from_grammar(&['F'], &grammar).unwrap();
+            assert_eq!(growth.length(3), BigUint::from(15u32));
+        }
+    }
+
+    mod fractal {
+        use num_bigint::BigUint;
+
+        use crate::fractal::{self, Koch};
+
+        #[test]
+        fn koch() {
+            let mut koch = fractal::koch(String::new());
+            let growth = koch.growth().unwrap();
+            for depth in 0..6 {
+                assert_eq!(growth.length(depth), BigUint::from(koch.iteration(depth as usize).unwrap().len()));
+            }
+            assert_eq!(growth.letter_count(&Koch::Forward, 40), BigUint::from(3u32) * BigUint::from(4u32).pow(40));
+            assert!((growth.growth_rate() - 4.0).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::limits::LimitError;
+use crate::tryout::{Letter, LindenmayerSystem};
+
+// Text shown on top of the fractal in the viewer. Only the contents are
+// computed here, main.rs draws the lines with the font addon.
+
+//***************************************************************************
+//
+// FrameTimer
+//
+//***************************************************************************
+
+// Averages the duration of the last few frames, so the shown numbers do
+// not flicker from frame to frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameTimer {
+    frames: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl FrameTimer {
+    pub fn new(capacity: usize) -> Self {
+        FrameTimer {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&mut self, frame: Duration) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        Some(self.frames.iter().sum::<Duration>() / self.frames.len() as u32)
+    }
+
+    pub fn frames_per_second(&self) -> Option<f64> {
+        self.average()
+            .filter(|average| !average.is_zero())
+            .map(|average| 1.0 / average.as_secs_f64())
+    }
+}
+
+//***************************************************************************
+//
+// Hud
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hud {
+    name: String,
+    depth: usize,
+    word_length: Option<usize>,
+    vertex_count: Option<usize>,
+    frame_time: Option<Duration>,
+    frames_per_second: Option<f64>,
+    progress: Option<(usize, usize)>,
+    limit: Option<String>,
+}
+
+impl Hud {
+    // Collects what is known about the given depth of the fractal. Depths
+    // that have not been computed yet are shown as pending.
+    pub fn new<L: Letter>(fractal: &LindenmayerSystem<L>, depth: usize, frame_timer: &FrameTimer) -> Self {
+        let name = match fractal.name() {
+            "" => String::from("unnamed"),
+            name => name.to_owned(),
+        };
+        Hud {
+            name,
+            depth,
+            word_length: fractal.get_word_stack_at(depth).map(|word| word.len()),
+            vertex_count: fractal.get_vertex_stack_at(depth).map(|vertices| vertices.iter().flatten().count()),
+            frame_time: frame_timer.average(),
+            frames_per_second: frame_timer.frames_per_second(),
+            progress: None,
+            limit: None,
+        }
+    }
+
+    // Depth currently computed in the background and the depth requested.
+    pub fn with_progress(mut self, progress: Option<(usize, usize)>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    // Why the depth could not be increased any further.
+    pub fn with_limit_error(mut self, limit_error: Option<&LimitError>) -> Self {
+        self.limit = limit_error.map(|error| error.to_string());
+        self
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let pending = || String::from("...");
+        let frame = match (self.frame_time, self.frames_per_second) {
+            (Some(frame_time), Some(frames_per_second)) => {
+                format!("{:.1} ms ({:.0} fps)", frame_time.as_secs_f64() * 1000.0, frames_per_second)
+            }
+            _ => pending(),
+        };
+        let mut lines = vec![
+            format!("fractal:  {}", self.name),
+            format!("depth:    {}", self.depth),
+            format!("letters:  {}", self.word_length.map_or_else(pending, |length| length.to_string())),
+            format!("vertices: {}", self.vertex_count.map_or_else(pending, |count| count.to_string())),
+            format!("frame:    {}", frame),
+        ];
+        if let Some((depth, target_depth)) = self.progress {
+            lines.push(format!("working:  depth {} of {}", depth, target_depth));
+        }
+        if let Some(limit) = &self.limit {
+            lines.push(format!("limit:    {}", limit));
+        }
+        lines
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod frame_timer {
+        use std::time::Duration;
+        use crate::hud::FrameTimer;
+
+        #[test]
+        fn average_of_the_last_frames() {
+            let mut frame_timer = FrameTimer::new(2);
+            assert_eq!(frame_timer.average(), None);
+            frame_timer.record(Duration::from_millis(100));
+            frame_timer.record(Duration::from_millis(20));
+            frame_timer.record(Duration::from_millis(30));
+            assert_eq!(frame_timer.average(), Some(Duration::from_millis(25)));
+            assert_eq!(frame_timer.frames_per_second(), Some(40.0));
+        }
+    }
+
+    mod hud {
+        use std::time::Duration;
+        use crate::hud::{FrameTimer, Hud};
+        use crate::limits::LimitError;
+        use crate::tryout::LindenmayerSystem;
+
+        #[test]
+        fn lines() {
+            let mut koch = LindenmayerSystem::koch();
+            koch.update_vertex_stack(1);
+            let mut frame_timer = FrameTimer::new(10);
+            frame_timer.record(Duration::from_millis(16));
+
+            let lines = Hud::new(&koch, 1, &frame_timer).lines();
+            assert_eq!(lines[0], "fractal:  koch");
+            assert_eq!(lines[1], "depth:    1");
+            assert_eq!(lines[2], "letters:  28");
+            assert_eq!(lines[3], "vertices: 13");
+            assert_eq!(lines[4], "frame:    16.0 ms (62 fps)");
+
+            let lines = Hud::new(&koch, 2, &FrameTimer::new(10)).lines();
+            assert_eq!(lines[3], "vertices: ...");
+            assert_eq!(lines[4], "frame:    ...");
+            assert_eq!(lines.len(), 5);
+
+            let lines = Hud::new(&koch, 1, &frame_timer).with_progress(Some((3, 5))).lines();
+            assert_eq!(lines[5], "working:  depth 3 of 5");
+
+            let error = LimitError::WordLength { depth: 2, length: 112, limit: 100 };
+            let lines = Hud::new(&koch, 1, &frame_timer).with_limit_error(Some(&error)).lines();
+            assert_eq!(lines[5], "limit:    depth 2 needs 112 letters, the limit is 100");
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// The fractals, their grammars and everything to derive, analyse, save and
+// render them. The window in main.rs and the command line mode in
+// bin/render.rs are built on top of it.
+
+pub mod word;
+pub mod grammar;
+pub mod grammar_file;
+pub mod grammar_analysis;
+pub mod fractint;
+pub mod svg;
+pub mod raster;
+pub mod cli;
+pub mod gallery;
+pub mod hud;
+pub mod worker;
+pub mod derivation;
+pub mod shared_word;
+pub mod semantics;
+pub mod dictionary;
+pub mod fractal;
+pub mod word_slice;
+pub mod matcher;
+pub mod alphabet;
+pub mod growth;
+pub mod limits;
+pub mod actions;
+
+pub mod coordinates;
+pub mod parametric;
+//mod common_fractals;
+pub mod tryout;
+//mod letter;
+pub mod check_this;
+
+pub const DISPLAY_WIDTH: i32 = 1900;
+pub const DISPLAY_HEIGHT: i32 = 1080;
+pub const S: f32 = 300.0;
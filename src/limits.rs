@@ -0,0 +1,166 @@
+use std::fmt::Display;
+
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+
+// Limits on how far a derivation may go. Words grow exponentially, so a
+// single depth too many can ask for more memory than the machine has.
+// The limits are checked before deriving, with the sizes predicted from
+// the growth of the rules, so exceeding them never allocates anything.
+
+//***************************************************************************
+//
+// LimitError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    WordLength { depth: usize, length: u128, limit: usize },
+    // Bytes taken by all words (and vertices) up to the depth.
+    Memory { depth: usize, bytes: u128, limit: usize },
+    Vertices { depth: usize, count: u128, limit: usize },
+}
+
+impl LimitError {
+    pub fn depth(&self) -> usize {
+        match self {
+            LimitError::WordLength { depth, .. } => *depth,
+            LimitError::Memory { depth, .. } => *depth,
+            LimitError::Vertices { depth, .. } => *depth,
+        }
+    }
+}
+
+impl Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitError::WordLength { depth, length, limit } => {
+                write!(f, "depth {} needs {} letters, the limit is {}", depth, length, limit)
+            }
+            LimitError::Memory { depth, bytes, limit } => {
+                write!(f, "depth {} needs {} bytes, the limit is {}", depth, bytes, limit)
+            }
+            LimitError::Vertices { depth, count, limit } => {
+                write!(f, "depth {} needs {} vertices, the limit is {}", depth, count, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+//***************************************************************************
+//
+// DerivationLimits
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DerivationLimits {
+    max_word_length: Option<usize>,
+    max_memory: Option<usize>,
+    max_vertices: Option<usize>,
+}
+
+impl DerivationLimits {
+    // Generous enough for every built-in fractal at its maximal depth in
+    // the viewer, small enough to stay far away from swapping.
+    pub fn new() -> Self {
+        DerivationLimits {
+            max_word_length: Some(100_000_000),
+            max_memory: Some(1 << 30),
+            max_vertices: Some(50_000_000),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        DerivationLimits {
+            max_word_length: None,
+            max_memory: None,
+            max_vertices: None,
+        }
+    }
+
+    pub fn with_max_word_length(mut self, max_word_length: usize) -> Self {
+        self.max_word_length = Some(max_word_length);
+        self
+    }
+
+    // In bytes.
+    pub fn with_max_memory(mut self, max_memory: usize) -> Self {
+        self.max_memory = Some(max_memory);
+        self
+    }
+
+    pub fn with_max_vertices(mut self, max_vertices: usize) -> Self {
+        self.max_vertices = Some(max_vertices);
+        self
+    }
+
+    pub fn max_word_length(&self) -> Option<usize> {
+        self.max_word_length
+    }
+
+    pub fn max_memory(&self) -> Option<usize> {
+        self.max_memory
+    }
+
+    pub fn max_vertices(&self) -> Option<usize> {
+        self.max_vertices
+    }
+
+    pub fn check_word_length(&self, depth: usize, length: u128) -> Result<(), LimitError> {
+        match self.max_word_length {
+            Some(limit) if length > limit as u128 => Err(LimitError::WordLength { depth, length, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_memory(&self, depth: usize, bytes: u128) -> Result<(), LimitError> {
+        match self.max_memory {
+            Some(limit) if bytes > limit as u128 => Err(LimitError::Memory { depth, bytes, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_vertices(&self, depth: usize, count: u128) -> Result<(), LimitError> {
+        match self.max_vertices {
+            Some(limit) if count > limit as u128 => Err(LimitError::Vertices { depth, count, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for DerivationLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Predicted sizes are big integers, anything beyond u128 exceeds every limit anyway.
+pub fn saturating_u128(number: &BigUint) -> u128 {
+    number.to_u128().unwrap_or(u128::MAX)
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod derivation_limits {
+        use crate::limits::{DerivationLimits, LimitError};
+
+        #[test]
+        fn check() {
+            let limits = DerivationLimits::unlimited().with_max_word_length(10).with_max_vertices(5);
+            assert_eq!(limits.check_word_length(3, 10), Ok(()));
+            assert_eq!(limits.check_word_length(3, 11), Err(LimitError::WordLength { depth: 3, length: 11, limit: 10 }));
+            assert_eq!(limits.check_memory(3, u128::MAX), Ok(()));
+            assert_eq!(
+                limits.check_vertices(4, 6).unwrap_err().to_string(),
+                "depth 4 needs 6 vertices, the limit is 5",
+            );
+        }
+    }
+}
@@ -1,34 +1,27 @@
 extern crate allegro;
 
-mod word;
-mod grammar;
-//mod semantics;
-//mod dictionary;
-//mod fractal;
-//mod word_slice;
-
-mod coordinates;
-//mod common_fractals;
-//mod tryout;
-//mod letter;
+use std::time::Instant;
 
 use allegro::*;
 use allegro_primitives::*;
+use allegro_font::*;
 
-use coordinates::MathPosition;
-use coordinates::ScreenPosition;
+use fractal_generator_rust_allegro::coordinates::MathPosition;
+use fractal_generator_rust_allegro::coordinates::ScreenPosition;
+use fractal_generator_rust_allegro::coordinates::Viewport;
+use fractal_generator_rust_allegro::gallery::Gallery;
+use fractal_generator_rust_allegro::hud::{FrameTimer, Hud};
+use fractal_generator_rust_allegro::{DISPLAY_WIDTH, DISPLAY_HEIGHT};
 
-mod check_this;
-use check_this::*;
+use fractal_generator_rust_allegro::check_this::*;
 
-use word::Word;
+use fractal_generator_rust_allegro::word::Word;
 
 //use common_fractals::*;
 //use tryout::*;
 
-const DISPLAY_WIDTH: i32 = 1900;
-const DISPLAY_HEIGHT: i32 = 1080;
-const S: f32 = 300.0;
+// Zoom factor of a single step of the mouse wheel.
+const ZOOM_STEP: f32 = 1.1;
 
 //pub struct LindenmayerFractal<Op: Operation + Replacement> {
 //    starting_word: Vec<Op>,
@@ -351,21 +344,21 @@ const S: f32 = 300.0;
 //    return vertex_buffer;
 //}
 
-pub fn draw_polygon(primitives: &PrimitivesAddon, vertices: &[MathPosition], color: Color) {
+pub fn draw_polygon(primitives: &PrimitivesAddon, viewport: &Viewport, vertices: &[MathPosition], color: Color) {
     let vertices: Vec<(f32, f32)> = vertices.iter()
         .map(|pos| {
-            ScreenPosition::from(pos).into()
+            viewport.math_to_screen(pos).into()
         })
         .collect();
     primitives.draw_polygon(&vertices, LineJoinType::Round, color, 2.0, 1.0);
 }
 
-pub fn draw_single_lines(primitives: &PrimitivesAddon, vertices: &[Option<MathPosition>], color: Color) {
+pub fn draw_single_lines(primitives: &PrimitivesAddon, viewport: &Viewport, vertices: &[Option<MathPosition>], color: Color) {
     let (red, green, blue) = color.to_rgb_f();
     let vertices: Vec<Option<(f32, f32)>> = vertices.iter()
         .map(|pos| {
             match pos {
-                Some(pos) => Some(ScreenPosition::from(pos).into()),
+                Some(pos) => Some(viewport.math_to_screen(pos).into()),
                 None => None,
             }
         })
@@ -387,9 +380,17 @@ allegro_main!
     } else {
         println!("Keyboard could not be installed!");
     }
+    if core.install_mouse().is_ok() {
+        println!("Mouse successfully installed!");
+    } else {
+        println!("Mouse could not be installed!");
+    }
     let primitives = PrimitivesAddon::init(&core).unwrap();
+    let font_addon = FontAddon::init(&core).unwrap();
+    let font = Font::new_builtin(&font_addon).unwrap();
 
     let display = Display::new(&core, DISPLAY_WIDTH, DISPLAY_HEIGHT).unwrap();
+    let mut viewport = Viewport::new(display.get_width(), display.get_height());
     let timer = Timer::new(&core, 1.0 / 60.0).unwrap();
     let queue = EventQueue::new(&core).unwrap();
 
@@ -447,19 +448,15 @@ allegro_main!
     // let iterated_operations = iterate_fractal(&base_operations, 15);
     // let vertex_iterations = iterated_vertices(&iterated_operations[..]);
     
-    //let mut fractals = vec![
-    //    LindenmayerSystem::koch(),
-    //    LindenmayerSystem::levy(),
-    //    LindenmayerSystem::dragon_curve(),
-    //    LindenmayerSystem::first_plant(),
-    //];
-
-    //let mut current_fractal = 0;
-    //fractals[current_fractal].update_vertex_stack(8);
+    let mut gallery = Gallery::built_in();
 
     queue.register_event_source(display.get_event_source());
     queue.register_event_source(timer.get_event_source());
     queue.register_event_source(core.get_keyboard_event_source().unwrap());
+    queue.register_event_source(core.get_mouse_event_source().unwrap());
+
+    // Screen position where the left mouse button went down, while dragging.
+    let mut drag_start: Option<(i32, i32)> = None;
 
     let first_word: Word<MyFirstLetter> = Word::from(
         &[
@@ -479,6 +476,8 @@ allegro_main!
     println!("{}", first_word);
     println!("{}", second_word);
 
+    let mut frame_timer = FrameTimer::new(60);
+    let mut last_frame = Instant::now();
     let mut redraw = true;
     timer.start();
 
@@ -486,12 +485,24 @@ allegro_main!
         if redraw && queue.is_empty()
         {
             core.clear_to_color(Color::from_rgb_f(0.1, 0.1, 0.1));
-            // draw_single_lines(&primitives, &vertex_iterations[current_depth], Color::from_rgb_f(0.7, 0.9, 0.7));
-            //if let Some(vertices) = fractals[current_fractal].get_vertex_stack_at(current_depth) {
-            //    draw_single_lines(&primitives, &vertices[..], Color::from_rgb_f(0.5, 0.9, 0.7));
-            //}
+            // draw_single_lines(&primitives, &viewport, &vertex_iterations[current_depth], Color::from_rgb_f(0.7, 0.9, 0.7));
+            gallery.update();
+            if let Some(vertices) = gallery.vertices() {
+                draw_single_lines(&primitives, &viewport, &vertices[..], Color::from_rgb_f(0.5, 0.9, 0.7));
+            }
+            if let Some(fractal) = gallery.current_fractal() {
+                let hud = Hud::new(fractal, gallery.displayed_depth(), &frame_timer)
+                    .with_progress(gallery.progress())
+                    .with_limit_error(gallery.limit_error());
+                for (index, line) in hud.lines().iter().enumerate() {
+                    let y = 10.0 + (index as i32 * font.get_line_height()) as f32;
+                    core.draw_text(&font, Color::from_rgb_f(0.9, 0.9, 0.9), 10.0, y, FontAlign::Left, line);
+                }
+            }
 
             core.flip_display();
+            frame_timer.record(last_frame.elapsed());
+            last_frame = Instant::now();
             redraw = false;
         }
 
@@ -499,6 +510,17 @@ allegro_main!
         {
             DisplayClose{..} => break 'exit,
             TimerTick{..} => redraw = true,
+            MouseButtonDown{x, y, button: 1, ..} => drag_start = Some((x, y)),
+            MouseButtonUp{button: 1, ..} => drag_start = None,
+            MouseAxes{x, y, dz, ..} => {
+                if let Some((start_x, start_y)) = drag_start {
+                    viewport.pan((x - start_x) as f32, (y - start_y) as f32);
+                    drag_start = Some((x, y));
+                }
+                if dz != 0 {
+                    viewport.zoom_at(&ScreenPosition::from((x as f32, y as f32)), ZOOM_STEP.powi(dz));
+                }
+            },
             //KeyDown{source, timestamp, keycode, display} if keycode == KeyCode::F => {
             //    if current_word < starting_words.len()-1 {
             //        current_word += 1;
@@ -515,29 +537,15 @@ allegro_main!
             //    iterated_operations = iterate_fractal(&base_operations, 10);
             //    vertex_iterations = iterated_vertices(&iterated_operations[..]);
             //},
-            //KeyDown{source: _, timestamp: _, keycode, ..} => {
-            //    match keycode {
-            //        KeyCode::I => {
-            //            println!("Key: I");
-            //            if current_depth < 8 {
-            //                current_depth += 1;
-            //            }
-            //        },
-            //        KeyCode::P => {
-            //            println!("Key: P");
-            //            if current_depth > 0 {
-            //                current_depth -= 1;
-            //            }
-            //        },
-            //        KeyCode::C => {
-            //            println!("Key: C");
-            //            current_fractal += 1;
-            //            current_fractal = current_fractal.checked_rem_euclid(fractals.len()).unwrap();
-            //            fractals[current_fractal].update_vertex_stack(8);
-            //        },
-            //        _ => (),
-            //    }
-            //},
+            KeyDown{keycode, ..} => {
+                match keycode {
+                    KeyCode::I => gallery.increase_depth(),
+                    KeyCode::P => gallery.decrease_depth(),
+                    KeyCode::C => gallery.next_fractal(),
+                    KeyCode::R => viewport.reset(),
+                    _ => (),
+                }
+            },
             _ => (),
         }
     }
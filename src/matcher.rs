@@ -0,0 +1,366 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+// Aho-Corasick automaton over the left hand sides of a rule set. It is
+// built once and then finds, for every position of a word, the longest
+// rule key starting there in a single pass over the word, instead of
+// trying every key at every position.
+//
+// Greedy rewriting then walks these positions from left to right: take
+// the longest key at the current position and jump behind it, or copy the
+// letter if no key starts there. This is the leftmost-longest semantics
+// of Word::first_subword and Word::subwords.
+
+struct Node<T> {
+    children: HashMap<T, usize>,
+    // Length of the key ending exactly in this node.
+    key_length: Option<usize>,
+    fail: usize,
+    // The next node along the failure links that ends a key.
+    output: Option<usize>,
+}
+
+impl<T> Node<T> {
+    fn new() -> Self {
+        Node {
+            children: HashMap::new(),
+            key_length: None,
+            fail: 0,
+            output: None,
+        }
+    }
+}
+
+pub struct SubwordMatcher<T> {
+    nodes: Vec<Node<T>>,
+    // How far behind the scanned letters the longest key of a position is known.
+    max_key_length: usize,
+}
+
+impl<T> SubwordMatcher<T>
+where
+    T: Clone + PartialEq + Eq + Hash,
+{
+    // Empty keys never match, as in Word::first_subword.
+    pub fn new<'a, I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [T]>,
+        T: 'a,
+    {
+        let mut nodes = vec![Node::new()];
+        let mut max_key_length = 0;
+        for key in keys {
+            if key.is_empty() {
+                continue;
+            }
+            let mut node = 0;
+            for letter in key {
+                node = match nodes[node].children.get(letter) {
+                    Some(&child) => child,
+                    None => {
+                        nodes.push(Node::new());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.insert(letter.clone(), child);
+                        child
+                    }
+                };
+            }
+            nodes[node].key_length = Some(key.len());
+            max_key_length = max_key_length.max(key.len());
+        }
+
+        // Failure links point to the node of the longest proper suffix that
+        // is also a prefix of some key, computed breadth first.
+        let mut queue: VecDeque<usize> = nodes[0].children.values().copied().collect();
+        while let Some(node) = queue.pop_front() {
+            let children: Vec<(T, usize)> = nodes[node].children.iter()
+                .map(|(letter, child)| (letter.clone(), *child))
+                .collect();
+            for (letter, child) in children {
+                let mut fail = nodes[node].fail;
+                let fail = loop {
+                    if let Some(&next) = nodes[fail].children.get(&letter) {
+                        break next;
+                    }
+                    if fail == 0 {
+                        break 0;
+                    }
+                    fail = nodes[fail].fail;
+                };
+                nodes[child].fail = fail;
+                nodes[child].output = if nodes[fail].key_length.is_some() { Some(fail) } else { nodes[fail].output };
+                queue.push_back(child);
+            }
+        }
+
+        SubwordMatcher { nodes, max_key_length }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    // For every position of the word, the length of the longest key starting there.
+    pub fn longest_keys<'w>(&self, word: &'w [T]) -> LongestKeys<'_, 'w, T> {
+        LongestKeys {
+            matcher: self,
+            word,
+            node: 0,
+            scanned: 0,
+            position: 0,
+            window: VecDeque::with_capacity(self.max_key_length + 1),
+        }
+    }
+
+    // The longest key the word starts with.
+    pub fn first_subword<'w>(&self, word: &'w [T]) -> Option<&'w [T]> {
+        let mut node = 0;
+        let mut longest = None;
+        for (index, letter) in word.iter().enumerate() {
+            match self.nodes[node].children.get(letter) {
+                Some(&next) => node = next,
+                None => break,
+            }
+            if self.nodes[node].key_length.is_some() {
+                longest = Some(&word[..=index]);
+            }
+        }
+        longest
+    }
+
+    // Splits the word into keys from the start on, until a position
+    // is reached where no key starts.
+    pub fn subwords<'m, 'w>(&'m self, word: &'w [T]) -> impl Iterator<Item = &'w [T]> + 'm
+    where
+        'w: 'm,
+    {
+        self.segments(word).map_while(Result::ok)
+    }
+
+    // Splits the whole word from left to right into the longest keys,
+    // with Err for every single letter where no key starts.
+    pub fn segments<'w>(&self, word: &'w [T]) -> Segments<'_, 'w, T> {
+        Segments {
+            longest_keys: self.longest_keys(word),
+        }
+    }
+
+    // Rewrites the word from left to right: the longest key at every position
+    // is handed to rewrite_key, which appends whatever replaces it, and letters
+    // where no key starts are copied. Every rewriting by subwords goes through
+    // here, so they all share the leftmost-longest semantics.
+    pub fn rewrite<F>(&self, word: &[T], mut rewrite_key: F) -> Vec<T>
+    where
+        F: FnMut(&[T], &mut Vec<T>),
+    {
+        let mut result = Vec::with_capacity(word.len());
+        for segment in self.segments(word) {
+            match segment {
+                Ok(key) => rewrite_key(key, &mut result),
+                Err(letter) => result.push(letter.clone()),
+            }
+        }
+        result
+    }
+
+    fn next_node(&self, mut node: usize, letter: &T) -> usize {
+        loop {
+            if let Some(&next) = self.nodes[node].children.get(letter) {
+                return next;
+            }
+            if node == 0 {
+                return 0;
+            }
+            node = self.nodes[node].fail;
+        }
+    }
+}
+
+//***************************************************************************
+//
+// LongestKeys
+//
+//***************************************************************************
+
+// The longest key starting at a position is only known once the automaton
+// is past the longest key that could start there, so the letters are
+// scanned that far ahead and only those positions are kept.
+pub struct LongestKeys<'m, 'w, T> {
+    matcher: &'m SubwordMatcher<T>,
+    word: &'w [T],
+    node: usize,
+    // Number of letters fed to the automaton.
+    scanned: usize,
+    // The next position to report.
+    position: usize,
+    // The longest keys of the positions from position up to scanned.
+    window: VecDeque<Option<usize>>,
+}
+
+impl<T> LongestKeys<'_, '_, T>
+where
+    T: Clone + PartialEq + Eq + Hash,
+{
+    // The longest key starting at the given position. Positions have to be
+    // asked for in increasing order, the ones in between are skipped.
+    pub fn at(&mut self, position: usize) -> Option<usize> {
+        assert!(position >= self.position, "position {} was already passed", position);
+        self.nth(position - self.position).flatten()
+    }
+
+    fn scan(&mut self) {
+        let end = self.scanned;
+        self.node = self.matcher.next_node(self.node, &self.word[end]);
+        self.window.push_back(None);
+
+        // Every key ending here starts behind the reported positions,
+        // since those were only reported once all their keys had ended.
+        let mut matched = Some(self.node);
+        while let Some(found) = matched {
+            if let Some(length) = self.matcher.nodes[found].key_length {
+                let longest = &mut self.window[end + 1 - length - self.position];
+                if *longest < Some(length) {
+                    *longest = Some(length);
+                }
+            }
+            matched = self.matcher.nodes[found].output;
+        }
+        self.scanned += 1;
+    }
+}
+
+impl<T> Iterator for LongestKeys<'_, '_, T>
+where
+    T: Clone + PartialEq + Eq + Hash,
+{
+    type Item = Option<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.word.len() {
+            return None;
+        }
+        let horizon = self.word.len().min(self.position + self.matcher.max_key_length.max(1));
+        while self.scanned < horizon {
+            self.scan();
+        }
+        self.position += 1;
+        self.window.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.word.len() - self.position;
+        (remaining, Some(remaining))
+    }
+}
+
+//***************************************************************************
+//
+// Segments
+//
+//***************************************************************************
+
+pub struct Segments<'m, 'w, T> {
+    longest_keys: LongestKeys<'m, 'w, T>,
+}
+
+impl<'w, T> Iterator for Segments<'_, 'w, T>
+where
+    T: Clone + PartialEq + Eq + Hash,
+{
+    type Item = Result<&'w [T], &'w T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.longest_keys.position;
+        let word = self.longest_keys.word;
+        match self.longest_keys.next()? {
+            Some(length) => {
+                if length > 1 {
+                    self.longest_keys.at(start + length - 1);
+                }
+                Some(Ok(&word[start..start + length]))
+            }
+            None => Some(Err(&word[start])),
+        }
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod longest_keys {
+        use crate::matcher::SubwordMatcher;
+
+        #[test]
+        fn overlapping_keys() {
+            let keys: Vec<&[char]> = vec![&['a', 'b'], &['b', 'c', 'd'], &['c'], &['a', 'b', 'c', 'd', 'e']];
+            let matcher = SubwordMatcher::new(keys);
+            let word: Vec<char> = "abcdx".chars().collect();
+            assert_eq!(matcher.longest_keys(&word).collect::<Vec<_>>(), vec![Some(2), Some(3), Some(1), None, None]);
+        }
+
+        #[test]
+        fn no_keys() {
+            let matcher = SubwordMatcher::<i32>::new(vec![&[][..]]);
+            assert!(matcher.is_empty());
+            assert_eq!(matcher.longest_keys(&[1, 2]).collect::<Vec<_>>(), vec![None, None]);
+        }
+
+        #[test]
+        fn skip_to_position() {
+            let keys: Vec<&[char]> = vec![&['a', 'b'], &['b', 'c', 'd'], &['c']];
+            let matcher = SubwordMatcher::new(keys);
+            let word: Vec<char> = "abcdabc".chars().collect();
+            let mut longest_keys = matcher.longest_keys(&word);
+            assert_eq!(longest_keys.at(1), Some(3));
+            assert_eq!(longest_keys.at(4), Some(2));
+            assert_eq!(longest_keys.at(6), Some(1));
+            assert_eq!(longest_keys.next(), None);
+        }
+    }
+
+    mod same_as_greedy {
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha8Rng;
+
+        use crate::matcher::SubwordMatcher;
+        use crate::word_slice::Word;
+
+        #[test]
+        fn random_words_and_keys() {
+            let mut rng = ChaCha8Rng::seed_from_u64(7);
+            for _ in 0..200 {
+                let keys: Vec<Vec<u8>> = (0..rng.gen_range(1..6))
+                    .map(|_| (0..rng.gen_range(0..4)).map(|_| rng.gen_range(0..3)).collect())
+                    .collect();
+                let word: Vec<u8> = (0..rng.gen_range(0..30)).map(|_| rng.gen_range(0..3)).collect();
+                let valid_subwords: Vec<&[u8]> = keys.iter().map(|key| &key[..]).collect();
+                let matcher = SubwordMatcher::new(valid_subwords.iter().copied());
+
+                for start in 0..word.len() {
+                    assert_eq!(matcher.first_subword(&word[start..]), word[start..].first_subword(&valid_subwords));
+                }
+
+                let mut greedy = Vec::new();
+                let mut start = 0;
+                while let Some(subword) = word[start..].first_subword(&valid_subwords) {
+                    greedy.push(subword);
+                    start += subword.len();
+                }
+                assert_eq!(matcher.subwords(&word).collect::<Vec<_>>(), greedy);
+
+                let mut segments = Vec::new();
+                let mut start = 0;
+                while start < word.len() {
+                    match word[start..].first_subword(&valid_subwords) {
+                        Some(subword) => segments.push(Ok(subword)),
+                        None => segments.push(Err(&word[start])),
+                    }
+                    start += segments.last().unwrap().map_or(1, |subword| subword.len());
+                }
+                assert_eq!(matcher.segments(&word).collect::<Vec<_>>(), segments);
+            }
+        }
+    }
+}
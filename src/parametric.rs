@@ -0,0 +1,646 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::check_this::Parametric;
+use crate::coordinates::MathPosition;
+use crate::tryout::LindenmayerPayload;
+
+//***************************************************************************
+//
+// ParametricError
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParametricError {
+    UnexpectedCharacter { position: usize, found: char },
+    UnexpectedEnd,
+    UnknownVariable(String),
+    InvalidLetter { symbol: char, parameters: Vec<f32> },
+    MissingArrow,
+    // Predecessors bind parameters to names, `F(x*2) -> F(x)` has nothing to bind.
+    NonVariableParameter { position: usize, parameter: String },
+}
+
+impl Display for ParametricError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParametricError::UnexpectedCharacter { position, found } => {
+                write!(f, "unexpected character '{}' at position {}", found, position)
+            }
+            ParametricError::UnexpectedEnd => {
+                write!(f, "unexpected end of input")
+            }
+            ParametricError::UnknownVariable(name) => {
+                write!(f, "unknown variable '{}'", name)
+            }
+            ParametricError::InvalidLetter { symbol, parameters } => {
+                write!(f, "no letter '{}' with parameters {:?}", symbol, parameters)
+            }
+            ParametricError::MissingArrow => {
+                write!(f, "rule is missing the '->' between predecessor and successor")
+            }
+            ParametricError::NonVariableParameter { position, parameter } => {
+                write!(f, "predecessor parameter '{}' at position {} is not a variable name", parameter, position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParametricError {}
+
+//***************************************************************************
+//
+// Expression
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+// Arithmetic and boolean expressions over the parameters of a letter.
+// Booleans are represented as 1.0 (true) and 0.0 (false), so guards
+// and parameter values can share a single expression type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f32),
+    Variable(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Self, ParametricError> {
+        let mut parser = Parser::new(input);
+        let expression = parser.expression()?;
+        parser.expect_end()?;
+        Ok(expression)
+    }
+
+    pub fn evaluate(&self, bindings: &HashMap<String, f32>) -> Result<f32, ParametricError> {
+        let value = match self {
+            Expression::Number(number) => *number,
+            Expression::Variable(name) => {
+                *bindings.get(name).ok_or_else(|| ParametricError::UnknownVariable(name.clone()))?
+            }
+            Expression::Negate(expression) => -expression.evaluate(bindings)?,
+            Expression::Not(expression) => truth(!expression.is_satisfied(bindings)?),
+            Expression::Binary(lhs, operator, rhs) => {
+                let lhs = lhs.evaluate(bindings)?;
+                let rhs = rhs.evaluate(bindings)?;
+                match operator {
+                    Operator::Add => lhs + rhs,
+                    Operator::Subtract => lhs - rhs,
+                    Operator::Multiply => lhs * rhs,
+                    Operator::Divide => lhs / rhs,
+                    Operator::Power => lhs.powf(rhs),
+                    Operator::Less => truth(lhs < rhs),
+                    Operator::LessEqual => truth(lhs <= rhs),
+                    Operator::Greater => truth(lhs > rhs),
+                    Operator::GreaterEqual => truth(lhs >= rhs),
+                    Operator::Equal => truth(lhs == rhs),
+                    Operator::NotEqual => truth(lhs != rhs),
+                    Operator::And => truth(lhs != 0.0 && rhs != 0.0),
+                    Operator::Or => truth(lhs != 0.0 || rhs != 0.0),
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    pub fn is_satisfied(&self, bindings: &HashMap<String, f32>) -> Result<bool, ParametricError> {
+        Ok(self.evaluate(bindings)? != 0.0)
+    }
+}
+
+fn truth(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+//***************************************************************************
+//
+// Parser
+//
+//***************************************************************************
+
+// Recursive descent parser, from the lowest to the highest precedence:
+// ||, &&, comparisons, + and -, * and /, ^ (right associative), unary ! and -.
+struct Parser {
+    input: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Self {
+        Parser {
+            input: input.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while let Some(c) = self.input.get(self.position) {
+            if !c.is_whitespace() {
+                return Some(*c);
+            }
+            self.position += 1;
+        }
+        None
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.peek();
+        let token: Vec<char> = token.chars().collect();
+        if self.input[self.position..].starts_with(&token) {
+            self.position += token.len();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), ParametricError> {
+        match self.peek() {
+            Some(c) if c == token => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(found) => Err(ParametricError::UnexpectedCharacter { position: self.position, found }),
+            None => Err(ParametricError::UnexpectedEnd),
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), ParametricError> {
+        match self.peek() {
+            Some(found) => Err(ParametricError::UnexpectedCharacter { position: self.position, found }),
+            None => Ok(()),
+        }
+    }
+
+    fn binary(lhs: Expression, operator: Operator, rhs: Expression) -> Expression {
+        Expression::Binary(Box::new(lhs), operator, Box::new(rhs))
+    }
+
+    fn expression(&mut self) -> Result<Expression, ParametricError> {
+        let mut lhs = self.conjunction()?;
+        while self.eat("||") {
+            lhs = Self::binary(lhs, Operator::Or, self.conjunction()?);
+        }
+        Ok(lhs)
+    }
+
+    fn conjunction(&mut self) -> Result<Expression, ParametricError> {
+        let mut lhs = self.comparison()?;
+        while self.eat("&&") {
+            lhs = Self::binary(lhs, Operator::And, self.comparison()?);
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, ParametricError> {
+        let lhs = self.sum()?;
+        // Two character operators have to be tried before their one character prefixes.
+        let operator = if self.eat("<=") {
+            Operator::LessEqual
+        } else if self.eat(">=") {
+            Operator::GreaterEqual
+        } else if self.eat("==") {
+            Operator::Equal
+        } else if self.eat("!=") {
+            Operator::NotEqual
+        } else if self.eat("<") {
+            Operator::Less
+        } else if self.eat(">") {
+            Operator::Greater
+        } else {
+            return Ok(lhs);
+        };
+        Ok(Self::binary(lhs, operator, self.sum()?))
+    }
+
+    fn sum(&mut self) -> Result<Expression, ParametricError> {
+        let mut lhs = self.product()?;
+        loop {
+            if self.eat("+") {
+                lhs = Self::binary(lhs, Operator::Add, self.product()?);
+            } else if self.eat("-") {
+                lhs = Self::binary(lhs, Operator::Subtract, self.product()?);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expression, ParametricError> {
+        let mut lhs = self.power()?;
+        loop {
+            if self.eat("*") {
+                lhs = Self::binary(lhs, Operator::Multiply, self.power()?);
+            } else if self.eat("/") {
+                lhs = Self::binary(lhs, Operator::Divide, self.power()?);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn power(&mut self) -> Result<Expression, ParametricError> {
+        let base = self.unary()?;
+        if self.eat("^") {
+            return Ok(Self::binary(base, Operator::Power, self.power()?));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParametricError> {
+        if self.eat("-") {
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParametricError> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expression = self.expression()?;
+                self.expect(')')?;
+                Ok(expression)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.position;
+                while matches!(self.input.get(self.position), Some(c) if c.is_ascii_digit() || *c == '.') {
+                    self.position += 1;
+                }
+                let number: String = self.input[start..self.position].iter().collect();
+                number.parse()
+                    .map(Expression::Number)
+                    .map_err(|_| ParametricError::UnexpectedCharacter { position: start, found: c })
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let start = self.position;
+                while matches!(self.input.get(self.position), Some(c) if c.is_alphanumeric() || *c == '_') {
+                    self.position += 1;
+                }
+                Ok(Expression::Variable(self.input[start..self.position].iter().collect()))
+            }
+            Some(found) => Err(ParametricError::UnexpectedCharacter { position: self.position, found }),
+            None => Err(ParametricError::UnexpectedEnd),
+        }
+    }
+
+    // A single letter symbol, optionally followed by a parenthesised,
+    // comma separated list of expressions.
+    fn letter(&mut self) -> Result<(char, Vec<Expression>), ParametricError> {
+        let symbol = self.peek().ok_or(ParametricError::UnexpectedEnd)?;
+        self.position += 1;
+        let mut arguments = Vec::new();
+        if self.eat("(") {
+            loop {
+                arguments.push(self.expression()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(')')?;
+        }
+        Ok((symbol, arguments))
+    }
+
+    // The predecessor of a rule, a letter whose arguments are names to bind.
+    fn predecessor(&mut self) -> Result<(char, Vec<String>), ParametricError> {
+        let symbol = self.peek().ok_or(ParametricError::UnexpectedEnd)?;
+        self.position += 1;
+        let mut parameters = Vec::new();
+        if self.eat("(") {
+            loop {
+                self.peek();
+                let start = self.position;
+                match self.expression()? {
+                    Expression::Variable(name) => parameters.push(name),
+                    _ => {
+                        let parameter: String = self.input[start..self.position].iter().collect();
+                        return Err(ParametricError::NonVariableParameter {
+                            position: start,
+                            parameter: parameter.trim_end().to_owned(),
+                        });
+                    }
+                }
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(')')?;
+        }
+        Ok((symbol, parameters))
+    }
+}
+
+//***************************************************************************
+//
+// ParametricRule
+//
+//***************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParametricSuccessor {
+    symbol: char,
+    arguments: Vec<Expression>,
+}
+
+// A production `F(x) : x > 1 -> F(x*0.5) + F(x*0.5)`.
+// The predecessor binds the parameters of a letter to names, the optional
+// guard decides whether the rule applies, and the successor computes the
+// parameters of the new letters from the bound names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParametricRule {
+    symbol: char,
+    parameters: Vec<String>,
+    guard: Option<Expression>,
+    successor: Vec<ParametricSuccessor>,
+}
+
+impl ParametricRule {
+    pub fn parse(input: &str) -> Result<Self, ParametricError> {
+        let (predecessor, successor) = input.split_once("->").ok_or(ParametricError::MissingArrow)?;
+        let (predecessor, guard) = match predecessor.split_once(':') {
+            Some((predecessor, guard)) => (predecessor, Some(Expression::parse(guard)?)),
+            None => (predecessor, None),
+        };
+
+        let mut parser = Parser::new(predecessor);
+        let (symbol, parameters) = parser.predecessor()?;
+        parser.expect_end()?;
+
+        Ok(ParametricRule {
+            symbol,
+            parameters,
+            guard,
+            successor: parse_successor(successor)?,
+        })
+    }
+
+    pub fn symbol(&self) -> char {
+        self.symbol
+    }
+
+    // Returns None if the rule does not match the letter,
+    // either because of its symbol, its number of parameters or the guard.
+    pub fn apply<L: Parametric>(&self, letter: &L, defaults: &HashMap<char, Vec<f32>>) -> Result<Option<Vec<L>>, ParametricError> {
+        let values = letter.parameters();
+        if letter.symbol() != self.symbol || values.len() != self.parameters.len() {
+            return Ok(None);
+        }
+
+        let bindings: HashMap<String, f32> = self.parameters.iter().cloned().zip(values).collect();
+        if let Some(guard) = &self.guard {
+            if !guard.is_satisfied(&bindings)? {
+                return Ok(None);
+            }
+        }
+
+        instantiate(&self.successor, &bindings, defaults).map(Some)
+    }
+}
+
+fn parse_successor(input: &str) -> Result<Vec<ParametricSuccessor>, ParametricError> {
+    let mut parser = Parser::new(input);
+    let mut successor = Vec::new();
+    while parser.peek().is_some() {
+        let (symbol, arguments) = parser.letter()?;
+        successor.push(ParametricSuccessor { symbol, arguments });
+    }
+    Ok(successor)
+}
+
+// Letters written without arguments get the default parameters of their symbol.
+fn instantiate<L: Parametric>(successor: &[ParametricSuccessor], bindings: &HashMap<String, f32>, defaults: &HashMap<char, Vec<f32>>) -> Result<Vec<L>, ParametricError> {
+    let mut word = Vec::with_capacity(successor.len());
+    for letter in successor {
+        let parameters = if letter.arguments.is_empty() {
+            defaults.get(&letter.symbol).cloned().unwrap_or_default()
+        } else {
+            letter.arguments.iter()
+                .map(|argument| argument.evaluate(bindings))
+                .collect::<Result<Vec<f32>, ParametricError>>()?
+        };
+        match L::from_parameters(letter.symbol, &parameters) {
+            Some(letter) => word.push(letter),
+            None => return Err(ParametricError::InvalidLetter { symbol: letter.symbol, parameters }),
+        }
+    }
+    Ok(word)
+}
+
+//***************************************************************************
+//
+// ParametricSystem
+//
+//***************************************************************************
+
+pub struct ParametricSystem<L> {
+    word_stack: Vec<Vec<L>>,
+    rules: Vec<ParametricRule>,
+    defaults: HashMap<char, Vec<f32>>,
+}
+
+impl<L> ParametricSystem<L>
+where
+    L: Parametric + Clone,
+{
+    pub fn new(starting_word: Vec<L>) -> Self {
+        ParametricSystem {
+            word_stack: vec![starting_word],
+            rules: Vec::new(),
+            defaults: HashMap::new(),
+        }
+    }
+
+    // Parses a starting word like `F(1) + F(1)`, using the defaults
+    // registered so far for letters written without arguments.
+    pub fn with_starting_word(mut self, starting_word: &str) -> Result<Self, ParametricError> {
+        let word = instantiate(&parse_successor(starting_word)?, &HashMap::new(), &self.defaults)?;
+        self.word_stack = vec![word];
+        Ok(self)
+    }
+
+    pub fn with_default_parameters(mut self, symbol: char, parameters: Vec<f32>) -> Self {
+        self.defaults.insert(symbol, parameters);
+        self
+    }
+
+    pub fn with_rule(mut self, rule: &str) -> Result<Self, ParametricError> {
+        self.rules.push(ParametricRule::parse(rule)?);
+        self.word_stack.truncate(1);
+        Ok(self)
+    }
+
+    // The first rule that matches a letter rewrites it,
+    // letters without a matching rule are copied unchanged.
+    pub fn apply_rules(&mut self) -> Result<(), ParametricError> {
+        let mut result = Vec::new();
+        if let Some(word) = self.word_stack.last() {
+            for letter in word {
+                let mut replacement = None;
+                for rule in &self.rules {
+                    replacement = rule.apply(letter, &self.defaults)?;
+                    if replacement.is_some() {
+                        break;
+                    }
+                }
+                match replacement {
+                    Some(mut replacement) => result.append(&mut replacement),
+                    None => result.push(letter.clone()),
+                }
+            }
+        }
+        self.word_stack.push(result);
+        Ok(())
+    }
+
+    pub fn iteration(&mut self, depth: usize) -> Result<&[L], ParametricError> {
+        while self.word_stack.len() <= depth {
+            self.apply_rules()?;
+        }
+        Ok(&self.word_stack[depth])
+    }
+
+    pub fn compute_vertices(&mut self, depth: usize, scaling_factor: f32) -> Result<Vec<Option<MathPosition>>, ParametricError> {
+        let mut payload = LindenmayerPayload::new();
+        payload.begin_vertices();
+        for letter in self.iteration(depth)? {
+            letter.interpret(&mut payload);
+        }
+        payload.finish_vertices(scaling_factor);
+        Ok(payload.vertex_buffer().to_vec())
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod expression {
+        use std::collections::HashMap;
+
+        use crate::parametric::{Expression, ParametricError};
+
+        fn bindings() -> HashMap<String, f32> {
+            HashMap::from([(String::from("x"), 3.0), (String::from("y"), 0.5)])
+        }
+
+        #[test]
+        fn precedence() {
+            let expression = Expression::parse("1 + x * 2 ^ 2 - -y").unwrap();
+            assert_eq!(expression.evaluate(&bindings()).unwrap(), 13.5);
+        }
+
+        #[test]
+        fn parentheses() {
+            let expression = Expression::parse("(1 + x) * y").unwrap();
+            assert_eq!(expression.evaluate(&bindings()).unwrap(), 2.0);
+        }
+
+        #[test]
+        fn guards() {
+            assert!(Expression::parse("x > 1 && y <= 0.5").unwrap().is_satisfied(&bindings()).unwrap());
+            assert!(Expression::parse("x == 2 || !(y != 0.5)").unwrap().is_satisfied(&bindings()).unwrap());
+            assert!(!Expression::parse("x >= 4").unwrap().is_satisfied(&bindings()).unwrap());
+        }
+
+        #[test]
+        fn unknown_variable() {
+            let expression = Expression::parse("z * 2").unwrap();
+            assert_eq!(expression.evaluate(&bindings()), Err(ParametricError::UnknownVariable(String::from("z"))));
+        }
+
+        #[test]
+        fn reject_trailing_input() {
+            assert!(Expression::parse("x 2").is_err());
+            assert!(Expression::parse("(x").is_err());
+        }
+    }
+
+    mod parametric_rule {
+        use crate::parametric::{ParametricError, ParametricRule};
+
+        #[test]
+        fn predecessor_binds_names() {
+            assert!(ParametricRule::parse("F(x, y) -> F(x*y)").is_ok());
+            assert_eq!(
+                ParametricRule::parse("F(x, y * 2 ) -> F(x*y)"),
+                Err(ParametricError::NonVariableParameter { position: 5, parameter: String::from("y * 2") })
+            );
+        }
+    }
+
+    mod parametric_system {
+        use crate::check_this::MyFirstLetter;
+        use crate::parametric::ParametricSystem;
+
+        fn halving() -> ParametricSystem<MyFirstLetter> {
+            ParametricSystem::new(vec![])
+                .with_default_parameters('+', vec![90.0])
+                .with_starting_word("F(4)").unwrap()
+                .with_rule("F(x) : x > 1 -> F(x*0.5) + F(x*0.5)").unwrap()
+        }
+
+        #[test]
+        fn derive() {
+            let mut system = halving();
+            assert_eq!(
+                system.iteration(1).unwrap(),
+                &[
+                    MyFirstLetter::Forward {length: 2.0},
+                    MyFirstLetter::TurnLeft {angle: 90.0},
+                    MyFirstLetter::Forward {length: 2.0},
+                ][..]
+            );
+        }
+
+        #[test]
+        fn guard_stops_rewriting() {
+            let mut system = halving();
+            let second = system.iteration(2).unwrap().to_vec();
+            assert_eq!(second.len(), 7);
+            assert!(second.iter().all(|letter| *letter != MyFirstLetter::Forward {length: 0.5}));
+            assert_eq!(system.iteration(3).unwrap(), &second[..]);
+        }
+
+        #[test]
+        fn missing_default_parameters() {
+            let system = ParametricSystem::<MyFirstLetter>::new(vec![])
+                .with_starting_word("F(1)").unwrap()
+                .with_rule("F(x) -> F(x) + F(x)");
+            assert!(system.unwrap().iteration(1).is_err());
+        }
+
+        #[test]
+        fn compute_vertices() {
+            let mut system = halving();
+            let vertices = system.compute_vertices(1, 1.0).unwrap();
+            assert_eq!(vertices.len(), 3);
+            let first = vertices[0].unwrap();
+            let last = vertices[2].unwrap();
+            assert!(((last - first).norm() - 8.0f32.sqrt()).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::coordinates::MathPosition;
+
+// CPU rendering of turtle vertices into PNG files, for machines without a
+// display. Lines are drawn with Xiaolin Wu's algorithm, so they come out
+// anti-aliased without any supersampling.
+
+//***************************************************************************
+//
+// RasterError
+//
+//***************************************************************************
+
+#[derive(Debug)]
+pub enum RasterError {
+    Io(std::io::Error),
+    Encoding(png::EncodingError),
+}
+
+impl Display for RasterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not write image: {}", error),
+            Self::Encoding(error) => write!(f, "could not encode png: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+impl From<std::io::Error> for RasterError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<png::EncodingError> for RasterError {
+    fn from(error: png::EncodingError) -> Self {
+        Self::Encoding(error)
+    }
+}
+
+//***************************************************************************
+//
+// Canvas
+//
+//***************************************************************************
+
+// Colors are stored like Color::from_rgb_f, every channel in [0, 1],
+// and only quantized when the image is encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<(f32, f32, f32)>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, background: (f32, f32, f32)) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![background; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<(f32, f32, f32)> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+
+    // Mixes the color into the pixel, weighted by how much of the pixel is covered.
+    // Pixels outside of the canvas are silently dropped.
+    pub fn blend(&mut self, x: i64, y: i64, color: (f32, f32, f32), coverage: f32) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let coverage = coverage.clamp(0.0, 1.0);
+        let pixel = &mut self.pixels[(y as u32 * self.width + x as u32) as usize];
+        pixel.0 += (color.0 - pixel.0) * coverage;
+        pixel.1 += (color.1 - pixel.1) * coverage;
+        pixel.2 += (color.2 - pixel.2) * coverage;
+    }
+
+    // Xiaolin Wu's line algorithm, the coordinates are in pixels.
+    pub fn draw_line(&mut self, from: (f32, f32), to: (f32, f32), color: (f32, f32, f32)) {
+        let (mut x0, mut y0) = from;
+        let (mut x1, mut y1) = to;
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            std::mem::swap(&mut x0, &mut y0);
+            std::mem::swap(&mut x1, &mut y1);
+        }
+        if x0 > x1 {
+            std::mem::swap(&mut x0, &mut x1);
+            std::mem::swap(&mut y0, &mut y1);
+        }
+
+        let dx = x1 - x0;
+        let gradient = if dx == 0.0 { 1.0 } else { (y1 - y0) / dx };
+
+        // Distance to the floor, f32::fract measures it towards zero and gets
+        // the coverage wrong for negative coordinates.
+        let fract = |value: f32| value - value.floor();
+
+        // Plots a pixel of the line, swapping the axes back for steep lines.
+        let plot = |canvas: &mut Canvas, x: f32, y: f32, coverage: f32| {
+            let (x, y) = if steep { (y, x) } else { (x, y) };
+            canvas.blend(x as i64, y as i64, color, coverage);
+        };
+
+        // First endpoint
+        let x_end = x0.round();
+        let y_end = y0 + gradient * (x_end - x0);
+        let x_gap = 1.0 - fract(x0 + 0.5);
+        let x_start = x_end;
+        let y_start = y_end.floor();
+        plot(self, x_start, y_start, (1.0 - fract(y_end)) * x_gap);
+        plot(self, x_start, y_start + 1.0, fract(y_end) * x_gap);
+        let mut intersection = y_end + gradient;
+
+        // Second endpoint
+        let x_end = x1.round();
+        let y_end = y1 + gradient * (x_end - x1);
+        let x_gap = fract(x1 + 0.5);
+        let x_stop = x_end;
+        let y_stop = y_end.floor();
+        plot(self, x_stop, y_stop, (1.0 - fract(y_end)) * x_gap);
+        plot(self, x_stop, y_stop + 1.0, fract(y_end) * x_gap);
+
+        let mut x = x_start + 1.0;
+        while x < x_stop {
+            plot(self, x, intersection.floor(), 1.0 - fract(intersection));
+            plot(self, x, intersection.floor() + 1.0, fract(intersection));
+            intersection += gradient;
+            x += 1.0;
+        }
+    }
+
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|(red, green, blue)| [*red, *green, *blue])
+            .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect()
+    }
+
+    pub fn encode_png<W: Write>(&self, writer: W) -> Result<(), RasterError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<(), RasterError> {
+        let file = File::create(path)?;
+        self.encode_png(BufWriter::new(file))
+    }
+}
+
+//***************************************************************************
+//
+// RasterRenderer
+//
+//***************************************************************************
+
+// Fits the vertices into an image of the given resolution, keeping the
+// aspect ratio, and draws them like draw_single_lines does:
+// a None between two vertices lifts the pen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RasterRenderer {
+    width: u32,
+    height: u32,
+    stroke_color: (f32, f32, f32),
+    background: (f32, f32, f32),
+    margin: f32,
+}
+
+impl RasterRenderer {
+    pub fn new(width: u32, height: u32) -> Self {
+        RasterRenderer {
+            width,
+            height,
+            stroke_color: (0.5, 0.9, 0.7),
+            background: (0.1, 0.1, 0.1),
+            margin: 10.0,
+        }
+    }
+
+    pub fn with_stroke_color(mut self, red: f32, green: f32, blue: f32) -> Self {
+        self.stroke_color = (red, green, blue);
+        self
+    }
+
+    pub fn with_background(mut self, red: f32, green: f32, blue: f32) -> Self {
+        self.background = (red, green, blue);
+        self
+    }
+
+    // Space between the drawing and the border of the image, in pixels.
+    pub fn with_margin(mut self, margin: f32) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    // Returns a function mapping the vertices into pixel coordinates.
+    fn fit<I: Iterator<Item = Option<MathPosition>>>(&self, vertices: I) -> impl Fn(&MathPosition) -> (f32, f32) {
+        let mut bounds: Option<(f32, f32, f32, f32)> = None;
+        for vertex in vertices.flatten() {
+            bounds = match bounds {
+                Some((min_x, min_y, max_x, max_y)) => Some((min_x.min(vertex.x), min_y.min(vertex.y), max_x.max(vertex.x), max_y.max(vertex.y))),
+                None => Some((vertex.x, vertex.y, vertex.x, vertex.y)),
+            };
+        }
+        let (min_x, min_y, max_x, max_y) = bounds.unwrap_or((0.0, 0.0, 0.0, 0.0));
+
+        // Integer pixel coordinates are the centers of the pixels, so the
+        // outermost pixels are width - 1 apart.
+        let available_width = (self.width as f32 - 1.0 - 2.0 * self.margin).max(1.0);
+        let available_height = (self.height as f32 - 1.0 - 2.0 * self.margin).max(1.0);
+        let scale_x = if max_x > min_x { available_width / (max_x - min_x) } else { f32::INFINITY };
+        let scale_y = if max_y > min_y { available_height / (max_y - min_y) } else { f32::INFINITY };
+        let scale = match scale_x.min(scale_y) {
+            scale if scale.is_finite() => scale,
+            _ => 1.0,
+        };
+
+        let center = MathPosition::new((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+        let (width, height) = (self.width as f32 - 1.0, self.height as f32 - 1.0);
+        move |vertex: &MathPosition| {
+            (
+                width / 2.0 + (vertex.x - center.x) * scale,
+                height / 2.0 - (vertex.y - center.y) * scale,
+            )
+        }
+    }
+
+    pub fn render(&self, vertices: &[Option<MathPosition>]) -> Canvas {
+        self.render_streamed(|| vertices.iter().copied())
+    }
+
+    // Like render, for vertices too many to keep in memory, e.g. the ones of
+    // LindenmayerSystem::derive_vertices. They are walked twice, once to fit
+    // them into the image and once to draw them.
+    pub fn render_streamed<F, I>(&self, vertices: F) -> Canvas
+    where
+        F: Fn() -> I,
+        I: Iterator<Item = Option<MathPosition>>,
+    {
+        let mut canvas = Canvas::new(self.width, self.height, self.background);
+        let to_pixel = self.fit(vertices());
+        let mut previous: Option<MathPosition> = None;
+        for vertex in vertices() {
+            if let (Some(from), Some(to)) = (previous, vertex) {
+                canvas.draw_line(to_pixel(&from), to_pixel(&to), self.stroke_color);
+            }
+            previous = vertex;
+        }
+        canvas
+    }
+
+    pub fn write_png(&self, path: &Path, vertices: &[Option<MathPosition>]) -> Result<(), RasterError> {
+        self.render(vertices).write_png(path)
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod draw_line {
+        use crate::raster::Canvas;
+
+        #[test]
+        fn horizontal() {
+            let mut canvas = Canvas::new(10, 5, (0.0, 0.0, 0.0));
+            canvas.draw_line((1.0, 2.0), (8.0, 2.0), (1.0, 1.0, 1.0));
+            for x in 2..8 {
+                assert_eq!(canvas.pixel(x, 2), Some((1.0, 1.0, 1.0)));
+                assert_eq!(canvas.pixel(x, 1), Some((0.0, 0.0, 0.0)));
+                assert_eq!(canvas.pixel(x, 3), Some((0.0, 0.0, 0.0)));
+            }
+        }
+
+        #[test]
+        fn anti_aliased() {
+            let mut canvas = Canvas::new(10, 10, (0.0, 0.0, 0.0));
+            canvas.draw_line((0.0, 0.0), (9.0, 4.5), (1.0, 1.0, 1.0));
+            // Halfway between two rows, both get half of the color.
+            let (upper, _, _) = canvas.pixel(3, 1).unwrap();
+            let (lower, _, _) = canvas.pixel(3, 2).unwrap();
+            assert!((upper - 0.5).abs() < 1e-4);
+            assert!((lower - 0.5).abs() < 1e-4);
+        }
+
+        #[test]
+        fn negative_coordinates() {
+            let mut canvas = Canvas::new(10, 5, (0.0, 0.0, 0.0));
+            canvas.draw_line((0.0, -0.5), (9.0, -0.5), (1.0, 1.0, 1.0));
+            // Halfway between the row above the canvas and the first one.
+            let (red, _, _) = canvas.pixel(3, 0).unwrap();
+            assert!((red - 0.5).abs() < 1e-4);
+        }
+
+        #[test]
+        fn steep_and_outside() {
+            let mut canvas = Canvas::new(5, 5, (0.0, 0.0, 0.0));
+            canvas.draw_line((2.0, -10.0), (2.0, 20.0), (0.0, 1.0, 0.0));
+            for y in 0..5 {
+                assert_eq!(canvas.pixel(2, y), Some((0.0, 1.0, 0.0)));
+            }
+            assert_eq!(canvas.pixel(5, 0), None);
+        }
+    }
+
+    mod render {
+        use crate::coordinates::MathPosition;
+        use crate::raster::RasterRenderer;
+        use crate::tryout::LindenmayerSystem;
+
+        #[test]
+        fn none_lifts_the_pen() {
+            let vertices = vec![
+                Some(MathPosition::new(0.0, 0.0)),
+                Some(MathPosition::new(0.0, 10.0)),
+                None,
+                Some(MathPosition::new(10.0, 10.0)),
+                Some(MathPosition::new(10.0, 0.0)),
+            ];
+            let canvas = RasterRenderer::new(21, 21)
+                .with_margin(0.0)
+                .with_background(0.0, 0.0, 0.0)
+                .with_stroke_color(1.0, 1.0, 1.0)
+                .render(&vertices);
+            assert_eq!(canvas.pixel(0, 10), Some((1.0, 1.0, 1.0)));
+            assert_eq!(canvas.pixel(20, 10), Some((1.0, 1.0, 1.0)));
+            // No line along the top between the two strokes.
+            assert_eq!(canvas.pixel(10, 0), Some((0.0, 0.0, 0.0)));
+        }
+
+        #[test]
+        fn encode_png() {
+            let mut koch = LindenmayerSystem::koch();
+            koch.update_vertex_stack(3);
+            let canvas = RasterRenderer::new(64, 48).render(koch.get_vertex_stack_at(3).unwrap());
+
+            let mut bytes = Vec::new();
+            canvas.encode_png(&mut bytes).unwrap();
+
+            let decoder = png::Decoder::new(&bytes[..]);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buffer).unwrap();
+            assert_eq!((info.width, info.height), (64, 48));
+            assert_eq!(&buffer[..info.buffer_size()], &canvas.to_rgb8()[..]);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use crate::tryout::Letter;
+
+// A derived word kept as a DAG of rule expansions instead of a flat Vec.
+// The expansion of a letter after k iterations is the same wherever the
+// letter appears, so it is stored only once and refers to the expansions
+// of the letters of its successor after k - 1 iterations. Depth n needs a
+// node per letter and depth, which makes words of depth 40 and beyond
+// cheap to ask about, even though they could never be written out.
+//
+// Lengths are u128 and saturate, indexing beyond u128::MAX letters is not
+// supported.
+
+enum Node<L> {
+    Leaf(L),
+    Expansion { children: Vec<usize>, length: u128 },
+}
+
+pub struct SharedWord<L: Letter> {
+    nodes: Vec<Node<L>>,
+    root: usize,
+    depth: usize,
+}
+
+impl<L: Letter> SharedWord<L> {
+    pub fn new(axiom: &[L], production_rules: &HashMap<L, Option<Vec<L>>>, depth: usize) -> Self {
+        let mut word = SharedWord {
+            nodes: Vec::new(),
+            root: 0,
+            depth,
+        };
+        let mut expansions = HashMap::new();
+        let children = axiom.iter()
+            .map(|&letter| word.expansion(letter, depth, production_rules, &mut expansions))
+            .collect();
+        word.root = word.push_expansion(children);
+        word
+    }
+
+    // Hash-consed node of the letter after the given number of iterations.
+    // A letter without a successor stays the same leaf at every depth.
+    fn expansion(&mut self, letter: L, depth: usize, production_rules: &HashMap<L, Option<Vec<L>>>, expansions: &mut HashMap<(L, usize), usize>) -> usize {
+        let successor = match production_rules.get(&letter) {
+            Some(Some(successor)) if depth > 0 => successor,
+            _ => {
+                return *expansions.entry((letter, 0)).or_insert_with(|| {
+                    self.nodes.push(Node::Leaf(letter));
+                    self.nodes.len() - 1
+                });
+            }
+        };
+        if let Some(&node) = expansions.get(&(letter, depth)) {
+            return node;
+        }
+        let children = successor.iter()
+            .map(|&letter| self.expansion(letter, depth - 1, production_rules, expansions))
+            .collect();
+        let node = self.push_expansion(children);
+        expansions.insert((letter, depth), node);
+        node
+    }
+
+    fn push_expansion(&mut self, children: Vec<usize>) -> usize {
+        let length = children.iter().fold(0u128, |length, &child| length.saturating_add(self.node_length(child)));
+        self.nodes.push(Node::Expansion { children, length });
+        self.nodes.len() - 1
+    }
+
+    fn node_length(&self, node: usize) -> u128 {
+        match &self.nodes[node] {
+            Node::Leaf(_) => 1,
+            Node::Expansion { length, .. } => *length,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn len(&self) -> u128 {
+        self.node_length(self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Number of distinct expansions stored, the actual memory used.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn get(&self, index: u128) -> Option<L> {
+        let mut letters = self.iter_from(index);
+        letters.next()
+    }
+
+    pub fn iter(&self) -> SharedLetters<'_, L> {
+        self.iter_from(0)
+    }
+
+    // Letters from the given position on, found by walking down the DAG
+    // instead of skipping the letters in front of it.
+    pub fn iter_from(&self, index: u128) -> SharedLetters<'_, L> {
+        let mut stack = Vec::new();
+        if index < self.len() {
+            let mut node = self.root;
+            let mut index = index;
+            while let Node::Expansion { children, .. } = &self.nodes[node] {
+                let mut position = 0;
+                loop {
+                    let length = self.node_length(children[position]);
+                    if index < length {
+                        break;
+                    }
+                    index -= length;
+                    position += 1;
+                }
+                stack.push((node, position + 1));
+                node = children[position];
+            }
+            stack.push((node, 0));
+        }
+        SharedLetters { word: self, stack }
+    }
+}
+
+//***************************************************************************
+//
+// SharedLetters
+//
+//***************************************************************************
+
+pub struct SharedLetters<'a, L: Letter> {
+    word: &'a SharedWord<L>,
+    // The nodes walked on every level and the next child to visit in each.
+    stack: Vec<(usize, usize)>,
+}
+
+impl<'a, L: Letter> Iterator for SharedLetters<'a, L> {
+    type Item = L;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, position) = self.stack.last_mut()?;
+            match &self.word.nodes[*node] {
+                Node::Leaf(letter) => {
+                    let letter = *letter;
+                    self.stack.pop();
+                    return Some(letter);
+                }
+                Node::Expansion { children, .. } => {
+                    if *position >= children.len() {
+                        self.stack.pop();
+                        continue;
+                    }
+                    let child = children[*position];
+                    *position += 1;
+                    self.stack.push((child, 0));
+                }
+            }
+        }
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod shared_word {
+        use std::collections::HashMap;
+
+        use crate::shared_word::SharedWord;
+        use crate::tryout::LindenmayerSystem;
+
+        #[test]
+        fn same_letters_as_the_derivation() {
+            for system in [
+                LindenmayerSystem::koch(),
+                LindenmayerSystem::dragon_curve(),
+                LindenmayerSystem::first_plant(),
+            ] {
+                for depth in 0..=4 {
+                    let word = system.shared_word(depth);
+                    let derived: Vec<_> = system.derive_letters(depth).collect();
+                    assert_eq!(word.len(), derived.len() as u128);
+                    assert_eq!(word.iter().collect::<Vec<_>>(), derived);
+                    for (index, letter) in derived.iter().enumerate() {
+                        assert_eq!(word.get(index as u128), Some(*letter));
+                        assert_eq!(word.iter_from(index as u128).count(), derived.len() - index);
+                    }
+                    assert_eq!(word.get(derived.len() as u128), None);
+                }
+            }
+        }
+
+        #[test]
+        fn deep_dragon_curve() {
+            let dragon = LindenmayerSystem::dragon_curve();
+            let word = dragon.shared_word(40);
+            assert_eq!(word.len(), (1 << 40) + 4 * ((1 << 40) - 1));
+            assert!(word.node_count() < 200);
+            // The first letters can still be streamed, the rest is out of reach.
+            assert!(word.iter().take(100).eq(dragon.derive_letters(40).take(100)));
+            assert_eq!(word.iter_from(word.len() - 3).count(), 3);
+        }
+
+        #[test]
+        fn empty_successors() {
+            let rules: HashMap<char, Option<Vec<char>>> = [('a', Some(vec![])), ('b', Some(vec!['a', 'b', 'a']))].into_iter().collect();
+            let word = SharedWord::new(&['a', 'b', 'a'], &rules, 2);
+            assert_eq!(word.iter().collect::<String>(), "aba");
+            assert_eq!(word.get(1), Some('b'));
+            assert_eq!(word.iter_from(1).collect::<String>(), "ba");
+            assert!(SharedWord::new(&['a'], &rules, 1).is_empty());
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::coordinates::MathPosition;
+
+// Writes the vertices of a turtle, as found in the vertex stack of a
+// LindenmayerSystem, into an SVG document. A None in the vertices lifts the
+// pen, so every run of Some vertices becomes its own subpath.
+// The viewBox is fitted around all vertices, the y axis is flipped since
+// SVG counts downwards while MathPosition counts upwards.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgExporter {
+    stroke_color: (f32, f32, f32),
+    stroke_width: f32,
+    margin: f32,
+    background: Option<(f32, f32, f32)>,
+    size: Option<(u32, u32)>,
+}
+
+impl SvgExporter {
+    pub fn new() -> Self {
+        SvgExporter {
+            stroke_color: (0.5, 0.9, 0.7),
+            stroke_width: 2.0,
+            margin: 10.0,
+            background: None,
+            size: None,
+        }
+    }
+
+    // Colors are given like Color::from_rgb_f, every channel in [0, 1].
+    pub fn with_stroke_color(mut self, red: f32, green: f32, blue: f32) -> Self {
+        self.stroke_color = (red, green, blue);
+        self
+    }
+
+    pub fn with_stroke_width(mut self, stroke_width: f32) -> Self {
+        self.stroke_width = stroke_width;
+        self
+    }
+
+    // Space around the bounding box of the vertices, in the units of the vertices.
+    pub fn with_margin(mut self, margin: f32) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn with_background(mut self, red: f32, green: f32, blue: f32) -> Self {
+        self.background = Some((red, green, blue));
+        self
+    }
+
+    // Width and height of the image, the drawing is fitted into it.
+    // Without a size, one unit of the vertices is one pixel.
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    // Returns (min_x, min_y, width, height) of the fitted viewBox in SVG coordinates.
+    pub fn view_box(&self, vertices: &[Option<MathPosition>]) -> (f32, f32, f32, f32) {
+        self.view_box_of(vertices.iter().copied())
+    }
+
+    pub fn view_box_of<I: Iterator<Item = Option<MathPosition>>>(&self, vertices: I) -> (f32, f32, f32, f32) {
+        let mut bounds: Option<(f32, f32, f32, f32)> = None;
+        for vertex in vertices.flatten() {
+            let (x, y) = (vertex.x, -vertex.y);
+            bounds = match bounds {
+                Some((min_x, min_y, max_x, max_y)) => Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))),
+                None => Some((x, y, x, y)),
+            };
+        }
+        let (min_x, min_y, max_x, max_y) = bounds.unwrap_or((0.0, 0.0, 0.0, 0.0));
+
+        // Half the stroke sticks out of the bounding box, and a straight
+        // line would otherwise give a viewBox without any height.
+        let padding = self.margin + self.stroke_width / 2.0;
+        (min_x - padding, min_y - padding, max_x - min_x + 2.0 * padding, max_y - min_y + 2.0 * padding)
+    }
+
+    pub fn render(&self, vertices: &[Option<MathPosition>]) -> String {
+        let mut svg = Vec::new();
+        self.write_to(&mut svg, || vertices.iter().copied()).unwrap();
+        String::from_utf8(svg).unwrap()
+    }
+
+    pub fn write(&self, path: &Path, vertices: &[Option<MathPosition>]) -> std::io::Result<()> {
+        self.write_streamed(path, || vertices.iter().copied())
+    }
+
+    // Like write, for vertices too many to keep in memory, e.g. the ones of
+    // LindenmayerSystem::derive_vertices. They are walked twice, once to fit
+    // the viewBox and once to write the path.
+    pub fn write_streamed<F, I>(&self, path: &Path, vertices: F) -> std::io::Result<()>
+    where
+        F: Fn() -> I,
+        I: Iterator<Item = Option<MathPosition>>,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer, vertices)?;
+        writer.flush()
+    }
+
+    pub fn write_to<W, F, I>(&self, writer: &mut W, vertices: F) -> std::io::Result<()>
+    where
+        W: Write,
+        F: Fn() -> I,
+        I: Iterator<Item = Option<MathPosition>>,
+    {
+        let (min_x, min_y, width, height) = self.view_box_of(vertices());
+        let (image_width, image_height) = match self.size {
+            Some((image_width, image_height)) => (image_width as f32, image_height as f32),
+            None => (width, height),
+        };
+
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            writer,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">",
+            format_number(image_width), format_number(image_height),
+            format_number(min_x), format_number(min_y), format_number(width), format_number(height),
+        )?;
+        if let Some(background) = self.background {
+            writeln!(
+                writer,
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                format_number(min_x), format_number(min_y), format_number(width), format_number(height),
+                format_color(background),
+            )?;
+        }
+        if write_path_data(writer, vertices())? {
+            writeln!(
+                writer,
+                "\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>",
+                format_color(self.stroke_color), format_number(self.stroke_width),
+            )?;
+        }
+        writeln!(writer, "</svg>")
+    }
+}
+
+impl Default for SvgExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Every run of at least two vertices becomes a "M x y L x y ..." subpath,
+// a single vertex between two pen lifts draws nothing. The opening of the
+// path element is only written once there is something to draw, returns
+// whether it was.
+fn write_path_data<W: Write, I: Iterator<Item = Option<MathPosition>>>(writer: &mut W, vertices: I) -> std::io::Result<bool> {
+    let mut started = false;
+    // The first vertex of the current run is held back until a second one
+    // shows that the run draws a line.
+    let mut run_start: Option<MathPosition> = None;
+    let mut run_length = 0;
+
+    for vertex in vertices {
+        let vertex = match vertex {
+            Some(vertex) => vertex,
+            None => {
+                run_length = 0;
+                continue;
+            }
+        };
+        run_length += 1;
+        match run_length {
+            1 => run_start = Some(vertex),
+            2 => {
+                if started {
+                    write!(writer, " ")?;
+                } else {
+                    write!(writer, "  <path d=\"")?;
+                    started = true;
+                }
+                if let Some(run_start) = run_start {
+                    write!(writer, "M{} {} ", format_number(run_start.x), format_number(-run_start.y))?;
+                }
+                write!(writer, "L{} {}", format_number(vertex.x), format_number(-vertex.y))?;
+            }
+            _ => write!(writer, " L{} {}", format_number(vertex.x), format_number(-vertex.y))?,
+        }
+    }
+    Ok(started)
+}
+
+// Three decimals are plenty for an image and keep the files small.
+fn format_number(number: f32) -> String {
+    let formatted = format!("{:.3}", number);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" | "" => String::from("0"),
+        _ => formatted.to_owned(),
+    }
+}
+
+fn format_color((red, green, blue): (f32, f32, f32)) -> String {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(red), channel(green), channel(blue))
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod render {
+        use crate::coordinates::MathPosition;
+        use crate::svg::SvgExporter;
+
+        #[test]
+        fn single_line() {
+            let vertices = vec![Some(MathPosition::new(0.0, 0.0)), Some(MathPosition::new(10.0, 5.0))];
+            let svg = SvgExporter::new()
+                .with_stroke_color(1.0, 0.0, 0.5)
+                .with_stroke_width(1.5)
+                .with_margin(0.0)
+                .render(&vertices);
+            assert!(svg.contains("viewBox=\"-0.75 -5.75 11.5 6.5\""));
+            assert!(svg.contains("d=\"M0 0 L10 -5\""));
+            assert!(svg.contains("stroke=\"#ff0080\""));
+            assert!(svg.contains("stroke-width=\"1.5\""));
+        }
+
+        #[test]
+        fn none_breaks_the_path() {
+            let vertices = vec![
+                Some(MathPosition::new(0.0, 0.0)),
+                Some(MathPosition::new(1.0, 0.0)),
+                None,
+                Some(MathPosition::new(2.0, 0.0)),
+                Some(MathPosition::new(3.0, 0.0)),
+                None,
+                Some(MathPosition::new(4.0, 0.0)),
+            ];
+            let svg = SvgExporter::new().render(&vertices);
+            assert!(svg.contains("d=\"M0 0 L1 0 M2 0 L3 0\""));
+        }
+
+        #[test]
+        fn fit_view_box_and_size() {
+            let vertices = vec![Some(MathPosition::new(-4.0, -2.0)), Some(MathPosition::new(4.0, 2.0))];
+            let exporter = SvgExporter::new().with_margin(1.0).with_stroke_width(0.0).with_size(800, 400);
+            assert_eq!(exporter.view_box(&vertices), (-5.0, -3.0, 10.0, 6.0));
+            let svg = exporter.with_background(0.0, 0.0, 0.0).render(&vertices);
+            assert!(svg.contains("width=\"800\" height=\"400\""));
+            assert!(svg.contains("fill=\"#000000\""));
+        }
+
+        #[test]
+        fn empty_vertices() {
+            let svg = SvgExporter::new().render(&[]);
+            assert!(!svg.contains("<path"));
+            assert!(svg.ends_with("</svg>\n"));
+        }
+    }
+
+    mod export {
+        use crate::svg::SvgExporter;
+        use crate::tryout::LindenmayerSystem;
+
+        #[test]
+        fn streamed() {
+            let mut dragon = LindenmayerSystem::dragon_curve();
+            dragon.update_vertex_stack(6);
+            let exporter = SvgExporter::new().with_margin(0.0);
+
+            // The vertex stack is centered and scaled, the streamed vertices
+            // are not, so only the shape of the path can be compared.
+            let collected: Vec<_> = dragon.derive_vertices(6).collect();
+            let mut streamed = Vec::new();
+            exporter.write_to(&mut streamed, || dragon.derive_vertices(6)).unwrap();
+            assert_eq!(String::from_utf8(streamed).unwrap(), exporter.render(&collected));
+            assert_eq!(collected.len(), dragon.get_vertex_stack_at(6).unwrap().len());
+        }
+
+        #[test]
+        fn koch() {
+            let mut koch = LindenmayerSystem::koch();
+            koch.update_vertex_stack(2);
+            let vertices = koch.get_vertex_stack_at(2).unwrap();
+            let svg = SvgExporter::new().render(vertices);
+            let path = svg.lines().find(|line| line.contains("<path")).unwrap();
+            assert_eq!(path.matches('M').count(), 1);
+            assert_eq!(path.matches('L').count(), vertices.len() - 1);
+        }
+    }
+}
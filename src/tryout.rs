@@ -1,9 +1,21 @@
 use std::{collections::HashMap, hash::Hash};
+use std::sync::PoisonError;
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::{coordinates::MathPosition, S};
+use crate::actions::{Action, ActionError, ActionRegistry, SharedActionRegistry};
+use crate::derivation::{Derivation, TurtleVertices};
+use crate::grammar::{ConversionError, Grammar, GrammarErrors, ProductionRule};
+use crate::growth::GrowthMatrix;
+use crate::limits::{saturating_u128, DerivationLimits, LimitError};
+use crate::shared_word::SharedWord;
+use crate::word::Word;
 
 pub trait Payload {}
-pub trait Letter: Copy + Clone + PartialEq + Eq + Hash {
+pub trait Letter: Copy + Clone + PartialEq + Eq + Hash + std::fmt::Debug {
     fn forward() -> Self;
+    // Semantics carried by the letter itself, e.g. its parameters,
+    // applied before the action associated with the letter.
+    fn interpret(&self, _payload: &mut LindenmayerPayload) {}
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +24,15 @@ pub struct LindenmayerPayload {
     coordinate_buffer: Vec<(MathPosition, f32)>,
     current_position: MathPosition,
     current_angle: f32,
+    turning_angle: f32,
+    turning_direction: f32,
+    step_length: f32,
+}
+
+impl Default for LindenmayerPayload {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LindenmayerPayload {
@@ -21,38 +42,62 @@ impl LindenmayerPayload {
             coordinate_buffer: vec![],
             current_position: MathPosition::new(0.0f32, 0.0f32),
             current_angle: 90.0f32.to_radians(),
+            turning_angle: 90.0f32.to_radians(),
+            turning_direction: 1.0f32,
+            step_length: 1.0f32,
         }
     }
-    pub fn compute_base_vertices<L: Letter>(&mut self, word: &[L], actions: &HashMap<L, Option<fn(payload: &mut LindenmayerPayload)>>) {
-        self.clear_vertex_buffer();
-        self.clear_current_position();
-        self.clear_current_angle();
-        self.vertex_buffer.push(Some(self.current_position));
-        for letter in word {
+    pub fn compute_base_vertices<L: Letter>(&mut self, word: &[L], actions: &HashMap<L, Option<Action>>) {
+        self.begin_vertices();
+        self.interpret_letters(word, actions);
+    }
+    // Moves the turtle along the letters, without resetting it first, so a
+    // word can be interpreted piece by piece.
+    pub fn interpret_letters<L: Letter>(&mut self, letters: &[L], actions: &HashMap<L, Option<Action>>) {
+        for letter in letters {
+            letter.interpret(self);
             if let Some(Some(action)) = actions.get(letter) {
                 action(self);
             }
         }
     }
-    pub fn compute_vertices<L: Letter>(&mut self, word: &[L], actions: &HashMap<L, Option<fn(payload: &mut LindenmayerPayload)>>, scaling_factor: f32) {
+    pub fn compute_vertices<L: Letter>(&mut self, word: &[L], actions: &HashMap<L, Option<Action>>, scaling_factor: f32) {
         self.compute_base_vertices(word, actions);
+        self.finish_vertices(scaling_factor);
+    }
+    // Resets the turtle and puts the starting point into the vertex buffer.
+    pub fn begin_vertices(&mut self) {
+        self.clear_vertex_buffer();
+        self.clear_current_position();
+        self.clear_current_angle();
+        self.clear_step_length();
+        self.clear_turning_direction();
+        self.vertex_buffer.push(Some(self.current_position));
+    }
+    // Centers the vertices computed since begin_vertices and scales them.
+    pub fn finish_vertices(&mut self, scaling_factor: f32) {
         self.apply_center_offset();
 
-        for entry in &mut self.vertex_buffer {
-            if let Some(vertex) = entry {
-                vertex.scale(scaling_factor);
-            }
+        for vertex in self.vertex_buffer.iter_mut().flatten() {
+            vertex.scale(scaling_factor);
         }
     }
+    pub fn vertex_buffer(&self) -> &[Option<MathPosition>] {
+        &self.vertex_buffer
+    }
+    // Removes all but the last `keep` vertices from the buffer, oldest first.
+    // Actions may look at the last vertex, so streaming keeps it around.
+    pub fn drain_vertex_buffer(&mut self, keep: usize) -> std::vec::Drain<'_, Option<MathPosition>> {
+        let end = self.vertex_buffer.len().saturating_sub(keep);
+        self.vertex_buffer.drain(..end)
+    }
     pub fn compute_center(&mut self) -> Option<MathPosition> {
         let mut center = MathPosition::new(0.0, 0.0);
 
         let mut n_vertices = 0;
-        for entry in &mut self.vertex_buffer {
-            if let Some(vertex) = entry {
-                center += *vertex;
-                n_vertices += 1;
-            }
+        for vertex in self.vertex_buffer.iter().flatten() {
+            center += *vertex;
+            n_vertices += 1;
         }
 
         // If we have a closed curve and the first index equals the last,
@@ -67,16 +112,14 @@ impl LindenmayerPayload {
             return None;
         }
         center.scale(1.0 / (n_vertices as f32));
-        return Some(center);
+        Some(center)
     }
     pub fn apply_center_offset(&mut self) {
         let center = self.compute_center();
 
         if let Some(center) = center {
-            for entry in &mut self.vertex_buffer {
-                if let Some(vertex) = entry {
-                    *vertex -= center;
-                }
+            for vertex in self.vertex_buffer.iter_mut().flatten() {
+                *vertex -= center;
             }
         }
     }
@@ -92,8 +135,17 @@ impl LindenmayerPayload {
     pub fn clear_current_angle(&mut self) {
         self.current_angle = 90.0f32.to_radians();
     }
+    pub fn clear_step_length(&mut self) {
+        self.step_length = 1.0f32;
+    }
+    pub fn clear_turning_direction(&mut self) {
+        self.turning_direction = 1.0f32;
+    }
     pub fn update_current_position(&mut self) {
-        self.current_position += MathPosition::new(self.current_angle.cos(), self.current_angle.sin());
+        self.update_current_position_by(self.step_length);
+    }
+    pub fn update_current_position_by(&mut self, length: f32) {
+        self.current_position += MathPosition::new(length * self.current_angle.cos(), length * self.current_angle.sin());
     }
     pub fn increase_current_angle(&mut self, delta: f32) {
         self.current_angle += delta;
@@ -101,6 +153,27 @@ impl LindenmayerPayload {
     pub fn decrease_current_angle(&mut self, delta: f32) {
         self.current_angle -= delta;
     }
+    // The turning angle is the angle of the fractal, used by the shared
+    // turn actions below so they do not need to hard code it.
+    pub fn set_turning_angle(&mut self, angle: f32) {
+        self.turning_angle = angle;
+    }
+    pub fn turn_left(&mut self) {
+        self.current_angle += self.turning_direction * self.turning_angle;
+    }
+    pub fn turn_right(&mut self) {
+        self.current_angle -= self.turning_direction * self.turning_angle;
+    }
+    pub fn turn_around(&mut self) {
+        self.current_angle += std::f32::consts::PI;
+    }
+    // Swaps the meaning of turn_left and turn_right.
+    pub fn reverse_turning_direction(&mut self) {
+        self.turning_direction = -self.turning_direction;
+    }
+    pub fn scale_step_length(&mut self, factor: f32) {
+        self.step_length *= factor;
+    }
     pub fn push_current_position(&mut self) {
         self.vertex_buffer.push(Some(self.current_position));
     }
@@ -115,25 +188,99 @@ impl LindenmayerPayload {
     }
 }
 
+// Turtle actions that only depend on the payload, usable for any letter type.
+
+pub fn forward(payload: &mut LindenmayerPayload) {
+    payload.update_current_position();
+    payload.push_current_position();
+}
+
+// Move forward without drawing a line.
+pub fn move_forward(payload: &mut LindenmayerPayload) {
+    payload.update_current_position();
+    if let Some(Some(_)) = payload.vertex_buffer.last() {
+        payload.vertex_buffer.push(None);
+    }
+    payload.push_current_position();
+}
+
+pub fn turn_left(payload: &mut LindenmayerPayload) {
+    payload.turn_left();
+}
+
+pub fn turn_right(payload: &mut LindenmayerPayload) {
+    payload.turn_right();
+}
+
+pub fn turn_around(payload: &mut LindenmayerPayload) {
+    payload.turn_around();
+}
+
+pub fn reverse_turning_direction(payload: &mut LindenmayerPayload) {
+    payload.reverse_turning_direction();
+}
+
+pub fn push(payload: &mut LindenmayerPayload) {
+    payload.save_current_position_and_angle();
+}
+
+pub fn pop(payload: &mut LindenmayerPayload) {
+    payload.pop_and_restore_current_position_and_angle();
+    payload.vertex_buffer.push(None);
+    payload.push_current_position();
+}
+
 #[derive(Clone)]
 pub struct LindenmayerSystem<L: Letter> {
-    starting_word: Vec<L>,
+    name: String,
+    // The starting word and the rules, everything else is derived from it.
+    grammar: Grammar<L>,
     word_stack: Vec<Vec<L>>,
     vertex_stack: Vec<Vec<Option<MathPosition>>>,
+    // The rules of the grammar by letter, looked up for every letter that
+    // is rewritten. Rebuilt whenever the rules change.
     production_rules: HashMap<L, Option<Vec<L>>>,
-    actions: HashMap<L, Option<fn(payload: &mut LindenmayerPayload)>>,
+    actions: HashMap<L, Option<Action>>,
     payload: LindenmayerPayload,
     angle: f32,
     staunching_factor: f32,
+    limits: DerivationLimits,
+    // Letters bound to an action by name, resolved in the registry.
+    action_bindings: HashMap<L, String>,
+    action_registry: SharedActionRegistry,
+    // Generation of the registry the bindings were last resolved in.
+    action_generation: Option<u64>,
 }
 
 impl<L: Letter> LindenmayerSystem<L> {
-    pub fn new(starting_word: &[L], angle: f32, production_rules: &[(L, Option<Vec<L>>)], actions: &[(L, Option<fn(payload: &mut LindenmayerPayload)>)]) -> Self {
-        let starting_word = starting_word.to_owned();
+    // Letters with a rule become the non terminals of the grammar, all
+    // other letters of the starting word and the rules its terminals.
+    pub fn new(starting_word: &[L], angle: f32, production_rules: &[(L, Option<Vec<L>>)], actions: &[(L, Option<Action>)]) -> Self {
+        let mut grammar = Grammar::new(Vec::new(), Vec::new());
+        for (letter, replacement) in production_rules {
+            set_rule(&mut grammar, *letter, replacement.clone());
+        }
+        grammar.add_terminals(starting_word);
+        // An empty starting word leaves the grammar without an axiom.
+        if !starting_word.is_empty() {
+            grammar.change_axiom(Word::from(starting_word)).unwrap();
+        }
+        Self::with_grammar(grammar, angle, actions)
+    }
+    // Only grammars of deterministic rules for single letters can be drawn
+    // this way, the axiom becomes the starting word. A grammar knows no
+    // actions, those are added with with_actions.
+    pub fn from_grammar(grammar: Grammar<L>, angle: f32) -> Result<Self, ConversionError> {
+        grammar.single_letter_rules()?;
+        Ok(Self::with_grammar(grammar, angle, &[]))
+    }
+    fn with_grammar(grammar: Grammar<L>, angle: f32, actions: &[(L, Option<Action>)]) -> Self {
+        let starting_word = grammar.axiom()[..].to_vec();
         let word_stack = vec![starting_word.clone()];
-        let production_rules: HashMap<L, Option<Vec<L>>> = production_rules.to_owned().into_iter().collect();
-        let actions: HashMap<L, Option<fn(payload: &mut LindenmayerPayload)>> = actions.to_owned().into_iter().collect();
+        let production_rules = rules_by_letter(&grammar);
+        let actions: HashMap<L, Option<Action>> = actions.iter().copied().collect();
         let mut payload = LindenmayerPayload::new();
+        payload.set_turning_angle(angle);
 
         payload.compute_vertices(&starting_word, &actions, S);
         let vertex_stack = vec![payload.vertex_buffer.clone()];
@@ -141,9 +288,11 @@ impl<L: Letter> LindenmayerSystem<L> {
         payload.clear_current_angle();
         payload.clear_vertex_buffer();
         payload.clear_coordinate_buffer();
+        let action_registry = ActionRegistry::turtle();
 
         let mut fractal = Self {
-            starting_word,
+            name: String::new(),
+            grammar,
             word_stack,
             vertex_stack,
             production_rules,
@@ -151,46 +300,140 @@ impl<L: Letter> LindenmayerSystem<L> {
             payload,
             angle,
             staunching_factor: 1.0f32,
+            limits: DerivationLimits::new(),
+            action_bindings: HashMap::new(),
+            // Nothing is bound yet, so nothing is outdated.
+            action_generation: Some(action_registry.generation()),
+            action_registry: action_registry.into_shared(),
         };
 
         fractal.compute_staunching_factor();
-        return fractal;
+        fractal
+    }
+    pub fn grammar(&self) -> &Grammar<L> {
+        &self.grammar
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn change_name(&mut self, name: &str) {
+        self.name = name.to_owned();
     }
-    pub fn change_starting_word(&mut self, starting_word: &[L]) {
+    // Letters that are new to the grammar become terminals.
+    pub fn change_starting_word(&mut self, starting_word: &[L]) -> Result<(), GrammarErrors<L>> {
+        self.grammar.add_terminals(starting_word);
+        self.grammar.change_axiom(Word::from(starting_word))?;
+        self.reset_word_stack();
+        Ok(())
+    }
+    fn reset_word_stack(&mut self) {
+        let starting_word = self.grammar.axiom()[..].to_vec();
         self.word_stack.clear();
         self.vertex_stack.clear();
-        self.starting_word = starting_word.to_owned();
-        self.word_stack.push(self.starting_word.clone());
-        self.payload.compute_vertices(&self.starting_word, &self.actions, S);
+        self.payload.compute_vertices(&starting_word, &self.actions, S);
+        self.word_stack.push(starting_word);
         self.vertex_stack.push(self.payload.vertex_buffer.clone());
     }
     pub fn change_angle(&mut self, angle: f32) {
         self.angle = angle;
+        self.payload.set_turning_angle(angle);
+        self.reset_word_stack();
+        self.compute_staunching_factor();
     }
     pub fn with_production_rules(&mut self, production_rules: &[(L, Option<Vec<L>>)]) {
-        self.production_rules = production_rules.to_owned().into_iter().collect();
+        for letter in self.production_rules.keys() {
+            set_rule(&mut self.grammar, *letter, None);
+        }
+        for (letter, replacement) in production_rules {
+            set_rule(&mut self.grammar, *letter, replacement.clone());
+        }
+        self.production_rules = rules_by_letter(&self.grammar);
         self.compute_staunching_factor();
     }
-    pub fn with_actions(&mut self, actions: &[(L, Option<fn(payload: &mut LindenmayerPayload)>)]) {
-        self.actions = actions.to_owned().into_iter().collect();
+    pub fn with_actions(&mut self, actions: &[(L, Option<Action>)]) {
+        self.actions = actions.iter().copied().collect();
+        self.recompute_vertices();
     }
     pub fn change_production_rule(&mut self, letter: L, replacement: Option<Vec<L>>) {
-        self.production_rules.insert(letter, replacement);
+        set_rule(&mut self.grammar, letter, replacement);
+        self.production_rules = rules_by_letter(&self.grammar);
         self.compute_staunching_factor();
     }
-    pub fn change_action(&mut self, letter: L, action: Option<fn(payload: &mut LindenmayerPayload)>) {
+    pub fn change_action(&mut self, letter: L, action: Option<Action>) {
+        self.action_bindings.remove(&letter);
         self.actions.insert(letter, action);
     }
+    // Letters bound by name follow the changes to the registry, which can
+    // be shared with other systems. Every system starts out with a registry
+    // of its own holding the turtle actions.
+    pub fn with_action_registry(&mut self, registry: SharedActionRegistry) {
+        self.action_registry = registry;
+        self.action_generation = None;
+        self.refresh_actions();
+    }
+    pub fn action_registry(&self) -> &SharedActionRegistry {
+        &self.action_registry
+    }
+    // Like change_action, with the action looked up in the registry.
+    pub fn bind_action(&mut self, letter: L, name: &str) -> Result<(), ActionError> {
+        let registry = &self.action_registry;
+        let action = registry.read().unwrap_or_else(PoisonError::into_inner).action(name)?;
+        self.actions.insert(letter, Some(action));
+        self.action_bindings.insert(letter, name.to_owned());
+        // The vertices are recomputed with the binding on the next update.
+        self.action_generation = None;
+        Ok(())
+    }
+    // Binds several letters at once, see bind_action. Nothing is bound if
+    // one of the names is unknown.
+    pub fn with_action_bindings(&mut self, bindings: &[(L, &str)]) -> Result<(), ActionError> {
+        let registry = self.action_registry.read().unwrap_or_else(PoisonError::into_inner);
+        let actions = bindings.iter()
+            .map(|(letter, name)| registry.action(name).map(|action| (*letter, action)))
+            .collect::<Result<Vec<_>, _>>()?;
+        drop(registry);
+        for ((letter, action), (_, name)) in actions.into_iter().zip(bindings) {
+            self.actions.insert(letter, Some(action));
+            self.action_bindings.insert(letter, (*name).to_owned());
+        }
+        self.recompute_vertices();
+        Ok(())
+    }
+    pub fn action_name(&self, letter: L) -> Option<&str> {
+        self.action_bindings.get(&letter).map(String::as_str)
+    }
+    // Resolves the letters bound by name again if the registry changed
+    // since, and recomputes the vertices of every depth computed so far.
+    // A name the registry does not know (anymore) leaves its letter without
+    // an action. Returns whether anything was resolved.
+    pub fn refresh_actions(&mut self) -> bool {
+        let registry = self.action_registry.read().unwrap_or_else(PoisonError::into_inner);
+        if self.action_generation == Some(registry.generation()) {
+            return false;
+        }
+        self.action_generation = Some(registry.generation());
+        for (letter, name) in &self.action_bindings {
+            self.actions.insert(*letter, registry.action(name).ok());
+        }
+        drop(registry);
+
+        self.recompute_vertices();
+        true
+    }
+    // The vertices of every depth computed so far, after the actions changed.
+    fn recompute_vertices(&mut self) {
+        self.compute_staunching_factor();
+        let words = std::mem::take(&mut self.word_stack);
+        self.vertex_stack.clear();
+        for word in words {
+            self.word_stack.push(word);
+            self.apply_actions();
+        }
+    }
     pub fn apply_production_rules(&mut self) {
         let mut result = vec![];
         if let Some(word) = self.word_stack.last() {
-            for letter in word {
-                if let Some(Some(replacement)) = self.production_rules.get(letter) {
-                    result.append(&mut replacement.clone());
-                } else {
-                    result.push(*letter);
-                }
-            }
+            result = rewrite(word, &self.production_rules);
         }
         self.word_stack.push(result);
     }
@@ -198,22 +441,20 @@ impl<L: Letter> LindenmayerSystem<L> {
         let depth = self.word_stack.len() - 1;
         if let Some(word) = self.word_stack.last() {
             self.payload.compute_vertices(word, &self.actions, S);
-            for entry in &mut self.payload.vertex_buffer {
-                if let Some(vertex) = entry {
-                    vertex.scale(self.staunching_factor.powi(depth as i32));
-                }
-            }
+            scale_to_depth(&mut self.payload.vertex_buffer, self.staunching_factor, depth);
             self.vertex_stack.push(self.payload.vertex_buffer.clone());
         }
     }
     pub fn compute_staunching_factor(&mut self) {
         let mut payload = LindenmayerPayload::new();
+        payload.set_turning_angle(self.angle);
         if let Some(Some(replacement)) = self.production_rules.get(&L::forward()) {
             payload.compute_base_vertices(replacement, &self.actions);
             self.staunching_factor = 1.0 / payload.vertex_buffer.last().unwrap().unwrap().norm();
         }
     }
     pub fn update_vertex_stack(&mut self, depth: usize) {
+        self.refresh_actions();
         if depth >= self.vertex_stack.len() {
             for _ in 1..=(depth - self.vertex_stack.len() + 1) {
                 self.apply_production_rules();
@@ -221,14 +462,253 @@ impl<L: Letter> LindenmayerSystem<L> {
             }
         }
     }
-    pub fn get_vertex_stack_at(&self, index: usize) -> Option<&Vec<Option<MathPosition>>> {
-        if index < self.vertex_stack.len() {
-            return Some(&self.vertex_stack[index]);
+    pub fn with_limits(&mut self, limits: DerivationLimits) {
+        self.limits = limits;
+    }
+    pub fn limits(&self) -> &DerivationLimits {
+        &self.limits
+    }
+    // The lengths of the missing words follow from the growth of the rules.
+    // The vertices are estimated by running the action of every letter once
+    // on a fresh turtle, so actions drawing more depending on the state of
+    // the turtle can still go beyond the limit.
+    pub fn check_limits(&self, depth: usize) -> Result<(), LimitError> {
+        let successors = self.production_rules.iter()
+            .filter_map(|(letter, successor)| Some((*letter, successor.clone()?)))
+            .collect();
+        let growth = GrowthMatrix::new(&self.grammar.axiom()[..], &successors);
+        let vertices_per_letter: Vec<u128> = growth.letters().iter()
+            .map(|letter| {
+                let mut payload = LindenmayerPayload::new();
+                payload.set_turning_angle(self.angle);
+                payload.compute_base_vertices(std::slice::from_ref(letter), &self.actions);
+                payload.vertex_buffer.len() as u128 - 1
+            })
+            .collect();
+
+        let letter_size = std::mem::size_of::<L>() as u128;
+        let vertex_size = std::mem::size_of::<Option<MathPosition>>() as u128;
+        let mut bytes = self.word_stack.iter().map(|word| word.len() as u128 * letter_size)
+            .chain(self.vertex_stack.iter().map(|vertices| vertices.len() as u128 * vertex_size))
+            .fold(0u128, u128::saturating_add);
+
+        for depth in self.vertex_stack.len()..=depth {
+            let counts = growth.letter_counts(depth as u64);
+            let length = counts.iter().map(saturating_u128).fold(0u128, u128::saturating_add);
+            let vertices = counts.iter().zip(&vertices_per_letter)
+                .map(|(count, per_letter)| saturating_u128(count).saturating_mul(*per_letter))
+                .fold(1u128, u128::saturating_add);
+            bytes = bytes
+                .saturating_add(length.saturating_mul(letter_size))
+                .saturating_add(vertices.saturating_mul(vertex_size));
+            self.limits.check_word_length(depth, length)?;
+            self.limits.check_vertices(depth, vertices)?;
+            self.limits.check_memory(depth, bytes)?;
         }
-        return None;
+        Ok(())
+    }
+    // Same as update_vertex_stack, unless the limits would be exceeded on the way.
+    pub fn try_update_vertex_stack(&mut self, depth: usize) -> Result<(), LimitError> {
+        self.check_limits(depth)?;
+        self.update_vertex_stack(depth);
+        Ok(())
+    }
+    // Streams the letters of the given depth without computing the words in between.
+    pub fn derive_letters(&self, depth: usize) -> Derivation<'_, L> {
+        Derivation::new(&self.grammar.axiom()[..], &self.production_rules, depth)
+    }
+    // Streams the turtle vertices of the given depth. Unlike the vertex stack
+    // they are neither centered nor scaled.
+    pub fn derive_vertices(&self, depth: usize) -> TurtleVertices<'_, L, Derivation<'_, L>> {
+        TurtleVertices::new(self.derive_letters(depth), &self.actions, self.angle)
+    }
+    // The word of the given depth as shared expansions, for depths too deep to derive.
+    pub fn shared_word(&self, depth: usize) -> SharedWord<L> {
+        SharedWord::new(&self.grammar.axiom()[..], &self.production_rules, depth)
+    }
+    // What it takes to compute the iterations after the deepest one so far.
+    // Actions bound by name are taken as they were last resolved, see
+    // refresh_actions.
+    pub fn continuation(&self) -> Continuation<L> {
+        Continuation {
+            depth: self.computed_depth(),
+            word: self.word_stack.last().cloned().unwrap_or_default(),
+            production_rules: self.production_rules.clone(),
+            actions: self.actions.clone(),
+            angle: self.angle,
+            staunching_factor: self.staunching_factor,
+        }
+    }
+    // Appends an iteration computed elsewhere, e.g. by a Continuation on
+    // a worker thread.
+    pub fn append_iteration(&mut self, word: Vec<L>, vertices: Vec<Option<MathPosition>>) {
+        self.word_stack.push(word);
+        self.vertex_stack.push(vertices);
+    }
+    // The deepest iteration computed so far.
+    pub fn computed_depth(&self) -> usize {
+        self.vertex_stack.len() - 1
+    }
+    pub fn get_vertex_stack_at(&self, index: usize) -> Option<&Vec<Option<MathPosition>>> {
+        self.vertex_stack.get(index)
+    }
+    pub fn get_word_stack_at(&self, index: usize) -> Option<&Vec<L>> {
+        self.word_stack.get(index)
     }
     pub fn get_vertex_stack(&self) -> &Vec<Vec<Option<MathPosition>>> {
-        return &self.vertex_stack;
+        &self.vertex_stack
+    }
+}
+
+// Replaces the rule of the letter, or removes it for None. The letters are
+// made known to the grammar first, so a rule of a single letter is valid.
+fn set_rule<L: Letter>(grammar: &mut Grammar<L>, letter: L, replacement: Option<Vec<L>>) {
+    let rules = match replacement {
+        Some(replacement) => {
+            grammar.add_non_terminals(&[letter]);
+            grammar.add_terminals(&replacement);
+            vec![ProductionRule::new(Word::from(letter), Word::new(replacement))]
+        }
+        None => Vec::new(),
+    };
+    grammar.replace_rules(&[letter], rules).unwrap();
+}
+
+// Only called for grammars whose rules were checked by single_letter_rules
+// or added by set_rule.
+fn rules_by_letter<L: Letter>(grammar: &Grammar<L>) -> HashMap<L, Option<Vec<L>>> {
+    grammar.single_letter_rules().unwrap().into_iter()
+        .map(|(letter, successor)| (*letter, Some(successor[..].to_vec())))
+        .collect()
+}
+
+// Every letter is rewritten on its own, so any part of a word can be
+// rewritten independently of the rest.
+fn rewrite<L: Letter>(word: &[L], production_rules: &HashMap<L, Option<Vec<L>>>) -> Vec<L> {
+    let mut result = Vec::with_capacity(word.len());
+    rewrite_into(word, production_rules, &mut result);
+    result
+}
+
+fn rewrite_into<L: Letter>(word: &[L], production_rules: &HashMap<L, Option<Vec<L>>>, result: &mut Vec<L>) {
+    for letter in word {
+        if let Some(Some(replacement)) = production_rules.get(letter) {
+            result.extend_from_slice(replacement);
+        } else {
+            result.push(*letter);
+        }
+    }
+}
+
+// The deeper the iteration, the shorter the lines, so every depth covers
+// about the same area.
+fn scale_to_depth(vertices: &mut [Option<MathPosition>], staunching_factor: f32, depth: usize) {
+    for vertex in vertices.iter_mut().flatten() {
+        vertex.scale(staunching_factor.powi(depth as i32));
+    }
+}
+
+// How many letters are rewritten or interpreted between two looks at the
+// cancellation flag of a Continuation.
+pub const CANCEL_CHECK_INTERVAL: usize = 1 << 16;
+
+// The deepest word of a LindenmayerSystem with its rules and actions, but
+// without the iterations before it. A worker thread derives the following
+// iterations from it and hands them back with append_iteration. Both steps
+// give up as soon as the cancellation flag is set.
+pub struct Continuation<L: Letter> {
+    depth: usize,
+    word: Vec<L>,
+    production_rules: HashMap<L, Option<Vec<L>>>,
+    actions: HashMap<L, Option<Action>>,
+    angle: f32,
+    staunching_factor: f32,
+}
+
+impl<L: Letter + Send + Sync> Continuation<L> {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    pub fn into_word(self) -> Vec<L> {
+        self.word
+    }
+    // Rewrites the word into the one of the next depth and returns the
+    // previous word. If cancelled, the word is left as it was.
+    pub fn rewrite(&mut self, cancelled: &AtomicBool) -> Option<Vec<L>> {
+        let next = rewrite_cancellable(&self.word, &self.production_rules, cancelled)?;
+        self.depth += 1;
+        Some(std::mem::replace(&mut self.word, next))
+    }
+    // The vertices of the word, centered and scaled like those of the
+    // vertex stack of the system.
+    pub fn vertices(&self, cancelled: &AtomicBool) -> Option<Vec<Option<MathPosition>>> {
+        let mut payload = LindenmayerPayload::new();
+        payload.set_turning_angle(self.angle);
+        payload.begin_vertices();
+        for chunk in self.word.chunks(CANCEL_CHECK_INTERVAL) {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            payload.interpret_letters(chunk, &self.actions);
+        }
+        payload.finish_vertices(S);
+        scale_to_depth(&mut payload.vertex_buffer, self.staunching_factor, self.depth);
+        Some(payload.vertex_buffer)
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn rewrite_cancellable<L: Letter>(word: &[L], production_rules: &HashMap<L, Option<Vec<L>>>, cancelled: &AtomicBool) -> Option<Vec<L>> {
+    let mut result = Vec::with_capacity(word.len());
+    for chunk in word.chunks(CANCEL_CHECK_INTERVAL) {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        rewrite_into(chunk, production_rules, &mut result);
+    }
+    Some(result)
+}
+
+#[cfg(feature = "parallel")]
+fn rewrite_cancellable<L: Letter + Send + Sync>(word: &[L], production_rules: &HashMap<L, Option<Vec<L>>>, cancelled: &AtomicBool) -> Option<Vec<L>> {
+    use rayon::prelude::*;
+
+    let chunks: Option<Vec<Vec<L>>> = word.par_chunks(PARALLEL_CHUNK_SIZE)
+        .map(|chunk| (!cancelled.load(Ordering::Relaxed)).then(|| rewrite(chunk, production_rules)))
+        .collect();
+    chunks.map(|chunks| chunks.concat())
+}
+
+// Words shorter than this are not worth handing out to other threads.
+#[cfg(feature = "parallel")]
+pub const PARALLEL_CHUNK_SIZE: usize = 1 << 16;
+
+#[cfg(feature = "parallel")]
+impl<L: Letter + Send + Sync> LindenmayerSystem<L> {
+    // Same as apply_production_rules, but the word is cut into chunks that
+    // are rewritten on all cores and concatenated again in order.
+    pub fn apply_production_rules_parallel(&mut self) {
+        use rayon::prelude::*;
+
+        let mut result = vec![];
+        if let Some(word) = self.word_stack.last() {
+            if word.len() <= PARALLEL_CHUNK_SIZE {
+                result = rewrite(word, &self.production_rules);
+            } else {
+                let chunks: Vec<Vec<L>> = word.par_chunks(PARALLEL_CHUNK_SIZE)
+                    .map(|chunk| rewrite(chunk, &self.production_rules))
+                    .collect();
+                result = chunks.concat();
+            }
+        }
+        self.word_stack.push(result);
+    }
+    pub fn update_vertex_stack_parallel(&mut self, depth: usize) {
+        self.refresh_actions();
+        while self.vertex_stack.len() <= depth {
+            self.apply_production_rules_parallel();
+            self.apply_actions();
+        }
     }
 }
 
@@ -246,26 +726,11 @@ impl LindenmayerSystem<LindenmayerLetter> {
 
         let angle = 60.0f32.to_radians();
 
-        let actions: Vec<(LindenmayerLetter, Option<fn(payload: &mut LindenmayerPayload)>)> = vec![
-            (
-                LindenmayerLetter::F,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.update_current_position();
-                    payload.push_current_position();
-                }),
-            ),
-            (
-                LindenmayerLetter::L,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.increase_current_angle(60.0f32.to_radians());
-                }),
-            ),
-            (
-                LindenmayerLetter::R,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.decrease_current_angle(60.0f32.to_radians());
-                }),
-            ),
+        // The built-ins draw with the turtle actions every system starts with.
+        let actions = [
+            (LindenmayerLetter::F, "forward"),
+            (LindenmayerLetter::L, "turn_left"),
+            (LindenmayerLetter::R, "turn_right"),
         ];
 
         let production_rules = vec![
@@ -282,12 +747,15 @@ impl LindenmayerSystem<LindenmayerLetter> {
         ];
 
 
-        LindenmayerSystem::new(
+        let mut system = LindenmayerSystem::new(
             &starting_word,
             angle,
             &production_rules,
-            &actions,
-        )
+            &[],
+        );
+        system.with_action_bindings(&actions).unwrap();
+        system.change_name("koch");
+        system
     }
 
     pub fn levy() -> Self {
@@ -297,26 +765,10 @@ impl LindenmayerSystem<LindenmayerLetter> {
 
         let angle = 45.0f32.to_radians();
 
-        let actions: Vec<(LindenmayerLetter, Option<fn(payload: &mut LindenmayerPayload)>)> = vec![
-            (
-                LindenmayerLetter::F,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.update_current_position();
-                    payload.push_current_position();
-                }),
-            ),
-            (
-                LindenmayerLetter::L,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.increase_current_angle(45.0f32.to_radians());
-                }),
-            ),
-            (
-                LindenmayerLetter::R,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.decrease_current_angle(45.0f32.to_radians());
-                }),
-            ),
+        let actions = [
+            (LindenmayerLetter::F, "forward"),
+            (LindenmayerLetter::L, "turn_left"),
+            (LindenmayerLetter::R, "turn_right"),
         ];
 
         let production_rules = vec![
@@ -331,12 +783,15 @@ impl LindenmayerSystem<LindenmayerLetter> {
         ];
 
 
-        LindenmayerSystem::new(
+        let mut system = LindenmayerSystem::new(
             &starting_word,
             angle,
             &production_rules,
-            &actions,
-        )
+            &[],
+        );
+        system.with_action_bindings(&actions).unwrap();
+        system.change_name("levy");
+        system
     }
 
     pub fn dragon_curve() -> Self {
@@ -346,33 +801,11 @@ impl LindenmayerSystem<LindenmayerLetter> {
 
         let angle = 45.0f32.to_radians();
 
-        let actions: Vec<(LindenmayerLetter, Option<fn(payload: &mut LindenmayerPayload)>)> = vec![
-            (
-                LindenmayerLetter::F,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.update_current_position();
-                    payload.push_current_position();
-                }),
-            ),
-            (
-                LindenmayerLetter::G,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.update_current_position();
-                    payload.push_current_position();
-                }),
-            ),
-            (
-                LindenmayerLetter::L,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.increase_current_angle(45.0f32.to_radians());
-                }),
-            ),
-            (
-                LindenmayerLetter::R,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.decrease_current_angle(45.0f32.to_radians());
-                }),
-            ),
+        let actions = [
+            (LindenmayerLetter::F, "forward"),
+            (LindenmayerLetter::G, "forward"),
+            (LindenmayerLetter::L, "turn_left"),
+            (LindenmayerLetter::R, "turn_right"),
         ];
 
         let production_rules = vec![
@@ -395,12 +828,15 @@ impl LindenmayerSystem<LindenmayerLetter> {
         ];
 
 
-        LindenmayerSystem::new(
+        let mut system = LindenmayerSystem::new(
             &starting_word,
             angle,
             &production_rules,
-            &actions,
-        )
+            &[],
+        );
+        system.with_action_bindings(&actions).unwrap();
+        system.change_name("dragon_curve");
+        system
     }
 
     pub fn first_plant() -> Self {
@@ -410,40 +846,12 @@ impl LindenmayerSystem<LindenmayerLetter> {
 
         let angle = 25.0f32.to_radians();
 
-        let actions: Vec<(LindenmayerLetter, Option<fn(payload: &mut LindenmayerPayload)>)> = vec![
-            (
-                LindenmayerLetter::F,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.update_current_position();
-                    payload.push_current_position();
-                }),
-            ),
-            (
-                LindenmayerLetter::L,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.increase_current_angle(25.0f32.to_radians());
-                }),
-            ),
-            (
-                LindenmayerLetter::R,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.decrease_current_angle(25.0f32.to_radians());
-                }),
-            ),
-            (
-                LindenmayerLetter::PUSH,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.save_current_position_and_angle();
-                }),
-            ),
-            (
-                LindenmayerLetter::POP,
-                Some(|payload: &mut LindenmayerPayload| {
-                    payload.pop_and_restore_current_position_and_angle();
-                    payload.vertex_buffer.push(None);
-                    payload.push_current_position();
-                })
-            ),
+        let actions = [
+            (LindenmayerLetter::F, "forward"),
+            (LindenmayerLetter::L, "turn_left"),
+            (LindenmayerLetter::R, "turn_right"),
+            (LindenmayerLetter::PUSH, "push"),
+            (LindenmayerLetter::POP, "pop"),
         ];
 
         let production_rules = vec![
@@ -474,12 +882,15 @@ impl LindenmayerSystem<LindenmayerLetter> {
         ];
 
 
-        LindenmayerSystem::new(
+        let mut system = LindenmayerSystem::new(
             &starting_word,
             angle,
             &production_rules,
-            &actions,
-        )
+            &[],
+        );
+        system.with_action_bindings(&actions).unwrap();
+        system.change_name("first_plant");
+        system
     }
 }
 
@@ -502,3 +913,30 @@ impl Letter for LindenmayerLetter {
         Self::F
     }
 }
+
+impl Letter for char {
+    fn forward() -> Self {
+        'F'
+    }
+}
+
+// TESTS
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+
+    mod apply_production_rules_parallel {
+        use crate::tryout::{LindenmayerSystem, PARALLEL_CHUNK_SIZE};
+
+        #[test]
+        fn same_word_as_sequential() {
+            let mut sequential = LindenmayerSystem::dragon_curve();
+            let mut parallel = sequential.clone();
+            sequential.update_vertex_stack(16);
+            parallel.update_vertex_stack_parallel(16);
+            assert!(sequential.get_word_stack_at(15).unwrap().len() > PARALLEL_CHUNK_SIZE);
+            assert_eq!(parallel.get_word_stack_at(16), sequential.get_word_stack_at(16));
+            assert_eq!(parallel.get_vertex_stack_at(16), sequential.get_vertex_stack_at(16));
+        }
+    }
+}
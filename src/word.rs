@@ -2,7 +2,9 @@ use std::fmt::{Debug, Display};
 use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::slice::SliceIndex;
 
+// With the serde feature a word is saved as the list of its letters.
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Word<T>(Vec<T>);
 
 //****************************************************************************
@@ -99,7 +101,7 @@ impl<T> Word<T>
     where
         T: PartialEq,
     {
-        self.0.iter().any(|elem| letters.iter().any(|letter| letter != elem))
+        self.0.iter().any(|elem| !letters.contains(elem))
     }
 
     //pub fn first_subword(&self, valid_subwords: &[&Word<T>]) -> Option<&[T]>
@@ -674,4 +676,13 @@ mod tests {
 //        let result = word_of_functions.apply_replacements(&dictionary);
 //        assert_eq!(result, Word::from_iter([second, third, first, third, second]));
 //    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let word = Word::from("F+[X]");
+        let json = serde_json::to_string(&word).unwrap();
+        assert_eq!(json, r#"["F","+","[","X","]"]"#);
+        assert_eq!(serde_json::from_str::<Word<char>>(&json).unwrap(), word);
+    }
 }
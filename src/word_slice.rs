@@ -1,13 +1,126 @@
-use std::{collections::HashMap, hash::Hash};
+use std::{collections::HashMap, fmt::Display, hash::Hash};
 
+use crate::matcher::SubwordMatcher;
 use crate::semantics::Payload;
 
+// A context sensitive rule `left < predecessor > right -> successor`.
+// The predecessor only gets replaced if it is preceded by the left context
+// and followed by the right context. An empty context always matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextRule<T> {
+    left: Vec<T>,
+    predecessor: Vec<T>,
+    right: Vec<T>,
+    successor: Vec<T>,
+}
+
+impl<T> ContextRule<T> {
+    pub fn new(left: Vec<T>, predecessor: Vec<T>, right: Vec<T>, successor: Vec<T>) -> Self {
+        ContextRule {
+            left,
+            predecessor,
+            right,
+            successor,
+        }
+    }
+    pub fn left(&self) -> &[T] {
+        &self.left
+    }
+    pub fn predecessor(&self) -> &[T] {
+        &self.predecessor
+    }
+    pub fn right(&self) -> &[T] {
+        &self.right
+    }
+    pub fn successor(&self) -> &[T] {
+        &self.successor
+    }
+}
+
+impl<T> Display for ContextRule<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn letters<T: Display>(f: &mut std::fmt::Formatter<'_>, letters: &[T]) -> std::fmt::Result {
+            for letter in letters {
+                write!(f, "{}", letter)?;
+            }
+            Ok(())
+        }
+
+        if !self.left.is_empty() {
+            letters(f, &self.left)?;
+            write!(f, " < ")?;
+        }
+        letters(f, &self.predecessor)?;
+        if !self.right.is_empty() {
+            write!(f, " > ")?;
+            letters(f, &self.right)?;
+        }
+        write!(f, " -> ")?;
+        letters(f, &self.successor)
+    }
+}
+
+// Letters that are skipped while matching the context of a rule.
+// Ignored letters (e.g. turtle turns) are simply stepped over, while
+// the branch letters make the matching follow the branching structure:
+// the left context of a letter is found in its parent branch and whole
+// side branches are skipped when looking for the right context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextIgnore<T> {
+    ignored: Vec<T>,
+    branches: Option<(T, T)>,
+}
+
+impl<T> ContextIgnore<T>
+where
+    T: PartialEq,
+{
+    pub fn new() -> Self {
+        ContextIgnore {
+            ignored: Vec::new(),
+            branches: None,
+        }
+    }
+    pub fn with_ignored(self, ignored: Vec<T>) -> Self {
+        Self { ignored, ..self }
+    }
+    pub fn with_branches(self, push: T, pop: T) -> Self {
+        Self { branches: Some((push, pop)), ..self }
+    }
+    pub fn is_ignored(&self, letter: &T) -> bool {
+        self.ignored.contains(letter)
+    }
+    pub fn is_push(&self, letter: &T) -> bool {
+        matches!(&self.branches, Some((push, _)) if push == letter)
+    }
+    pub fn is_pop(&self, letter: &T) -> bool {
+        matches!(&self.branches, Some((_, pop)) if pop == letter)
+    }
+}
+
+impl<T> Default for ContextIgnore<T>
+where
+    T: PartialEq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub trait Word {
     type Owned;
+    type Letter;
     fn first_subword(&self, valid_subwords: &[&Self]) -> Option<&Self>;
-    fn subwords(&self, valid_subwords: &[&Self]) -> Vec<&Self>;
+    fn subwords(&self, matcher: &SubwordMatcher<Self::Letter>) -> Vec<&Self>;
     fn contains(&self, word: &Self) -> bool;
     fn apply_relacements(&self, replacements: &HashMap<Self::Owned, Self::Owned>) -> Self::Owned;
+    fn apply_matched_replacements(&self, matcher: &SubwordMatcher<Self::Letter>, replacements: &HashMap<Self::Owned, Self::Owned>) -> Self::Owned;
+    fn matches_left_context(&self, position: usize, context: &[Self::Letter], ignore: &ContextIgnore<Self::Letter>) -> bool;
+    fn matches_right_context(&self, position: usize, context: &[Self::Letter], ignore: &ContextIgnore<Self::Letter>) -> bool;
+    fn apply_context_replacements(&self, rules: &[ContextRule<Self::Letter>], replacements: &HashMap<Self::Owned, Self::Owned>, ignore: &ContextIgnore<Self::Letter>) -> Self::Owned;
     fn apply_semantics<P: Payload>(&self, semantics: &HashMap<Self::Owned, fn(&mut P)>, target: &mut P);
 }
 
@@ -16,6 +129,7 @@ where
     T: Clone + PartialEq + Eq + Hash,
 {
     type Owned = Vec<T>;
+    type Letter = T;
     fn first_subword(&self, valid_subwords: &[&Self]) -> Option<&Self> {
         let mut longest_subword: &[T] = &[];
 
@@ -33,19 +147,12 @@ where
             return None;
         }
 
-        return Some(longest_subword);
+        Some(longest_subword)
     }
 
-    fn subwords(&self, valid_subwords: &[&Self]) -> Vec<&Self> {
-        let mut subwords = Vec::new();
-        let mut start = 0;
-
-        while let Some(word) = self[start..].first_subword(valid_subwords) {
-            subwords.push(word);
-            start += word.len();
-        }
-
-        return subwords;
+    // The matcher is built once from the valid subwords, see SubwordMatcher::new.
+    fn subwords(&self, matcher: &SubwordMatcher<T>) -> Vec<&Self> {
+        matcher.subwords(self).collect()
     }
 
     fn contains(&self, word: &Self) -> bool {
@@ -60,31 +167,159 @@ where
             }
         }
 
-        return is_contained;
+        is_contained
     }
 
+    // Letters that do not start any valid subword are copied over unchanged,
+    // so the rules only need to cover the letters that actually get rewritten.
     fn apply_relacements(&self, replacements: &HashMap<Self::Owned, Self::Owned>) -> Self::Owned {
-        let valid_subwords: Vec<&Self> = replacements.keys().map(|word| &word[..]).collect();
-        let word = self.subwords(&valid_subwords[..]).into_iter()
-            .map(|word| {
-                if let Some(replacement) = replacements.get(word) {
-                    replacement
-                } else {
-                    word
+        let matcher = SubwordMatcher::new(replacements.keys().map(|word| &word[..]));
+        self.apply_matched_replacements(&matcher, replacements)
+    }
+
+    // Same as apply_relacements with a matcher built beforehand from the keys
+    // of the replacements, so it can be reused for every iteration.
+    fn apply_matched_replacements(&self, matcher: &SubwordMatcher<T>, replacements: &HashMap<Self::Owned, Self::Owned>) -> Self::Owned {
+        matcher.rewrite(self, |subword, word| word.extend_from_slice(&replacements[subword]))
+    }
+
+    // The left context has to end right before position. Walking backwards,
+    // a closed side branch is skipped as a whole and an opening bracket
+    // leads from the current branch into its parent.
+    fn matches_left_context(&self, position: usize, context: &[T], ignore: &ContextIgnore<T>) -> bool {
+        let mut index = position;
+        let mut remaining = context.len();
+
+        while remaining > 0 {
+            if index == 0 {
+                return false;
+            }
+            index -= 1;
+            let letter = &self[index];
+
+            if ignore.is_ignored(letter) || ignore.is_push(letter) {
+                continue;
+            }
+            if ignore.is_pop(letter) {
+                let mut depth = 1;
+                while depth > 0 {
+                    if index == 0 {
+                        return false;
+                    }
+                    index -= 1;
+                    if ignore.is_pop(&self[index]) {
+                        depth += 1;
+                    } else if ignore.is_push(&self[index]) {
+                        depth -= 1;
+                    }
+                }
+                continue;
+            }
+            if letter != &context[remaining - 1] {
+                return false;
+            }
+            remaining -= 1;
+        }
+
+        true
+    }
+
+    // The right context has to start at position. Walking forwards,
+    // side branches are skipped as a whole, and the end of the current
+    // branch ends the search.
+    fn matches_right_context(&self, position: usize, context: &[T], ignore: &ContextIgnore<T>) -> bool {
+        let mut index = position;
+        let mut matched = 0;
+
+        while matched < context.len() {
+            if index >= self.len() {
+                return false;
+            }
+            let letter = &self[index];
+            index += 1;
+
+            if ignore.is_ignored(letter) {
+                continue;
+            }
+            if ignore.is_pop(letter) {
+                return false;
+            }
+            if ignore.is_push(letter) {
+                let mut depth = 1;
+                while depth > 0 {
+                    if index >= self.len() {
+                        return false;
+                    }
+                    if ignore.is_push(&self[index]) {
+                        depth += 1;
+                    } else if ignore.is_pop(&self[index]) {
+                        depth -= 1;
+                    }
+                    index += 1;
                 }
-            })
-            .map(|word| word.to_owned())
-            .flatten()
-            .collect();
+                continue;
+            }
+            if letter != &context[matched] {
+                return false;
+            }
+            matched += 1;
+        }
+
+        true
+    }
+
+    // Context sensitive rules take precedence over the context free replacements.
+    // If several context sensitive rules apply at the same position, the one with
+    // the longest predecessor wins, and among those the first one in the list.
+    fn apply_context_replacements(&self, rules: &[ContextRule<T>], replacements: &HashMap<Self::Owned, Self::Owned>, ignore: &ContextIgnore<T>) -> Self::Owned {
+        let matcher = SubwordMatcher::new(replacements.keys().map(|word| &word[..]));
+        let mut longest_keys = matcher.longest_keys(self);
+        let mut word = Vec::with_capacity(self.len());
+        let mut start = 0;
+
+        while start < self.len() {
+            let mut applicable_rule: Option<&ContextRule<T>> = None;
+            for rule in rules {
+                let end = start + rule.predecessor.len();
+                if rule.predecessor.is_empty() || !self[start..].starts_with(&rule.predecessor) {
+                    continue;
+                }
+                if let Some(current) = applicable_rule {
+                    if current.predecessor.len() >= rule.predecessor.len() {
+                        continue;
+                    }
+                }
+                if self.matches_left_context(start, &rule.left, ignore) && self.matches_right_context(end, &rule.right, ignore) {
+                    applicable_rule = Some(rule);
+                }
+            }
+
+            if let Some(rule) = applicable_rule {
+                word.extend_from_slice(&rule.successor);
+                start += rule.predecessor.len();
+                continue;
+            }
+
+            match longest_keys.at(start) {
+                Some(length) => {
+                    word.extend_from_slice(&replacements[&self[start..start + length]]);
+                    start += length;
+                }
+                None => {
+                    word.push(self[start].clone());
+                    start += 1;
+                }
+            }
+        }
+
         word
     }
 
     fn apply_semantics<P: Payload>(&self, semantics: &HashMap<Self::Owned, fn(&mut P)>, target: &mut P) {
-        let valid_subwords: Vec<&Self> = semantics.keys().map(|word| &word[..]).collect();
-        for word in self.subwords(&valid_subwords[..]){
-            if let Some(action) = semantics.get(word) {
-                action(target);
-            }
+        let matcher = SubwordMatcher::new(semantics.keys().map(|word| &word[..]));
+
+        for subword in matcher.segments(self).flatten() {
+            semantics[subword](target);
         }
     }
 }
@@ -97,8 +332,8 @@ mod test {
 
         #[test]
         fn return_none_for_empty_word() {
-            let word = vec![];
-            let valid_subwords = vec![
+            let word = [];
+            let valid_subwords = [
                 &[1][..],
             ];
             let result = word.first_subword(&valid_subwords[..]);
@@ -107,7 +342,7 @@ mod test {
 
         #[test]
         fn return_none_if_valid_word_is_empty() {
-            let word = vec![1, 2, 3];
+            let word = [1, 2, 3];
             let valid_subwords: Vec<&[i32]> = vec![
                 &[],
             ];
@@ -117,8 +352,8 @@ mod test {
 
         #[test]
         fn context_free() {
-            let word = vec![1, 2, 3, 4, 5];
-            let valid_subwords = vec![
+            let word = [1, 2, 3, 4, 5];
+            let valid_subwords = [
                 &[1][..],
             ];
             let result = word.first_subword(&valid_subwords[..]);
@@ -128,8 +363,8 @@ mod test {
 
         #[test]
         fn context_dependent() {
-            let word = vec![1, 2, 3, 4, 5];
-            let valid_subwords = vec![
+            let word = [1, 2, 3, 4, 5];
+            let valid_subwords = [
                 &[1][..],
                 &[1, 2][..],
             ];
@@ -140,6 +375,7 @@ mod test {
     }
 
     mod subwords {
+        use crate::matcher::SubwordMatcher;
         use crate::word_slice::Word;
 
         #[test]
@@ -148,21 +384,21 @@ mod test {
             let valid_subwords = vec![
                 &[1][..]
             ];
-            let subwords = word.subwords(&valid_subwords);
+            let subwords = word.subwords(&SubwordMatcher::new(valid_subwords));
             assert_eq!(subwords, vec![] as Vec<&[i32]>);
         }
 
         #[test]
         fn return_no_subwords_for_empty_list_of_valid_words() {
-            let word = vec![1, 2, 3, 4, 5];
-            let valid_subwords = vec![];
-            let subwords = word.subwords(&valid_subwords);
+            let word = [1, 2, 3, 4, 5];
+            let valid_subwords: Vec<&[i32]> = vec![];
+            let subwords = word.subwords(&SubwordMatcher::new(valid_subwords));
             assert_eq!(subwords, vec![] as Vec<&[i32]>);
         }
 
         #[test]
         fn context_free() {
-            let word = vec![1, 2, 3, 4, 5];
+            let word = [1, 2, 3, 4, 5];
             let first = [1, 2];
             let second = [3];
             let third = [4, 5];
@@ -171,13 +407,13 @@ mod test {
                 &second[..],
                 &third[..],
             ];
-            let subwords = word.subwords(&valid_subwords);
+            let subwords = word.subwords(&SubwordMatcher::new(valid_subwords));
             assert_eq!(subwords, vec![&first[..], &second[..], &third[..]]);
         }
 
         #[test]
         fn context_dependent() {
-            let word = vec![1, 2, 3, 4, 5];
+            let word = [1, 2, 3, 4, 5];
             let first = [1, 2];
             let second = [1, 2, 3];
             let third = [4, 5];
@@ -186,8 +422,105 @@ mod test {
                 &second[..],
                 &third[..],
             ];
-            let subwords = word.subwords(&valid_subwords);
+            let subwords = word.subwords(&SubwordMatcher::new(valid_subwords));
             assert_eq!(subwords, vec![&second[..], &third[..]]);
         }
     }
+
+    mod apply_relacements {
+        use std::collections::HashMap;
+
+        use crate::word_slice::Word;
+
+        #[test]
+        fn copy_letters_without_replacement() {
+            let word = [1, 2, 3, 1];
+            let mut replacements = HashMap::new();
+            replacements.insert(vec![1], vec![4, 4]);
+            assert_eq!(word.apply_relacements(&replacements), vec![4, 4, 2, 3, 4, 4]);
+        }
+
+        #[test]
+        fn prefer_longest_subword() {
+            let word = [1, 2, 3];
+            let mut replacements = HashMap::new();
+            replacements.insert(vec![1], vec![5]);
+            replacements.insert(vec![1, 2], vec![6]);
+            assert_eq!(word.apply_relacements(&replacements), vec![6, 3]);
+        }
+    }
+
+    mod apply_context_replacements {
+        use std::collections::HashMap;
+
+        use crate::word_slice::{ContextIgnore, ContextRule, Word};
+
+        fn signal() -> Vec<ContextRule<char>> {
+            vec![
+                ContextRule::new(vec!['b'], vec!['a'], vec![], vec!['b']),
+                ContextRule::new(vec![], vec!['b'], vec![], vec!['a']),
+            ]
+        }
+
+        #[test]
+        fn propagate_signal() {
+            let word: Vec<char> = "baaa".chars().collect();
+            let result = word.apply_context_replacements(&signal(), &HashMap::new(), &ContextIgnore::new());
+            assert_eq!(result, "abaa".chars().collect::<Vec<char>>());
+        }
+
+        #[test]
+        fn right_context() {
+            let word: Vec<char> = "aaab".chars().collect();
+            let rules = vec![ContextRule::new(vec![], vec!['a'], vec!['b'], vec!['b'])];
+            let result = word.apply_context_replacements(&rules, &HashMap::new(), &ContextIgnore::new());
+            assert_eq!(result, "aabb".chars().collect::<Vec<char>>());
+        }
+
+        #[test]
+        fn skip_ignored_letters() {
+            let word: Vec<char> = "b+-a".chars().collect();
+            let ignore = ContextIgnore::new().with_ignored(vec!['+', '-']);
+            let result = word.apply_context_replacements(&signal(), &HashMap::new(), &ignore);
+            assert_eq!(result, "a+-b".chars().collect::<Vec<char>>());
+        }
+
+        #[test]
+        fn propagate_signal_into_and_past_branches() {
+            let word: Vec<char> = "b[a[a]]a".chars().collect();
+            let ignore = ContextIgnore::new().with_branches('[', ']');
+            let result = word.apply_context_replacements(&signal(), &HashMap::new(), &ignore);
+            assert_eq!(result, "a[b[a]]b".chars().collect::<Vec<char>>());
+        }
+
+        #[test]
+        fn right_context_skips_side_branches_and_stops_at_branch_end() {
+            let word: Vec<char> = "a[c]b[ab]".chars().collect();
+            let ignore = ContextIgnore::new().with_branches('[', ']');
+            let rules = vec![ContextRule::new(vec![], vec!['a'], vec!['b'], vec!['x'])];
+            let result = word.apply_context_replacements(&rules, &HashMap::new(), &ignore);
+            assert_eq!(result, "x[c]b[xb]".chars().collect::<Vec<char>>());
+
+            let word: Vec<char> = "[a]b".chars().collect();
+            let result = word.apply_context_replacements(&rules, &HashMap::new(), &ignore);
+            assert_eq!(result, word);
+        }
+
+        #[test]
+        fn fall_back_to_context_free_replacements() {
+            let word: Vec<char> = "ca".chars().collect();
+            let mut replacements = HashMap::new();
+            replacements.insert(vec!['a'], vec!['d']);
+            let result = word.apply_context_replacements(&signal(), &replacements, &ContextIgnore::new());
+            assert_eq!(result, "cd".chars().collect::<Vec<char>>());
+        }
+
+        #[test]
+        fn display() {
+            let rule = ContextRule::new(vec!['a'], vec!['b'], vec!['c'], vec!['x']);
+            assert_eq!(format!("{}", rule), "a < b > c -> x");
+            let rule = ContextRule::new(vec![], vec!['b'], vec![], vec!['x', 'y']);
+            assert_eq!(format!("{}", rule), "b -> xy");
+        }
+    }
 }
@@ -0,0 +1,271 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use crate::coordinates::MathPosition;
+use crate::limits::LimitError;
+use crate::tryout::{Letter, LindenmayerSystem};
+
+// Computes deep iterations of a LindenmayerSystem on a separate thread, so
+// the viewer keeps drawing while the words grow. The worker only gets the
+// deepest word with the rules and actions, see Continuation, and moves every
+// finished iteration back, to be appended to the system with append_iteration.
+// A word is sent once the next one is rewritten from it, so the worker never
+// holds more than two words.
+// The target depth can be raised while the worker runs. Cancelling takes
+// effect within CANCEL_CHECK_INTERVAL letters.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkerMessage<L: Letter> {
+    // The worker started on the given depth.
+    Started { depth: usize, target_depth: usize },
+    Iteration { depth: usize, word: Vec<L>, vertices: Vec<Option<MathPosition>> },
+    // The next depth would exceed the limits of the system, the worker stops.
+    LimitExceeded(LimitError),
+    // Only cancelled if cancel was called, stopping at the limits is not.
+    Finished { depth: usize, cancelled: bool },
+}
+
+pub struct DerivationWorker<L: Letter> {
+    receiver: Receiver<WorkerMessage<L>>,
+    cancelled: Arc<AtomicBool>,
+    target_depth: Arc<AtomicUsize>,
+    current_depth: usize,
+    finished: bool,
+}
+
+impl<L: Letter + Send + Sync + 'static> DerivationWorker<L> {
+    // Depths beyond the limits of the system are not computed, the worker
+    // stops before them and reports the exceeded limit.
+    pub fn spawn(system: &LindenmayerSystem<L>, target_depth: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut continuation = system.continuation();
+        let current_depth = continuation.depth();
+        let limit_error = system.check_limits(target_depth).err();
+        let target_depth = Arc::new(AtomicUsize::new(match &limit_error {
+            Some(error) => error.depth() - 1,
+            None => target_depth,
+        }));
+
+        let worker_cancelled = Arc::clone(&cancelled);
+        let worker_target_depth = Arc::clone(&target_depth);
+        thread::spawn(move || {
+            let cancelled = &*worker_cancelled;
+            // The vertices of the depth of the continuation, waiting for its word.
+            let mut vertices = None;
+            let mut sent_depth = current_depth;
+            while !cancelled.load(Ordering::Relaxed) {
+                let target_depth = worker_target_depth.load(Ordering::Relaxed);
+                let depth = continuation.depth() + 1;
+                if depth > target_depth {
+                    break;
+                }
+                if sender.send(WorkerMessage::Started { depth, target_depth }).is_err() {
+                    return;
+                }
+                let Some(word) = continuation.rewrite(cancelled) else {
+                    break;
+                };
+                if let Some(vertices) = vertices.take() {
+                    sent_depth = depth - 1;
+                    if sender.send(WorkerMessage::Iteration { depth: sent_depth, word, vertices }).is_err() {
+                        return;
+                    }
+                }
+                vertices = continuation.vertices(cancelled);
+            }
+
+            if let Some(vertices) = vertices {
+                sent_depth = continuation.depth();
+                let word = continuation.into_word();
+                if sender.send(WorkerMessage::Iteration { depth: sent_depth, word, vertices }).is_err() {
+                    return;
+                }
+            }
+            let cancelled = cancelled.load(Ordering::Relaxed);
+            if let Some(error) = limit_error {
+                if !cancelled && sent_depth + 1 == error.depth() {
+                    let _ = sender.send(WorkerMessage::LimitExceeded(error));
+                }
+            }
+            let _ = sender.send(WorkerMessage::Finished { depth: sent_depth, cancelled });
+        });
+
+        DerivationWorker {
+            receiver,
+            cancelled,
+            target_depth,
+            current_depth,
+            finished: false,
+        }
+    }
+
+    // Lets a running worker go on to a deeper target instead of starting
+    // a new one. The system is the one the worker was spawned for, with the
+    // iterations received so far, to check the limits. Raising the target
+    // of a finished worker has no effect.
+    pub fn raise_target(&self, system: &LindenmayerSystem<L>, target_depth: usize) -> Result<(), LimitError> {
+        system.check_limits(target_depth)?;
+        self.target_depth.fetch_max(target_depth, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl<L: Letter> DerivationWorker<L> {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn target_depth(&self) -> usize {
+        self.target_depth.load(Ordering::Relaxed)
+    }
+
+    // The depth being computed right now, or the last one finished.
+    pub fn current_depth(&self) -> usize {
+        self.current_depth
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Returns the messages sent since the last call without blocking.
+    pub fn poll(&mut self) -> Vec<WorkerMessage<L>> {
+        let mut messages = vec![];
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => {
+                    match &message {
+                        WorkerMessage::Started { depth, .. } => self.current_depth = *depth,
+                        // Arrives after the start of the next depth.
+                        WorkerMessage::Iteration { depth, .. } => self.current_depth = self.current_depth.max(*depth),
+                        WorkerMessage::LimitExceeded(_) => (),
+                        WorkerMessage::Finished { .. } => self.finished = true,
+                    }
+                    messages.push(message);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    break;
+                }
+            }
+        }
+        messages
+    }
+}
+
+// A worker nobody listens to anymore should stop as soon as possible.
+impl<L: Letter> Drop for DerivationWorker<L> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+
+    mod derivation_worker {
+        use crate::limits::{DerivationLimits, LimitError};
+        use crate::tryout::LindenmayerSystem;
+        use crate::worker::{DerivationWorker, WorkerMessage};
+
+        #[test]
+        fn send_every_iteration() {
+            let mut koch = LindenmayerSystem::koch();
+            let mut worker = DerivationWorker::spawn(&koch, 3);
+            let mut messages = vec![];
+            while !worker.is_finished() {
+                messages.append(&mut worker.poll());
+                std::thread::yield_now();
+            }
+
+            let mut finished = None;
+            for message in messages {
+                match message {
+                    WorkerMessage::Iteration { word, vertices, .. } => koch.append_iteration(word, vertices),
+                    WorkerMessage::Finished { depth, cancelled } => finished = Some((depth, cancelled)),
+                    WorkerMessage::Started { .. } | WorkerMessage::LimitExceeded(_) => (),
+                }
+            }
+            assert_eq!(finished, Some((3, false)));
+            assert_eq!(worker.current_depth(), 3);
+
+            let mut expected = LindenmayerSystem::koch();
+            expected.update_vertex_stack(3);
+            assert_eq!(koch.computed_depth(), 3);
+            assert_eq!(koch.get_vertex_stack(), expected.get_vertex_stack());
+        }
+
+        #[test]
+        fn raise_the_target() {
+            let mut levy = LindenmayerSystem::levy();
+            let mut worker = DerivationWorker::spawn(&levy, 12);
+            worker.raise_target(&levy, 14).unwrap();
+            worker.raise_target(&levy, 13).unwrap();
+            assert_eq!(worker.target_depth(), 14);
+            assert!(worker.raise_target(&levy, 100).is_err());
+
+            let mut finished = None;
+            while !worker.is_finished() {
+                for message in worker.poll() {
+                    match message {
+                        WorkerMessage::Iteration { word, vertices, .. } => levy.append_iteration(word, vertices),
+                        WorkerMessage::Finished { depth, cancelled } => finished = Some((depth, cancelled)),
+                        WorkerMessage::Started { .. } | WorkerMessage::LimitExceeded(_) => (),
+                    }
+                }
+                std::thread::yield_now();
+            }
+            assert_eq!(finished, Some((14, false)));
+            assert_eq!(levy.computed_depth(), 14);
+            assert_eq!(levy.get_word_stack_at(14).unwrap().len(), 5 * 2usize.pow(14) - 4);
+        }
+
+        #[test]
+        fn cancel() {
+            let mut worker = DerivationWorker::spawn(&LindenmayerSystem::dragon_curve(), 30);
+            // Deep enough for every depth to take a while, so the worker
+            // can't start another one between the last poll and the cancel.
+            while worker.current_depth() < 16 {
+                worker.poll();
+                std::thread::yield_now();
+            }
+            let depth = worker.current_depth();
+            worker.cancel();
+
+            let mut finished = None;
+            while !worker.is_finished() {
+                for message in worker.poll() {
+                    if let WorkerMessage::Finished { depth, cancelled } = message {
+                        finished = Some((depth, cancelled));
+                    }
+                }
+                std::thread::yield_now();
+            }
+            let (finished_depth, cancelled) = finished.unwrap();
+            assert!(cancelled);
+            assert!(finished_depth <= depth);
+            assert_eq!(worker.current_depth(), depth);
+        }
+
+        #[test]
+        fn stop_at_the_limits() {
+            let mut dragon = LindenmayerSystem::dragon_curve();
+            dragon.with_limits(DerivationLimits::unlimited().with_max_word_length(1000));
+            let mut worker = DerivationWorker::spawn(&dragon, 30);
+            let mut messages = vec![];
+            while !worker.is_finished() {
+                messages.append(&mut worker.poll());
+                std::thread::yield_now();
+            }
+            // Depth n of the dragon curve has 5 * 2^n - 4 letters.
+            assert!(messages.contains(&WorkerMessage::LimitExceeded(LimitError::WordLength { depth: 8, length: 1276, limit: 1000 })));
+            assert!(messages.contains(&WorkerMessage::Finished { depth: 7, cancelled: false }));
+        }
+    }
+}